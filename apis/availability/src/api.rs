@@ -3,27 +3,75 @@
 
 use crate::{
     data_source::AvailabilityDataSource,
-    query_data::{BlockQueryData, BlockSummaryQueryData, RecordQueryData, StateQueryData},
+    query_data::{
+        BlockQueryData, BlockSummaryQueryData, NamespaceQueryData, QuorumCertificateQueryData,
+        RangeQueryData, RecordFrontierQueryData, RecordQueryData, StateQueryData,
+    },
 };
 use ark_serialize::CanonicalSerialize;
 use clap::Args;
 use derive_more::From;
+use espresso_core::app_data::NamespaceId;
+use espresso_core::certificate::CertificateError;
 use espresso_core::state::{ElaboratedBlockCommitment, TransactionCommitment, ValidatorState};
 use futures::FutureExt;
 use hotshot_types::data::QuorumCertificate;
+use jf_cap::{
+    structs::{Nullifier, RecordCommitment},
+    MerkleLeafProof, MerkleTree,
+};
 use serde::{Deserialize, Serialize};
-use snafu::{OptionExt, Snafu};
-use std::{cmp::min, ops::Deref, path::PathBuf};
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::{cmp::min, iter, ops::Deref, path::PathBuf};
 use tide_disco::{
     api::{Api, ApiError},
     method::ReadState,
     RequestError, RequestParams, StatusCode,
 };
 
-#[derive(Args, Default)]
+/// The default maximum number of items returned by a single range query.
+pub const DEFAULT_MAX_RANGE: u64 = 100;
+
+/// The default maximum number of records replayed to build a single record proof.
+pub const DEFAULT_MAX_PROOF_RANGE: u64 = 10_000;
+
+#[derive(Args)]
 pub struct Options {
     #[arg(long = "availability-api-path", env = "ESPRESSO_AVAILABILITY_API_PATH")]
     pub api_path: Option<PathBuf>,
+
+    /// Maximum number of items to return from a single range query.
+    ///
+    /// Clients can request larger ranges, but they will be paginated into pages of at most this
+    /// size.
+    #[arg(
+        long = "availability-max-range",
+        env = "ESPRESSO_AVAILABILITY_MAX_RANGE",
+        default_value_t = DEFAULT_MAX_RANGE
+    )]
+    pub max_range: u64,
+
+    /// Maximum number of records which may separate a record from the state a proof of that
+    /// record is requested for.
+    ///
+    /// The server builds a proof by replaying every record created after the requested record up
+    /// to the requested state, so this bounds the work done for a single request.
+    #[arg(
+        long = "availability-max-proof-range",
+        env = "ESPRESSO_AVAILABILITY_MAX_PROOF_RANGE",
+        default_value_t = DEFAULT_MAX_PROOF_RANGE
+    )]
+    pub max_proof_range: u64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            api_path: None,
+            max_range: DEFAULT_MAX_RANGE,
+            max_proof_range: DEFAULT_MAX_PROOF_RANGE,
+        }
+    }
 }
 
 #[derive(Clone, Debug, From, Snafu, Deserialize, Serialize)]
@@ -44,12 +92,45 @@ pub enum Error {
         hash: TransactionCommitment,
     },
 
+    #[from(ignore)]
+    #[snafu(display("the requested nullifier {} has not been spent", nullifier))]
+    UnknownNullifier {
+        nullifier: Nullifier,
+    },
+
+    #[from(ignore)]
+    #[snafu(display("the requested record commitment {} does not exist", commitment))]
+    UnknownRecordCommitment {
+        commitment: RecordCommitment,
+    },
+
     #[from(ignore)]
     #[snafu(display("the requested record UID {} does not exist", uid))]
     UnknownRecordUid {
         uid: u64,
     },
 
+    #[from(ignore)]
+    #[snafu(display("record {} was not yet created as of block {}", uid, block_id))]
+    RecordNotInState {
+        uid: u64,
+        block_id: u64,
+    },
+
+    #[from(ignore)]
+    #[snafu(display(
+        "record {} is more than {} records older than block {}; request a proof relative to an \
+         earlier block",
+        uid,
+        max,
+        block_id
+    ))]
+    ProofRangeTooLarge {
+        uid: u64,
+        block_id: u64,
+        max: u64,
+    },
+
     #[from(ignore)]
     #[snafu(display("a block with the requested ID {} does not exist", block_id))]
     InvalidBlockId {
@@ -80,6 +161,13 @@ pub enum Error {
         output_index: u64,
     },
 
+    #[from(ignore)]
+    #[snafu(display("invalid range: start {} is greater than end {}", from, until))]
+    InvalidRange {
+        from: u64,
+        until: u64,
+    },
+
     #[from(ignore)]
     #[snafu(display("this server does not have block {}", block_id))]
     MissingBlock {
@@ -91,6 +179,23 @@ pub enum Error {
     MissingState {
         block_id: u64,
     },
+
+    #[from(ignore)]
+    #[snafu(display(
+        "the certificate for block {} is carried by the next block, which this server does not \
+         have yet",
+        block_id
+    ))]
+    MissingCertificate {
+        block_id: u64,
+    },
+
+    #[from(ignore)]
+    #[snafu(display("the certificate for block {} is malformed: {}", block_id, source))]
+    MalformedCertificate {
+        block_id: u64,
+        source: CertificateError,
+    },
 }
 
 impl Error {
@@ -99,12 +204,19 @@ impl Error {
             Self::Request { .. } => StatusCode::BadRequest,
             Self::UnknownBlockHash { .. } => StatusCode::BadRequest,
             Self::UnknownTransactionHash { .. } => StatusCode::BadRequest,
+            Self::UnknownNullifier { .. } => StatusCode::NotFound,
+            Self::UnknownRecordCommitment { .. } => StatusCode::BadRequest,
             Self::UnknownRecordUid { .. } => StatusCode::BadRequest,
+            Self::RecordNotInState { .. } => StatusCode::BadRequest,
+            Self::ProofRangeTooLarge { .. } => StatusCode::BadRequest,
             Self::InvalidBlockId { .. } => StatusCode::BadRequest,
             Self::InvalidTransactionId { .. } => StatusCode::BadRequest,
             Self::InvalidRecordId { .. } => StatusCode::BadRequest,
+            Self::InvalidRange { .. } => StatusCode::BadRequest,
             Self::MissingBlock { .. } => StatusCode::NotFound,
             Self::MissingState { .. } => StatusCode::NotFound,
+            Self::MissingCertificate { .. } => StatusCode::NotFound,
+            Self::MalformedCertificate { .. } => StatusCode::InternalServerError,
         }
     }
 }
@@ -170,6 +282,54 @@ where
         .context(MissingStateSnafu { block_id })
 }
 
+/// Get the most recent state at or before block `block_id`, skipping missing states.
+fn get_state_at_or_before<State>(state: State, block_id: u64) -> Option<StateQueryData>
+where
+    State: AvailabilityDataSource,
+{
+    (0..=block_id)
+        .rev()
+        .find_map(|id| state.get_nth_state_iter(id as usize).next().flatten())
+}
+
+/// Get the most recent state whose events all come before `event_index`.
+///
+/// Returns [None] if even the genesis block has events at or after `event_index`.
+fn get_state_before_event<State: Copy>(state: State, event_index: u64) -> Option<StateQueryData>
+where
+    State: AvailabilityDataSource,
+{
+    // Find a block ID past the end of the chain by repeated doubling, and then binary search for
+    // the last block whose continuation event index is not after `event_index`. The continuation
+    // event index only increases from one block to the next, so the search is well defined even
+    // though we skip over missing states.
+    let exists = |id: u64| state.get_nth_state_iter(id as usize).next().is_some();
+    let mut end = 1;
+    while exists(end - 1) {
+        end *= 2;
+    }
+    let precedes = |id: u64| {
+        get_state_at_or_before(state, id)
+            .map_or(true, |s| s.continuation_event_index <= event_index)
+    };
+    // Invariant: every block before `lo` precedes `event_index`, and no block at or after `hi`
+    // exists or precedes it.
+    let (mut lo, mut hi) = (0, end);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if exists(mid) && precedes(mid) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    if lo == 0 {
+        None
+    } else {
+        get_state_at_or_before(state, lo - 1)
+    }
+}
+
 fn get_qcert<State>(state: State, block_id: u64) -> Result<QuorumCertificate<ValidatorState>, Error>
 where
     State: AvailabilityDataSource,
@@ -181,6 +341,28 @@ where
         .context(MissingStateSnafu { block_id })
 }
 
+/// The quorum certificate which decided block `block_id`.
+///
+/// Each leaf carries the certificate for its parent, so the certificate for a block is stored with
+/// the next block, and is not available until that block has been decided. This is the certificate
+/// every route means by the certificate, or the view number, of a block.
+fn get_block_certificate<State: Clone>(
+    state: State,
+    block_id: u64,
+) -> Result<QuorumCertificate<ValidatorState>, Error>
+where
+    State: AvailabilityDataSource,
+{
+    // The block itself must exist.
+    get_qcert(state.clone(), block_id)?;
+    match get_qcert(state, block_id + 1) {
+        Err(Error::InvalidBlockId { .. } | Error::MissingState { .. }) => {
+            Err(Error::MissingCertificate { block_id })
+        }
+        res => res,
+    }
+}
+
 fn get_block_summary<State: Clone>(
     state: State,
     block_id: u64,
@@ -193,30 +375,173 @@ where
     let count = min(count, block_id + 1);
     for id in (block_id + 1 - count..block_id + 1).rev() {
         let block_data = get_block(state.clone(), id)?;
-        let qcert_data = get_qcert(state.clone(), id)?;
-        let size = block_data.raw_block.serialized_size();
-        let txn_count = block_data.txn_hashes.len();
-        let records_from = block_data.records_from;
-        let record_count = block_data.record_count;
-        let view_number = *qcert_data.view_number.deref();
-        let timestamp = block_data.timestamp;
-        let proposer_id = block_data.proposer_id.0;
-        let block_hash = block_data.block_hash;
-        summaries.push(BlockSummaryQueryData {
-            size,
-            txn_count,
-            records_from,
-            record_count,
-            view_number,
-            timestamp,
-            proposer_id,
-            block_id: id,
-            block_hash,
-        });
+        let qcert_data = match get_block_certificate(state.clone(), id) {
+            Ok(qcert) => Some(qcert),
+            Err(Error::MissingCertificate { .. }) => None,
+            Err(err) => return Err(err),
+        };
+        summaries.push(block_summary(block_data, qcert_data.as_ref()));
     }
     Ok(summaries)
 }
 
+/// Summarize a block, given the certificate which decided it, if it is available yet.
+fn block_summary(
+    block_data: BlockQueryData,
+    qcert_data: Option<&QuorumCertificate<ValidatorState>>,
+) -> BlockSummaryQueryData {
+    BlockSummaryQueryData {
+        size: block_data.raw_block.serialized_size(),
+        txn_count: block_data.txn_hashes.len(),
+        records_from: block_data.records_from,
+        record_count: block_data.record_count,
+        view_number: qcert_data.map(|qcert| *qcert.view_number.deref()),
+        timestamp: block_data.timestamp,
+        proposer_id: block_data.proposer_id.0,
+        block_id: block_data.block_id,
+        block_hash: block_data.block_hash,
+    }
+}
+
+/// Compute the end of the page of a range query starting at `from`.
+///
+/// The page ends at `until` (exclusive) or after the maximum page size, whichever comes first. The
+/// page size is the smaller of the server-enforced `max_range` and the client's requested `:limit`,
+/// if there is one.
+fn page_end(req: &RequestParams, from: u64, until: u64, max_range: u64) -> Result<u64, Error> {
+    if from > until {
+        return Err(Error::InvalidRange { from, until });
+    }
+    let limit: Option<u64> = req.opt_integer_param("limit")?;
+    let limit = match limit {
+        Some(limit) => min(limit, max_range),
+        None => max_range,
+    };
+    Ok(min(until, from.saturating_add(limit)))
+}
+
+/// Package the results of a range query `[from, until)` into a page.
+///
+/// `end` is the end of the page which was requested from the data source. If we got fewer than
+/// `end - from` items, we have run out of data, and there is no next page (yet).
+fn page<T>(items: Vec<T>, from: u64, end: u64, until: u64) -> RangeQueryData<T> {
+    let next = if from + (items.len() as u64) == end && end < until {
+        Some(end)
+    } else {
+        None
+    };
+    RangeQueryData { items, next }
+}
+
+fn get_block_range<State>(state: State, from: u64, end: u64) -> Result<Vec<BlockQueryData>, Error>
+where
+    State: AvailabilityDataSource,
+{
+    (from..end)
+        .zip(state.get_nth_block_iter(from as usize))
+        .map(|(block_id, block)| block.context(MissingBlockSnafu { block_id }))
+        .collect()
+}
+
+/// Summarize the blocks in the range `[from, end)`.
+fn get_block_summary_range<State: Clone>(
+    state: State,
+    from: u64,
+    end: u64,
+) -> Result<Vec<BlockSummaryQueryData>, Error>
+where
+    State: AvailabilityDataSource,
+{
+    let blocks = get_block_range(state.clone(), from, end)?;
+    // The certificate which decided each block is carried by the next one, so the last block may
+    // not have one yet.
+    let qcerts = state
+        .get_nth_qcert_iter(from as usize + 1)
+        .chain(iter::repeat(None));
+    Ok(blocks
+        .into_iter()
+        .zip(qcerts)
+        .map(|(block, qcert)| block_summary(block, qcert.as_ref()))
+        .collect())
+}
+
+fn get_record_range<State>(state: State, from: u64, end: u64) -> Result<Vec<RecordQueryData>, Error>
+where
+    State: AvailabilityDataSource,
+{
+    let mut records = Vec::new();
+    if from >= end {
+        return Ok(records);
+    }
+    // Find the block containing the first record in the range. If there is no such record, the
+    // range starts after the end of the chain, and the result is empty.
+    let first_block = match state.get_record_index_by_uid(from) {
+        Some((block_id, _, _)) => block_id,
+        None => return Ok(records),
+    };
+    // Walk forward from that block, collecting records until we reach the end of the range.
+    for (block_id, block) in (first_block..).zip(state.get_nth_block_iter(first_block as usize)) {
+        let block = block.context(MissingBlockSnafu { block_id })?;
+        for record in block.records().skip_while(|record| record.uid < from) {
+            if record.uid >= end {
+                return Ok(records);
+            }
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+/// Get a Merkle proof for the record `uid` relative to the record Merkle root after `block_id`.
+///
+/// Rather than rebuilding the entire record Merkle tree, we start from the frontier of the state
+/// just before the block which created `uid`, and then append all of the records created between
+/// that state and the end of `block_id`. This leaves the path to every appended record in memory.
+fn get_record_proof<State: Clone>(
+    state: State,
+    uid: u64,
+    block_id: u64,
+    max_proof_range: u64,
+) -> Result<MerkleLeafProof, Error>
+where
+    State: AvailabilityDataSource,
+{
+    let root = get_state(state.clone(), block_id)?
+        .state
+        .record_merkle_commitment;
+    if uid >= root.num_leaves {
+        return Err(Error::RecordNotInState { uid, block_id });
+    }
+    if root.num_leaves - uid > max_proof_range {
+        return Err(Error::ProofRangeTooLarge {
+            uid,
+            block_id,
+            max: max_proof_range,
+        });
+    }
+    let (first_block, _, _) = state
+        .get_record_index_by_uid(uid)
+        .context(UnknownRecordUidSnafu { uid })?;
+    let mut tree = if first_block == 0 {
+        MerkleTree::new(root.height).unwrap()
+    } else {
+        state
+            .get_record_merkle_tree_at_block_index(first_block as usize - 1)
+            .context(MissingStateSnafu {
+                block_id: first_block - 1,
+            })?
+    };
+    for record in get_record_range(state, tree.num_leaves(), root.num_leaves)? {
+        tree.push(record.commitment.to_field_element());
+    }
+    let (_, proof) = tree
+        .get_leaf(uid)
+        .expect_ok()
+        .ok()
+        .context(UnknownRecordUidSnafu { uid })?;
+    Ok(proof)
+}
+
 pub fn define_api<State>(options: &Options) -> Result<Api<State, Error>, ApiError>
 where
     State: 'static + Send + Sync + ReadState,
@@ -233,6 +558,8 @@ where
             Api::<State, Error>::new(toml)?
         }
     };
+    let max_range = options.max_range;
+    let max_proof_range = options.max_proof_range;
     api.with_version(env!("CARGO_PKG_VERSION").parse().unwrap())
         .get("getblock", |req, state| {
             async move {
@@ -241,6 +568,14 @@ where
             }
             .boxed()
         })?
+        .get("getrecordproof", move |req, state| {
+            async move {
+                let uid = req.integer_param("uid")?;
+                let block_id = req.integer_param("block_id")?;
+                get_record_proof(state, uid, block_id, max_proof_range)
+            }
+            .boxed()
+        })?
         .get("getstate", |req, state| {
             async move {
                 let id = block_index(&req, state)?;
@@ -261,6 +596,10 @@ where
                     state
                         .get_txn_index_by_hash(hash)
                         .context(UnknownTransactionHashSnafu { hash })?
+                } else if let Some(nullifier) = req.opt_blob_param("nullifier")? {
+                    state
+                        .get_txn_index_by_nullifier(nullifier)
+                        .context(UnknownNullifierSnafu { nullifier })?
                 } else {
                     (req.integer_param("block_id")?, req.integer_param("txn_id")?)
                 };
@@ -273,18 +612,26 @@ where
         })?
         .get("getrecord", |req, state| {
             async move {
-                let (block_id, txn_id, output_index) =
-                    if let Some(uid) = req.opt_integer_param("uid")? {
+                let uid = if let Some(commitment) = req.opt_blob_param("commitment")? {
+                    Some(
                         state
-                            .get_record_index_by_uid(uid)
-                            .context(UnknownRecordUidSnafu { uid })?
-                    } else {
-                        (
-                            req.integer_param("block_id")?,
-                            req.integer_param("txn_id")?,
-                            req.integer_param("output_index")?,
-                        )
-                    };
+                            .get_record_uid_by_commitment(commitment)
+                            .context(UnknownRecordCommitmentSnafu { commitment })?,
+                    )
+                } else {
+                    req.opt_integer_param("uid")?
+                };
+                let (block_id, txn_id, output_index) = if let Some(uid) = uid {
+                    state
+                        .get_record_index_by_uid(uid)
+                        .context(UnknownRecordUidSnafu { uid })?
+                } else {
+                    (
+                        req.integer_param("block_id")?,
+                        req.integer_param("txn_id")?,
+                        req.integer_param("output_index")?,
+                    )
+                };
                 let block = get_block(state, block_id)?;
                 let commitment = *block
                     .raw_block
@@ -313,11 +660,32 @@ where
         .get("getviewnumber", |req, state| {
             async move {
                 let block_id = req.integer_param("block_id")?;
-                let view_number = get_qcert(state, block_id)?.view_number;
+                let view_number = get_block_certificate(state, block_id)?.view_number;
                 Ok(view_number)
             }
             .boxed()
         })?
+        .get("getfrontier", |req, state| {
+            async move {
+                if let Some(event_index) = req.opt_integer_param("event_index")? {
+                    Ok(get_state_before_event(state, event_index)
+                        .map(RecordFrontierQueryData::from))
+                } else {
+                    let id = block_index(&req, state)?;
+                    Ok(Some(get_state(state, id)?.into()))
+                }
+            }
+            .boxed()
+        })?
+        .get("getqc", |req, state| {
+            async move {
+                let block_id = block_index(&req, state)?;
+                let qc = get_block_certificate(state, block_id)?;
+                QuorumCertificateQueryData::new(block_id, qc)
+                    .context(MalformedCertificateSnafu { block_id })
+            }
+            .boxed()
+        })?
         .get("getblocksummary", |req, state| {
             async move {
                 let block_id = req.integer_param("block_id")?;
@@ -326,6 +694,50 @@ where
             }
             .boxed()
         })?
+        .get("getblocks", move |req, state| {
+            async move {
+                let from = req.integer_param("from")?;
+                let until = req.integer_param("until")?;
+                let end = page_end(&req, from, until, max_range)?;
+                let blocks = get_block_range(state, from, end)?;
+                Ok(page(blocks, from, end, until))
+            }
+            .boxed()
+        })?
+        .get("getblocksummaries", move |req, state| {
+            async move {
+                let from = req.integer_param("from")?;
+                let until = req.integer_param("until")?;
+                let end = page_end(&req, from, until, max_range)?;
+                let summaries = get_block_summary_range(state, from, end)?;
+                Ok(page(summaries, from, end, until))
+            }
+            .boxed()
+        })?
+        .get("getnamespace", move |req, state| {
+            async move {
+                let namespace = NamespaceId(req.integer_param("namespace")?);
+                let from = req.integer_param("from")?;
+                let until = req.integer_param("until")?;
+                let end = page_end(&req, from, until, max_range)?;
+                let items: Vec<NamespaceQueryData> = get_block_range(state, from, end)?
+                    .into_iter()
+                    .map(|block| block.namespace(namespace))
+                    .collect();
+                Ok(page(items, from, end, until))
+            }
+            .boxed()
+        })?
+        .get("getrecords", move |req, state| {
+            async move {
+                let from = req.integer_param("from")?;
+                let until = req.integer_param("until")?;
+                let end = page_end(&req, from, until, max_range)?;
+                let records = get_record_range(state, from, end)?;
+                Ok(page(records, from, end, until))
+            }
+            .boxed()
+        })?
         .get("getproposals", |req, state| {
             async move {
                 let proposer_id = req.blob_param("proposer")?;
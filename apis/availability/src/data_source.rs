@@ -4,7 +4,10 @@
 use crate::query_data::{BlockQueryData, EncodedPublicKey, StateQueryData};
 use espresso_core::state::{ElaboratedBlockCommitment, TransactionCommitment, ValidatorState};
 use hotshot_types::data::QuorumCertificate;
-use jf_cap::MerkleTree;
+use jf_cap::{
+    structs::{Nullifier, RecordCommitment},
+    MerkleTree,
+};
 use std::error::Error;
 use std::fmt::Debug;
 
@@ -24,6 +27,10 @@ pub trait AvailabilityDataSource {
     fn get_nth_qcert_iter(&self, n: usize) -> Self::QCertIterType;
     fn get_block_index_by_hash(&self, hash: ElaboratedBlockCommitment) -> Option<u64>;
     fn get_txn_index_by_hash(&self, hash: TransactionCommitment) -> Option<(u64, u64)>;
+    /// Get the block and transaction index of the transaction which spent `nullifier`.
+    fn get_txn_index_by_nullifier(&self, nullifier: Nullifier) -> Option<(u64, u64)>;
+    /// Get the UID of the record with the given commitment.
+    fn get_record_uid_by_commitment(&self, commitment: RecordCommitment) -> Option<u64>;
     fn get_record_index_by_uid(&self, uid: u64) -> Option<(u64, u64, u64)>; // None if OOB
                                                                             // it should be possible to implement this one in terms of the above,
                                                                             // leaving more compact and/or performant solutions as optional
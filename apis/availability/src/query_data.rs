@@ -2,11 +2,16 @@
 // This file is part of the Espresso library.
 
 use ark_serialize::*;
+use commit::{Commitment, Committable};
+use espresso_core::app_data::{NamespaceId, NamespaceProof};
+use espresso_core::certificate::{CertificateError, QuorumCertificateData};
 use espresso_core::state::{
-    state_comm::LedgerStateCommitment, ElaboratedBlock, ElaboratedBlockCommitment,
+    state_comm::LedgerStateCommitment, Block, ElaboratedBlock, ElaboratedBlockCommitment,
     ElaboratedTransaction, TransactionCommitment, ValidatorState,
 };
+use hotshot_types::data::QuorumCertificate;
 use jf_cap::structs::RecordCommitment;
+use jf_cap::{MerkleCommitment, MerkleFrontier};
 use jf_utils::tagged_blob;
 use serde::{Deserialize, Serialize};
 
@@ -50,6 +55,43 @@ impl BlockQueryData {
             transaction_hash: self.txn_hashes[i],
         })
     }
+
+    /// The transactions in this block from `namespace`, with a proof that there are no others.
+    pub fn namespace(&self, namespace: NamespaceId) -> NamespaceQueryData {
+        let (indices, proof) = self.raw_block.block.namespace_proof(namespace);
+        NamespaceQueryData {
+            block_id: self.block_id,
+            block_commitment: self.raw_block.block.commit(),
+            transactions: indices
+                .into_iter()
+                .filter_map(|i| self.transaction(i as usize))
+                .collect(),
+            proof,
+        }
+    }
+
+    /// The records created by this block, in order of UID.
+    pub fn records(&self) -> impl '_ + Iterator<Item = RecordQueryData> {
+        let mut uid = self.records_from;
+        self.raw_block
+            .block
+            .0
+            .iter()
+            .enumerate()
+            .flat_map(move |(txn_id, txn)| {
+                let records = txn.output_commitments().into_iter().enumerate().map(
+                    move |(output_index, commitment)| RecordQueryData {
+                        commitment,
+                        uid: uid + output_index as u64,
+                        block_id: self.block_id,
+                        txn_id: txn_id as u64,
+                        output_index: output_index as u64,
+                    },
+                );
+                uid += txn.output_len() as u64;
+                records
+            })
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -60,6 +102,19 @@ pub struct TransactionQueryData {
     pub transaction_hash: TransactionCommitment,
 }
 
+/// The transactions from one namespace in a block.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct NamespaceQueryData {
+    pub block_id: u64,
+    /// Commitment to the contents of the block.
+    ///
+    /// This is the `prev_block` commitment in the state after this block.
+    pub block_commitment: Commitment<Block>,
+    pub transactions: Vec<TransactionQueryData>,
+    /// Proof that `transactions` are all of the transactions from this namespace in the block.
+    pub proof: NamespaceProof,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct RecordQueryData {
     pub commitment: RecordCommitment,
@@ -78,6 +133,59 @@ pub struct StateQueryData {
     pub continuation_event_index: u64,
 }
 
+/// The record Merkle tree frontier after a block, and the index of the first event after it.
+///
+/// A client can restore a sparse record Merkle tree from the frontier and then replay events
+/// starting from `continuation_event_index`, without replaying the events of earlier blocks.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RecordFrontierQueryData {
+    pub block_id: u64,
+    pub record_merkle_commitment: MerkleCommitment,
+    pub record_merkle_frontier: MerkleFrontier,
+    pub continuation_event_index: u64,
+}
+
+impl From<StateQueryData> for RecordFrontierQueryData {
+    fn from(state: StateQueryData) -> Self {
+        Self {
+            block_id: state.block_id,
+            record_merkle_commitment: state.state.record_merkle_commitment,
+            record_merkle_frontier: state.state.record_merkle_frontier,
+            continuation_event_index: state.continuation_event_index,
+        }
+    }
+}
+
+/// The quorum certificate which decided block `block_id`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct QuorumCertificateQueryData {
+    pub block_id: u64,
+    #[serde(flatten)]
+    pub certificate: QuorumCertificateData,
+}
+
+impl QuorumCertificateQueryData {
+    pub fn new(
+        block_id: u64,
+        qc: QuorumCertificate<ValidatorState>,
+    ) -> Result<Self, CertificateError> {
+        Ok(Self {
+            block_id,
+            certificate: qc.try_into()?,
+        })
+    }
+}
+
+/// One page of the results of a range query.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RangeQueryData<T> {
+    pub items: Vec<T>,
+    /// The start of the next page, if there are more results in the requested range.
+    ///
+    /// If this is `None`, the query has returned all of the results which are currently available.
+    pub next: Option<u64>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockSummaryQueryData {
     pub size: usize,
@@ -86,7 +194,10 @@ pub struct BlockSummaryQueryData {
     pub records_from: u64,
     /// The total number of outputs in this block.
     pub record_count: u64,
-    pub view_number: u64,
+    /// The view number of the certificate which decided this block.
+    ///
+    /// This is `None` until the certificate is available, which is when the next block is decided.
+    pub view_number: Option<u64>,
     pub timestamp: i128,
     pub proposer_id: Vec<u8>,
     pub block_hash: ElaboratedBlockCommitment,
@@ -4,6 +4,7 @@
 use crate::data_source::CatchUpDataSource;
 use clap::Args;
 use derive_more::From;
+use espresso_core::metrics::{Metrics, SubscriberGuard};
 use futures::{stream::iter, FutureExt, StreamExt, TryFutureExt};
 use serde::{Deserialize, Serialize};
 use snafu::Snafu;
@@ -33,7 +34,8 @@ impl Error {
     }
 }
 
-pub fn define_api<State>(options: &Options) -> Result<Api<State, Error>, ApiError>
+/// Define the catchup API, counting event subscribers in `metrics`.
+pub fn define_api<State>(options: &Options, metrics: Metrics) -> Result<Api<State, Error>, ApiError>
 where
     State: 'static + Send + Sync + ReadState,
     for<'a> &'a <State as ReadState>::State: Send + Sync + CatchUpDataSource,
@@ -65,7 +67,8 @@ where
             }
             .boxed()
         })?
-        .stream("subscribe_for_events", |req, state| {
+        .stream("subscribe_for_events", move |req, state| {
+            let metrics = metrics.clone();
             async move {
                 let mut first = req.integer_param("first")?;
                 let (prefix, receiver) = state
@@ -84,13 +87,18 @@ where
                 // We will yield all the events we already have buffered, then subscribe to future
                 // events starting from there.
                 first += prefix.len();
+                // Count this subscriber until the stream is dropped.
+                let guard = SubscriberGuard::new(&metrics);
                 Ok(iter(prefix)
                     .map(Ok)
-                    .chain(receiver.filter_map(move |(i, e)| async move {
-                        if i >= first {
-                            Some(Ok(e))
-                        } else {
-                            None
+                    .chain(receiver.filter_map(move |(i, e)| {
+                        let _ = &guard;
+                        async move {
+                            if i >= first {
+                                Some(Ok(e))
+                            } else {
+                                None
+                            }
                         }
                     })))
             }
@@ -19,16 +19,18 @@ use crate::{
 };
 use async_std::{
     sync::{Arc, RwLock},
-    task::{spawn, JoinHandle},
+    task::{block_on, spawn, JoinHandle},
 };
 use clap::{Args, Subcommand};
 use espresso_availability_api::api as availability;
 use espresso_catchup_api::api as catchup;
+use espresso_core::proposals::ProposalTracker;
 use espresso_metastate_api::api as metastate;
 use espresso_status_api::api as status;
 use espresso_validator_api::{api as validator, data_source::ValidatorDataSource};
 use std::fmt::Display;
 use std::io;
+use std::sync::Mutex;
 use tide_disco::{http::Url, App};
 
 #[derive(Args)]
@@ -97,10 +99,12 @@ impl EsQS {
         command: &Command,
         data_source: Arc<RwLock<QueryData>>,
         consensus: impl ValidatorDataSource + Send + Sync + 'static,
+        proposals: Arc<Mutex<ProposalTracker>>,
     ) -> io::Result<Self> {
         let Command::Esqs(opt) = command;
+        let metrics = block_on(data_source.read()).metrics().clone();
         let availability_api = availability::define_api(&opt.availability).map_err(io_error)?;
-        let catchup_api = catchup::define_api(&opt.catchup).map_err(io_error)?;
+        let catchup_api = catchup::define_api(&opt.catchup, metrics.clone()).map_err(io_error)?;
         let metastate_api = metastate::define_api(&opt.metastate).map_err(io_error)?;
         let status_api = status::define_api(&opt.status).map_err(io_error)?;
         let validator_api = validator::define_api(&opt.validator).map_err(io_error)?;
@@ -135,6 +139,8 @@ impl EsQS {
             data_source.clone(),
             data_source.clone(),
             data_source,
+            proposals,
+            metrics,
         );
         Ok(Self {
             port,
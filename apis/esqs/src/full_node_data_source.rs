@@ -1,16 +1,19 @@
 // Copyright (c) 2022 Espresso Systems (espressosys.com)
 // This file is part of the Espresso library.
 
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::convert::From;
 use std::path::Path;
+use std::time::Instant;
 
 use crate::ApiError;
+use ark_serialize::CanonicalSerialize;
 use async_trait::async_trait;
 use atomic_store::{
     append_log::Iter as ALIter, load_store::BincodeLoadStore, AppendLog, AtomicStore,
     AtomicStoreLoader, PersistenceError, RollingLog,
 };
+use commit::Committable;
 use espresso_availability_api::data_source::{
     AvailabilityDataSource, BlockAndAssociated, UpdateAvailabilityData,
 };
@@ -18,9 +21,10 @@ use espresso_availability_api::query_data::EncodedPublicKey;
 use espresso_availability_api::query_data::{BlockQueryData, StateQueryData};
 use espresso_catchup_api::data_source::{CatchUpDataSource, UpdateCatchUpData};
 use espresso_core::ledger::EspressoLedger;
+use espresso_core::metrics::Metrics;
 use espresso_core::state::{
-    ElaboratedBlockCommitment, ElaboratedTransaction, SetMerkleProof, SetMerkleTree,
-    TransactionCommitment, ValidatorState,
+    ElaboratedBlockCommitment, ElaboratedTransaction, EspressoTransaction, SetMerkleProof,
+    SetMerkleTree, TransactionCommitment, ValidationError, ValidatorState,
 };
 use espresso_metastate_api::{
     api as metastate,
@@ -28,10 +32,13 @@ use espresso_metastate_api::{
 };
 use espresso_status_api::data_source::{StatusDataSource, UpdateStatusData};
 use espresso_status_api::query_data::ValidatorStatus;
-use espresso_validator_api::data_source::{ConsensusEvent, ValidatorDataSource};
+use espresso_validator_api::data_source::{
+    ConsensusEvent, MempoolError, SubmitError, ValidatorDataSource,
+};
+use espresso_validator_api::query_data::TransactionStatus;
 use hotshot::{data::QuorumCertificate, HotShotError};
 use itertools::izip;
-use jf_cap::structs::Nullifier;
+use jf_cap::structs::{Nullifier, RecordCommitment};
 use jf_cap::MerkleTree;
 use postage::{broadcast, sink::Sink};
 use seahorse::events::LedgerEvent;
@@ -41,14 +48,18 @@ use tracing::warn;
 const CACHED_BLOCKS_COUNT: usize = 50;
 const CACHED_EVENTS_COUNT: usize = 500;
 const EVENT_CHANNEL_CAPACITY: usize = 500;
+/// Number of rejected or evicted transactions whose status is remembered.
+const DROPPED_TRANSACTIONS_COUNT: usize = 10_000;
 
-pub type Consensus = Box<dyn ValidatorDataSource<Error = HotShotError> + Send + Sync>;
+pub type Consensus = Box<dyn ValidatorDataSource<Error = SubmitError<HotShotError>> + Send + Sync>;
 
 pub struct QueryData {
     cached_blocks_start: usize,
     cached_blocks: Vec<BlockAndAssociated>,
     index_by_block_hash: HashMap<ElaboratedBlockCommitment, u64>,
     index_by_txn_hash: HashMap<TransactionCommitment, (u64, u64)>,
+    index_by_nullifier: HashMap<Nullifier, (u64, u64)>,
+    index_by_record_commitment: HashMap<RecordCommitment, u64>,
     index_by_last_record_id: BTreeMap<u64, u64>,
     index_by_proposer_id: HashMap<EncodedPublicKey, Vec<u64>>,
     cached_events_start: usize,
@@ -57,6 +68,12 @@ pub struct QueryData {
     event_receiver: broadcast::Receiver<(usize, Option<LedgerEvent<EspressoLedger>>)>,
     cached_nullifier_sets: BTreeMap<u64, SetMerkleTree>,
     node_status: ValidatorStatus,
+    /// Submitted transactions, kept so they can be rechecked as the ledger moves on without them.
+    pending_transactions: HashMap<TransactionCommitment, ElaboratedTransaction>,
+    rejected_transactions: HashMap<TransactionCommitment, ValidationError>,
+    evicted_transactions: HashSet<TransactionCommitment>,
+    /// Rejected and evicted transactions, oldest first, for pruning.
+    dropped_transactions: VecDeque<TransactionCommitment>,
     query_storage: AtomicStore,
     block_storage: AppendLog<BincodeLoadStore<Option<BlockQueryData>>>,
     state_storage: AppendLog<BincodeLoadStore<Option<StateQueryData>>>,
@@ -65,6 +82,7 @@ pub struct QueryData {
     status_storage: RollingLog<BincodeLoadStore<ValidatorStatus>>,
     consensus: Consensus,
     location: Option<String>,
+    metrics: Metrics,
 }
 
 pub trait Extract<T> {
@@ -232,6 +250,12 @@ impl<'a> AvailabilityDataSource for &'a QueryData {
     fn get_txn_index_by_hash(&self, hash: TransactionCommitment) -> Option<(u64, u64)> {
         self.index_by_txn_hash.get(&hash).cloned()
     }
+    fn get_txn_index_by_nullifier(&self, nullifier: Nullifier) -> Option<(u64, u64)> {
+        self.index_by_nullifier.get(&nullifier).cloned()
+    }
+    fn get_record_uid_by_commitment(&self, commitment: RecordCommitment) -> Option<u64> {
+        self.index_by_record_commitment.get(&commitment).cloned()
+    }
     fn get_record_index_by_uid(&self, uid: u64) -> Option<(u64, u64, u64)> {
         let apply = |block: &BlockQueryData| {
             let mut remainder = uid - block.records_from;
@@ -347,6 +371,16 @@ impl UpdateAvailabilityData for QueryData {
                     self.index_by_txn_hash
                         .insert(*txn_hash, (block.block_id, index as u64));
                 }
+                for (index, txn) in block.raw_block.block.0.iter().enumerate() {
+                    for nullifier in txn.input_nullifiers() {
+                        self.index_by_nullifier
+                            .insert(nullifier, (block.block_id, index as u64));
+                    }
+                }
+                for record in block.records() {
+                    self.index_by_record_commitment
+                        .insert(record.commitment, record.uid);
+                }
             }
             if let Err(e) = self.block_storage.store_resource(opt_block) {
                 warn!("Failed to store block {:?}: Error: {}", opt_block, e);
@@ -369,6 +403,9 @@ impl UpdateAvailabilityData for QueryData {
             self.cached_blocks_start += prune_by;
             self.cached_blocks.drain(..prune_by);
         }
+        // Check pending transactions against the new blocks only once they are appended, so that
+        // the block height and latest state include them.
+        self.reject_dead_transactions();
         Ok(())
     }
 }
@@ -521,6 +558,23 @@ impl MetaStateDataSource for QueryData {
             None
         }
     }
+
+    fn get_nullifier_multi_proof_for(
+        &self,
+        block_id: u64,
+        nullifiers: &[Nullifier],
+    ) -> Option<SetMerkleTree> {
+        self.with_nullifier_set_at_block(block_id, |ns| {
+            let mut proof = SetMerkleTree::sparse(ns.hash());
+            for nullifier in nullifiers {
+                let (_, path) = ns.contains(*nullifier)?;
+                proof.remember(*nullifier, path).ok()?;
+            }
+            Some(proof)
+        })
+        .ok()
+        .flatten()
+    }
 }
 
 impl UpdateMetaStateData for QueryData {
@@ -572,6 +626,9 @@ impl UpdateStatusData for QueryData {
         Self::Error: From<U>,
     {
         op(&mut self.node_status).map_err(ApiError::from)?;
+        self.metrics
+            .mempool_transactions
+            .set(self.node_status.mempool_info.transaction_count);
         if let Err(e) = self.status_storage.store_resource(&self.node_status) {
             warn!(
                 "Failed to store status {:?}, Error {}",
@@ -584,10 +641,73 @@ impl UpdateStatusData for QueryData {
 
 #[async_trait]
 impl ValidatorDataSource for QueryData {
-    type Error = HotShotError;
+    type Error = SubmitError<HotShotError>;
+
+    async fn submit(
+        &mut self,
+        txn: ElaboratedTransaction,
+    ) -> Result<TransactionCommitment, Self::Error> {
+        let hash = TransactionCommitment(txn.txn.commit());
+        let output_count = txn.txn.output_len() as u64;
+        let size = txn.txn.serialized_size() as u64;
+        self.consensus.submit(txn.clone()).await?;
+        self.pending_transactions.insert(hash, txn);
+        self.evicted_transactions.remove(&hash);
+        // Admitting this transaction may have evicted others to make room for it.
+        let evicted = self.consensus.take_evicted();
+        for hash in &evicted {
+            self.pending_transactions.remove(hash);
+            self.record_dropped(*hash, None);
+        }
+        if let Err(err) = self.edit_status(|vs| {
+            vs.add_to_mempool(hash, output_count, size);
+            for hash in &evicted {
+                vs.remove_from_mempool(hash);
+            }
+            Ok::<(), ApiError>(())
+        }) {
+            warn!(
+                "failed to add transaction {} to mempool status: {}",
+                hash, err
+            );
+        }
+        Ok(hash)
+    }
+
+    fn get_transaction_status(&self, hash: TransactionCommitment) -> Option<TransactionStatus> {
+        if let Some((block_id, txn_id)) = self.index_by_txn_hash.get(&hash) {
+            Some(TransactionStatus::Committed {
+                block_id: *block_id,
+                txn_id: *txn_id,
+            })
+        } else if let Some(error) = self.rejected_transactions.get(&hash) {
+            Some(TransactionStatus::Rejected {
+                error: error.clone(),
+            })
+        } else if self.pending_transactions.contains_key(&hash) {
+            Some(TransactionStatus::Pending)
+        } else if self.evicted_transactions.contains(&hash) {
+            Some(TransactionStatus::Evicted)
+        } else {
+            None
+        }
+    }
+
+    fn check_admission(&self, txn: &ElaboratedTransaction) -> Result<(), MempoolError> {
+        self.consensus.check_admission(txn)
+    }
+
+    fn take_evicted(&mut self) -> Vec<TransactionCommitment> {
+        // Evictions are applied to the transaction status as soon as they are seen, in `submit` and
+        // when blocks are decided.
+        vec![]
+    }
 
-    async fn submit(&mut self, txn: ElaboratedTransaction) -> Result<(), Self::Error> {
-        self.consensus.submit(txn).await
+    fn get_decided_state(&self) -> Option<&ValidatorState> {
+        self.cached_blocks
+            .iter()
+            .rev()
+            .find_map(|(_, state, _)| state.as_ref().map(|state| &state.state))
     }
 
     async fn next_event(&mut self) -> Result<ConsensusEvent, Self::Error> {
@@ -602,6 +722,7 @@ impl QueryData {
         store_path: &Path,
         consensus: Consensus,
         location: Option<String>,
+        metrics: Metrics,
     ) -> Result<QueryData, PersistenceError> {
         let key_tag = "query_data_store";
         let blocks_tag = format!("{}_blocks", key_tag);
@@ -627,6 +748,8 @@ impl QueryData {
             cached_blocks: Vec::new(),
             index_by_block_hash: HashMap::new(),
             index_by_txn_hash: HashMap::new(),
+            index_by_nullifier: HashMap::new(),
+            index_by_record_commitment: HashMap::new(),
             index_by_last_record_id: BTreeMap::new(),
             index_by_proposer_id: HashMap::new(),
             cached_events_start: 0usize,
@@ -635,6 +758,10 @@ impl QueryData {
             event_receiver,
             cached_nullifier_sets: BTreeMap::new(),
             node_status: ValidatorStatus::default(),
+            pending_transactions: HashMap::new(),
+            rejected_transactions: HashMap::new(),
+            evicted_transactions: HashSet::new(),
+            dropped_transactions: VecDeque::new(),
             query_storage,
             block_storage,
             state_storage,
@@ -643,6 +770,7 @@ impl QueryData {
             status_storage,
             consensus,
             location,
+            metrics,
         })
     }
 
@@ -650,6 +778,7 @@ impl QueryData {
         store_path: &Path,
         consensus: Consensus,
         location: Option<String>,
+        metrics: Metrics,
     ) -> Result<QueryData, PersistenceError> {
         let key_tag = "query_data_store";
         let blocks_tag = format!("{}_blocks", key_tag);
@@ -698,6 +827,8 @@ impl QueryData {
         );
         let cached_blocks: Vec<BlockAndAssociated> = zipped_iters.collect();
         let mut index_by_txn_hash = HashMap::new();
+        let mut index_by_nullifier = HashMap::new();
+        let mut index_by_record_commitment = HashMap::new();
         let mut index_by_last_record_id = BTreeMap::new();
         let mut index_by_proposer_id = HashMap::new();
         let mut cached_nullifier_sets = BTreeMap::new();
@@ -721,11 +852,15 @@ impl QueryData {
                         .for_each(|(id, txn_hash)| {
                             index_by_txn_hash.insert(*txn_hash, (block.block_id, id as u64));
                         });
-                    block.raw_block.block.0.iter().for_each(|txn| {
+                    for (id, txn) in block.raw_block.block.0.iter().enumerate() {
                         for n in txn.input_nullifiers() {
                             running_nullifier_set.insert(n);
+                            index_by_nullifier.insert(n, (block.block_id, id as u64));
                         }
-                    });
+                    }
+                    for record in block.records() {
+                        index_by_record_commitment.insert(record.commitment, record.uid);
+                    }
                     if Self::calculate_sparse_cache(
                         block.block_id,
                         block_storage.iter().len() as u64,
@@ -767,13 +902,18 @@ impl QueryData {
         // Load the last persisted validator status. If there is no existing status (e.g. the user
         // gave us an empty directory, but did not set the reset flag, so we ended up here and not
         // in `new`) we should behave as we do when creating a new store: use the default status.
-        let node_status = status_storage.load_latest().unwrap_or_default();
+        let mut node_status: ValidatorStatus = status_storage.load_latest().unwrap_or_default();
+        // The mempool does not survive a restart, and the transactions which made up the persisted
+        // mempool statistics are not persisted, so they could never be removed from the totals.
+        node_status.mempool_info = Default::default();
 
         Ok(QueryData {
             cached_blocks_start,
             cached_blocks,
             index_by_block_hash,
             index_by_txn_hash,
+            index_by_nullifier,
+            index_by_record_commitment,
             index_by_last_record_id,
             index_by_proposer_id,
             cached_events_start,
@@ -782,6 +922,10 @@ impl QueryData {
             event_receiver,
             cached_nullifier_sets,
             node_status,
+            pending_transactions: HashMap::new(),
+            rejected_transactions: HashMap::new(),
+            evicted_transactions: HashSet::new(),
+            dropped_transactions: VecDeque::new(),
             query_storage,
             block_storage,
             state_storage,
@@ -790,10 +934,17 @@ impl QueryData {
             status_storage,
             consensus,
             location,
+            metrics,
         })
     }
 
+    /// The metrics of the node this query service runs in.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
     pub fn commit_all(&mut self) {
+        let start = Instant::now();
         if let Err(e) = self.block_storage.commit_version() {
             warn!("Failed to commit block storage: Error {}", e);
         }
@@ -815,6 +966,104 @@ impl QueryData {
         if let Err(e) = self.status_storage.prune_file_entries() {
             warn!("Failed to prune status storage: Error {}", e);
         }
+        self.metrics
+            .store_commit_time
+            .observe(start.elapsed().as_secs_f64());
+    }
+
+    /// Stop tracking pending transactions which have been decided, reject those which can no
+    /// longer be decided, and forget those which the mempool has evicted.
+    ///
+    /// This runs under the data source lock for every decided block, so instead of validating each
+    /// pending transaction in full it only makes cheap checks against the latest decided state: a
+    /// transaction can no longer be decided if one of its inputs was spent by another transaction,
+    /// if the Merkle root it was built against is no longer recent, or if its proof has expired.
+    /// A transaction which stays valid remains pending, even if the mempool has stopped waiting for
+    /// it, because HotShot may still propose it.
+    fn reject_dead_transactions(&mut self) {
+        let state = self
+            .cached_blocks
+            .last()
+            .and_then(|(_, state, _)| state.as_ref())
+            .map(|state| &state.state);
+        let evicted = self.consensus.take_evicted();
+        let mut rejected = Vec::new();
+        self.pending_transactions.retain(|hash, pending| {
+            if self.index_by_txn_hash.contains_key(hash) || evicted.contains(hash) {
+                return false;
+            }
+            if let Some(nullifier) = pending
+                .txn
+                .input_nullifiers()
+                .into_iter()
+                .find(|nullifier| self.index_by_nullifier.contains_key(nullifier))
+            {
+                rejected.push((*hash, ValidationError::NullifierAlreadyExists { nullifier }));
+                return false;
+            }
+            if let Some(state) = state {
+                if let EspressoTransaction::CAP(note) = &pending.txn {
+                    if !state.is_recent_merkle_root(&note.merkle_root()) {
+                        rejected.push((*hash, ValidationError::BadMerkleRoot {}));
+                        return false;
+                    }
+                }
+                if let Some(valid_until) = pending.txn.proof_bound_valid_until() {
+                    if valid_until < state.block_height {
+                        rejected.push((
+                            *hash,
+                            ValidationError::Expired {
+                                valid_until,
+                                block_height: state.block_height,
+                            },
+                        ));
+                        return false;
+                    }
+                }
+            }
+            true
+        });
+        if rejected.is_empty() && evicted.is_empty() {
+            return;
+        }
+        if let Err(err) = self.edit_status(|vs| {
+            for hash in rejected.iter().map(|(hash, _)| hash).chain(&evicted) {
+                vs.remove_from_mempool(hash);
+            }
+            Ok::<(), ApiError>(())
+        }) {
+            warn!(
+                "failed to remove dead transactions from mempool status: {}",
+                err
+            );
+        }
+        for (hash, err) in rejected {
+            self.record_dropped(hash, Some(err));
+        }
+        for hash in evicted {
+            self.record_dropped(hash, None);
+        }
+    }
+
+    /// Remember that a transaction was rejected (with `error`) or evicted (with `None`), forgetting
+    /// the oldest dropped transactions once there are more than [DROPPED_TRANSACTIONS_COUNT].
+    fn record_dropped(&mut self, hash: TransactionCommitment, error: Option<ValidationError>) {
+        match error {
+            Some(error) => {
+                self.metrics.record_validation_failure(&error);
+                self.rejected_transactions.insert(hash, error);
+            }
+            None => {
+                self.evicted_transactions.insert(hash);
+            }
+        }
+        self.dropped_transactions.push_back(hash);
+        while self.dropped_transactions.len() > DROPPED_TRANSACTIONS_COUNT {
+            if let Some(hash) = self.dropped_transactions.pop_front() {
+                self.rejected_transactions.remove(&hash);
+                self.evicted_transactions.remove(&hash);
+            }
+        }
     }
 
     fn calculate_sparse_cache(_index: u64, _total_size: u64) -> bool {
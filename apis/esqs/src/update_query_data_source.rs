@@ -14,11 +14,13 @@ use espresso_availability_api::{
     query_data::{BlockQueryData, EncodedPublicKey, StateQueryData},
 };
 use espresso_catchup_api::data_source::UpdateCatchUpData;
+use espresso_core::metrics::Metrics;
+use espresso_core::proposals::ProposalTracker;
 use espresso_core::state::{
     EspressoTransaction, EspressoTxnHelperProofs, TransactionCommitment, ValidatorState,
 };
 use espresso_metastate_api::data_source::UpdateMetaStateData;
-use espresso_status_api::data_source::UpdateStatusData;
+use espresso_status_api::{data_source::UpdateStatusData, query_data::ValidatorStatus};
 use futures::{
     future::RemoteHandle,
     task::{SpawnError, SpawnExt},
@@ -27,6 +29,8 @@ use futures::{
 use itertools::izip;
 use reef::traits::Transaction;
 use seahorse::events::LedgerEvent;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 pub type HotShotEvent = hotshot::types::EventType<ValidatorState>;
 
@@ -46,7 +50,12 @@ where
     meta_state_store: Arc<RwLock<TYPES::MS>>,
     status_store: Arc<RwLock<TYPES::ST>>,
     event_handler: Arc<RwLock<TYPES::EH>>,
+    proposals: Arc<Mutex<ProposalTracker>>,
+    metrics: Metrics,
     validator_state: ValidatorState,
+    last_status_update: Instant,
+    /// The proposal counts from the proposal tracker as of the last status update.
+    last_proposal_counts: (u64, u64),
     _event_task: Option<RemoteHandle<()>>,
 }
 
@@ -65,6 +74,8 @@ where
         meta_state_store: Arc<RwLock<TYPES::MS>>,
         status_store: Arc<RwLock<TYPES::ST>>,
         event_handler: Arc<RwLock<TYPES::EH>>,
+        proposals: Arc<Mutex<ProposalTracker>>,
+        metrics: Metrics,
     ) -> Arc<RwLock<Self>> {
         let instance = Arc::new(RwLock::new(Self {
             catchup_store,
@@ -72,7 +83,11 @@ where
             meta_state_store,
             status_store,
             event_handler,
+            proposals,
+            metrics,
             validator_state: Default::default(),
+            last_status_update: Instant::now(),
+            last_proposal_counts: (0, 0),
             _event_task: None,
         }));
         if let Ok(task_handle) = launch_updates(event_source, instance.clone()) {
@@ -82,8 +97,49 @@ where
         instance
     }
 
+    /// The time elapsed since the last status update, to be added to `time_operational`.
+    fn elapsed_since_last_status_update(&mut self) -> Duration {
+        let now = Instant::now();
+        let elapsed = now - self.last_status_update;
+        self.last_status_update = now;
+        elapsed
+    }
+
+    /// Bring the proposal statistics in `vs` up to date with the proposal tracker.
+    ///
+    /// The tracker only counts proposals since this process started, while the status is
+    /// persisted, so the status accumulates the tracker's counts since the last update.
+    fn update_proposals(&mut self, vs: &mut ValidatorStatus) {
+        let tracker = self.proposals.lock().unwrap();
+        let (proposed, abandoned) = (tracker.proposed_count(), tracker.abandoned_count());
+        vs.proposed_block_count += proposed - self.last_proposal_counts.0;
+        vs.abandoned_block_count += abandoned - self.last_proposal_counts.1;
+        vs.pending_blocks = tracker.pending().clone();
+        self.last_proposal_counts = (proposed, abandoned);
+    }
+
     async fn update(&mut self, event: HotShotEvent) {
+        if let HotShotEvent::ViewTimeout { view_number } = event {
+            tracing::debug!("view {:?} timed out", view_number);
+            // A timeout does not resolve any proposals, but there may be new ones to report.
+            let elapsed = self.elapsed_since_last_status_update();
+            let status_store = self.status_store.clone();
+            let mut status_store = status_store.write().await;
+            status_store
+                .edit_status(|vs| {
+                    self.update_proposals(vs);
+                    vs.time_operational += elapsed;
+                    Ok(())
+                })
+                .unwrap();
+            return;
+        }
+
         if let HotShotEvent::Decide { leaf_chain } = event {
+            self.proposals
+                .lock()
+                .unwrap()
+                .decide(leaf_chain.iter().map(|leaf| *leaf.view_number));
             if let Some(leaf) = leaf_chain.last() {
                 // HotShot can give us a leaf chain that does not follow immediately from our last
                 // saved state, if it skipped ahead for liveness reasons. Insert missing blocks as
@@ -108,7 +164,9 @@ where
             }
 
             let mut cumulative_size = 0usize;
+            let mut decided_txns = Vec::new();
             for leaf in leaf_chain.iter().rev() {
+                let append_start = Instant::now();
                 let mut block = leaf.deltas.clone();
                 let state = &leaf.state;
                 let qcert = leaf.justify_qc.clone();
@@ -132,6 +190,7 @@ where
                     }
                     let hash = TransactionCommitment(txn.commit());
                     txn_hashes.push(hash);
+                    decided_txns.push(hash);
                 }
                 cumulative_size += block.serialized_size();
                 let continuation_event_index;
@@ -222,16 +281,26 @@ where
                         tracing::warn!("append_block_nullifiers returned error {}", e);
                     }
                 }
+                self.metrics
+                    .esqs_append_latency
+                    .observe(append_start.elapsed().as_secs_f64());
             }
-            let mut status_store = self.status_store.write().await;
+            let elapsed = self.elapsed_since_last_status_update();
+            let status_store = self.status_store.clone();
+            let mut status_store = status_store.write().await;
             status_store
                 .edit_status(|vs| {
+                    self.update_proposals(vs);
                     vs.latest_block_id = self.validator_state.block_height as u64 - 1;
                     vs.decided_block_count = self.validator_state.block_height as u64;
                     vs.cumulative_txn_count = self.validator_state.transaction_count as u64;
                     vs.cumulative_size += cumulative_size as u64;
                     vs.record_count = self.validator_state.record_merkle_commitment.num_leaves;
                     vs.nullifier_count = self.validator_state.nullifiers_count() as u64;
+                    vs.time_operational += elapsed;
+                    for hash in &decided_txns {
+                        vs.remove_from_mempool(hash);
+                    }
                     Ok(())
                 })
                 .unwrap();
@@ -4,14 +4,15 @@
 use crate::data_source::MetaStateDataSource;
 use clap::Args;
 use derive_more::From;
-use espresso_core::state::SetMerkleProof;
+use espresso_core::state::{SetMerkleProof, SetMerkleTree};
 use futures::FutureExt;
+use jf_cap::structs::Nullifier;
 use serde::{Deserialize, Serialize};
-use snafu::{OptionExt, Snafu};
+use snafu::{ensure, OptionExt, Snafu};
 use std::path::PathBuf;
 use tide_disco::{
     api::{Api, ApiError},
-    method::ReadState,
+    method::{ReadState, WriteState},
     RequestError, StatusCode,
 };
 
@@ -25,6 +26,8 @@ pub struct Options {
 pub enum Error {
     Request { source: RequestError },
     InvalidBlockId { block_id: u64 },
+    MissingNullifierProof { nullifier: Nullifier },
+    TooManyNullifiers { count: usize, max: usize },
 }
 
 impl Error {
@@ -32,20 +35,36 @@ impl Error {
         match self {
             Self::Request { .. } => StatusCode::BadRequest,
             Self::InvalidBlockId { .. } => StatusCode::BadRequest,
+            Self::MissingNullifierProof { .. } => StatusCode::InternalServerError,
+            Self::TooManyNullifiers { .. } => StatusCode::BadRequest,
         }
     }
 }
 
+/// The maximum number of nullifiers which can be checked in one `check_nullifiers` request.
+pub const MAX_NULLIFIER_BATCH: usize = 1000;
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct NullifierCheck {
     pub spent: bool,
     pub proof: SetMerkleProof,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NullifierBatchCheck {
+    /// The spent/unspent status of each requested nullifier, in the order they were requested.
+    pub checks: Vec<NullifierCheck>,
+    /// A sparse nullifier set containing just the requested nullifiers.
+    ///
+    /// The root hash of this set is the nullifier set root hash after the requested block, so it
+    /// authenticates all of the `checks` at once.
+    pub proof: SetMerkleTree,
+}
+
 pub fn define_api<State>(options: &Options) -> Result<Api<State, Error>, ApiError>
 where
-    State: 'static + Send + Sync + ReadState,
-    <State as ReadState>::State: Sync + MetaStateDataSource,
+    State: 'static + Send + Sync + WriteState,
+    <State as ReadState>::State: Send + Sync + MetaStateDataSource,
 {
     let mut api = match &options.api_path {
         Some(path) => Api::<State, Error>::from_file(path)?,
@@ -69,6 +88,33 @@ where
                 Ok(NullifierCheck { spent, proof })
             }
             .boxed()
+        })?
+        .post("check_nullifiers", |req, state| {
+            async move {
+                let block_id = req.integer_param("block_id")?;
+                let nullifiers: Vec<Nullifier> = req.body_auto()?;
+                ensure!(
+                    nullifiers.len() <= MAX_NULLIFIER_BATCH,
+                    TooManyNullifiersSnafu {
+                        count: nullifiers.len(),
+                        max: MAX_NULLIFIER_BATCH,
+                    }
+                );
+                let proof = state
+                    .get_nullifier_multi_proof_for(block_id, &nullifiers)
+                    .context(InvalidBlockIdSnafu { block_id })?;
+                let checks = nullifiers
+                    .into_iter()
+                    .map(|nullifier| {
+                        let (spent, proof) = proof
+                            .contains(nullifier)
+                            .context(MissingNullifierProofSnafu { nullifier })?;
+                        Ok(NullifierCheck { spent, proof })
+                    })
+                    .collect::<Result<_, Error>>()?;
+                Ok(NullifierBatchCheck { checks, proof })
+            }
+            .boxed()
         })?;
     Ok(api)
 }
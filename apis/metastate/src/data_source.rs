@@ -1,7 +1,7 @@
 // Copyright (c) 2022 Espresso Systems (espressosys.com)
 // This file is part of the Espresso library.
 
-use espresso_core::state::SetMerkleProof;
+use espresso_core::state::{SetMerkleProof, SetMerkleTree};
 use jf_cap::structs::Nullifier;
 use std::error::Error;
 use std::fmt::Debug;
@@ -12,6 +12,16 @@ pub trait MetaStateDataSource {
         block_id: u64,
         nullifier: Nullifier,
     ) -> Option<(bool, SetMerkleProof)>;
+
+    /// Get a sparse copy of the nullifier set after `block_id` containing only `nullifiers`.
+    ///
+    /// The result has the same root hash as the full nullifier set, so it serves as a single proof
+    /// of the spent/unspent status of all of the given nullifiers.
+    fn get_nullifier_multi_proof_for(
+        &self,
+        block_id: u64,
+        nullifiers: &[Nullifier],
+    ) -> Option<SetMerkleTree>;
 }
 
 pub trait UpdateMetaStateData {
@@ -74,7 +74,14 @@ where
         .get("success_rate", |_, state| {
             async move {
                 let status = state.get_validator_status();
-                Ok(status.decided_block_count as f64 / status.proposed_block_count as f64)
+                // Proposals which are still pending have neither succeeded nor failed yet.
+                let resolved = status
+                    .proposed_block_count
+                    .saturating_sub(status.pending_blocks.len() as u64);
+                if resolved == 0 {
+                    return Ok(0f64);
+                }
+                Ok(resolved.saturating_sub(status.abandoned_block_count) as f64 / resolved as f64)
             }
             .boxed()
         })?
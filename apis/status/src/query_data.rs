@@ -3,12 +3,11 @@
 
 use core::time::Duration;
 use espresso_core::{
-    state::{ElaboratedBlock, ValidatorState},
+    state::{ElaboratedBlock, TransactionCommitment},
     StakingKey,
 };
-use hotshot::data::QuorumCertificate;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PeerInfo {
@@ -40,16 +39,11 @@ pub struct Throughput {
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub struct ValidatorStatus {
     pub peer_list: Vec<PeerInfo>,
-    // TBD; these are going to correspond to active views, possibly want to also retain recent views?
-    // We may or may not want a seperate map of QuorumCertificates for `qc/:index` endpoint.
-    pub pending_blocks: HashMap<
-        u64,
-        (
-            ElaboratedBlock,
-            ValidatorState,
-            QuorumCertificate<ValidatorState>,
-        ),
-    >,
+    /// Blocks proposed to this node which have not yet been decided or abandoned, by view number.
+    ///
+    /// This is not persisted, since proposals pending before a restart are never resolved.
+    #[serde(skip)]
+    pub pending_blocks: BTreeMap<u64, ElaboratedBlock>,
     pub latest_block_id: u64, // id of latest block to reach DECIDE
     pub mempool_info: MempoolInfo,
     pub proposed_block_count: u64,
@@ -60,4 +54,39 @@ pub struct ValidatorStatus {
     pub record_count: u64,
     pub nullifier_count: u64,
     pub time_operational: Duration,
+    /// Transactions submitted through this node which have not yet been included in a block.
+    ///
+    /// Each entry is the contribution of one transaction to `mempool_info`. This is not persisted,
+    /// since the consensus mempool itself does not survive a restart.
+    #[serde(skip)]
+    pub mempool: HashMap<TransactionCommitment, MempoolInfo>,
+}
+
+impl ValidatorStatus {
+    /// Record a transaction which has been submitted to the mempool.
+    pub fn add_to_mempool(&mut self, hash: TransactionCommitment, output_count: u64, size: u64) {
+        if self.mempool.contains_key(&hash) {
+            return;
+        }
+        let info = MempoolInfo {
+            transaction_count: 1,
+            output_count,
+            memory_footprint: size,
+        };
+        self.mempool_info.transaction_count += info.transaction_count;
+        self.mempool_info.output_count += info.output_count;
+        self.mempool_info.memory_footprint += info.memory_footprint;
+        self.mempool.insert(hash, info);
+    }
+
+    /// Record that a transaction has left the mempool, usually by being included in a block.
+    ///
+    /// Transactions which were not submitted through this node are ignored.
+    pub fn remove_from_mempool(&mut self, hash: &TransactionCommitment) {
+        if let Some(info) = self.mempool.remove(hash) {
+            self.mempool_info.transaction_count -= info.transaction_count;
+            self.mempool_info.output_count -= info.output_count;
+            self.mempool_info.memory_footprint -= info.memory_footprint;
+        }
+    }
 }
@@ -1,9 +1,10 @@
 // Copyright (c) 2022 Espresso Systems (espressosys.com)
 // This file is part of the Espresso library.
 
-use crate::data_source::ValidatorDataSource;
+use crate::data_source::{MempoolError, ValidatorDataSource};
 use clap::Args;
 use derive_more::From;
+use espresso_core::state::{ElaboratedTransaction, TransactionCommitment, ValidationError};
 use futures::FutureExt;
 use serde::{Deserialize, Serialize};
 use snafu::Snafu;
@@ -30,6 +31,28 @@ pub enum Error {
     Submission {
         reason: String,
     },
+
+    #[from(ignore)]
+    #[snafu(display("invalid transaction: {}", error))]
+    InvalidTransaction {
+        error: ValidationError,
+    },
+
+    #[from(ignore)]
+    #[snafu(display("transaction refused by the mempool: {}", error))]
+    Mempool {
+        error: MempoolError,
+    },
+
+    #[from(ignore)]
+    #[snafu(display("the requested transaction {} is not known to this validator", hash))]
+    UnknownTransaction {
+        hash: TransactionCommitment,
+    },
+
+    #[from(ignore)]
+    #[snafu(display("no blocks have been decided yet"))]
+    NoDecidedState,
 }
 
 impl Error {
@@ -37,6 +60,13 @@ impl Error {
         match self {
             Self::Request { .. } => StatusCode::BadRequest,
             Self::Submission { .. } => StatusCode::InternalServerError,
+            Self::InvalidTransaction { .. } => StatusCode::BadRequest,
+            Self::Mempool {
+                error: MempoolError::Full,
+            } => StatusCode::ServiceUnavailable,
+            Self::Mempool { .. } => StatusCode::BadRequest,
+            Self::UnknownTransaction { .. } => StatusCode::NotFound,
+            Self::NoDecidedState => StatusCode::ServiceUnavailable,
         }
     }
 }
@@ -60,12 +90,39 @@ where
     api.with_version(env!("CARGO_PKG_VERSION").parse().unwrap())
         .post("submit", |req, state| {
             async move {
-                let txn = req.body_auto()?;
+                let txn: ElaboratedTransaction = req.body_auto()?;
+                // Reject transactions which are certain to fail validation here, rather than
+                // letting them into the mempool where they would cost every node verification time.
+                if let Some(validator_state) = state.get_decided_state() {
+                    validator_state
+                        .validate_transaction(&txn)
+                        .map_err(|error| Error::InvalidTransaction { error })?;
+                }
+                state
+                    .check_admission(&txn)
+                    .map_err(|error| Error::Mempool { error })?;
                 state.submit(txn).await.map_err(|source| Error::Submission {
                     reason: source.to_string(),
                 })
             }
             .boxed()
+        })?
+        .post("simulate", |req, state| {
+            async move {
+                let txn = req.body_auto()?;
+                let validator_state = state.get_decided_state().ok_or(Error::NoDecidedState)?;
+                Ok(validator_state.validate_transaction(&txn))
+            }
+            .boxed()
+        })?
+        .get("status", |req, state| {
+            async move {
+                let hash = req.blob_param("hash")?;
+                state
+                    .get_transaction_status(hash)
+                    .ok_or(Error::UnknownTransaction { hash })
+            }
+            .boxed()
         })?;
     Ok(api)
 }
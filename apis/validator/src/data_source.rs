@@ -1,23 +1,67 @@
 // Copyright (c) 2022 Espresso Systems (espressosys.com)
 // This file is part of the Espresso library.
 
+use crate::query_data::TransactionStatus;
 use async_trait::async_trait;
-use espresso_core::state::{ElaboratedTransaction, ValidatorState};
+use commit::Committable;
+use espresso_core::state::{ElaboratedTransaction, TransactionCommitment, ValidatorState};
 use futures::stream::{unfold, BoxStream, StreamExt};
 use hotshot::{
     traits::NodeImplementation,
     types::{EventType, HotShotHandle},
     HotShotError,
 };
+use jf_cap::structs::Nullifier;
+use serde::{Deserialize, Serialize};
+use snafu::Snafu;
 use std::error::Error;
 use std::fmt::Debug;
 
 pub type ConsensusEvent = EventType<ValidatorState>;
 
+/// Reasons a transaction can be refused admission to the mempool.
+#[derive(Clone, Debug, Snafu, Serialize, Deserialize)]
+pub enum MempoolError {
+    #[snafu(display("transaction {} is already in the mempool", hash))]
+    Duplicate { hash: TransactionCommitment },
+
+    #[snafu(display(
+        "nullifier {} is already spent by pending transaction {}",
+        nullifier,
+        hash
+    ))]
+    ConflictingNullifier {
+        nullifier: Nullifier,
+        hash: TransactionCommitment,
+    },
+
+    #[snafu(display("the mempool is full"))]
+    Full,
+}
+
+/// Errors submitting a transaction to consensus through a mempool.
+#[derive(Debug, Snafu)]
+pub enum SubmitError<E: Error + 'static> {
+    #[snafu(display("transaction refused by the mempool: {}", error))]
+    Mempool { error: MempoolError },
+
+    #[snafu(display("{}", source))]
+    Consensus { source: E },
+}
+
 #[async_trait]
 pub trait ValidatorDataSource {
     type Error: Error + Debug;
-    async fn submit(&mut self, txn: ElaboratedTransaction) -> Result<(), Self::Error>;
+    async fn submit(
+        &mut self,
+        txn: ElaboratedTransaction,
+    ) -> Result<TransactionCommitment, Self::Error>;
+    fn get_transaction_status(&self, hash: TransactionCommitment) -> Option<TransactionStatus>;
+    fn get_decided_state(&self) -> Option<&ValidatorState>;
+    /// Check whether `txn` would be admitted to the mempool if it were submitted.
+    fn check_admission(&self, txn: &ElaboratedTransaction) -> Result<(), MempoolError>;
+    /// Take the hashes of transactions which have been evicted from the mempool since the last call.
+    fn take_evicted(&mut self) -> Vec<TransactionCommitment>;
     async fn next_event(&mut self) -> Result<ConsensusEvent, Self::Error>;
 
     fn into_stream(self) -> BoxStream<'static, ConsensusEvent>
@@ -41,8 +85,32 @@ where
 {
     type Error = HotShotError;
 
-    async fn submit(&mut self, txn: ElaboratedTransaction) -> Result<(), Self::Error> {
-        self.submit_transaction(txn).await
+    async fn submit(
+        &mut self,
+        txn: ElaboratedTransaction,
+    ) -> Result<TransactionCommitment, Self::Error> {
+        let hash = TransactionCommitment(txn.txn.commit());
+        self.submit_transaction(txn).await?;
+        Ok(hash)
+    }
+
+    fn get_transaction_status(&self, _hash: TransactionCommitment) -> Option<TransactionStatus> {
+        // HotShot does not track the transactions submitted to it.
+        None
+    }
+
+    fn get_decided_state(&self) -> Option<&ValidatorState> {
+        // The decided state is only available asynchronously from a HotShot handle.
+        None
+    }
+
+    fn check_admission(&self, _txn: &ElaboratedTransaction) -> Result<(), MempoolError> {
+        // HotShot accepts every transaction into its queue.
+        Ok(())
+    }
+
+    fn take_evicted(&mut self) -> Vec<TransactionCommitment> {
+        vec![]
     }
 
     async fn next_event(&mut self) -> Result<ConsensusEvent, Self::Error> {
@@ -3,5 +3,6 @@
 
 pub mod api;
 pub mod data_source;
+pub mod query_data;
 
 pub use api::*;
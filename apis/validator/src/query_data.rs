@@ -0,0 +1,20 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Espresso library.
+
+use espresso_core::state::ValidationError;
+use serde::{Deserialize, Serialize};
+
+/// The status of a transaction submitted to a validator.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TransactionStatus {
+    /// The transaction is waiting in the mempool of the validator it was submitted to.
+    Pending,
+    /// The transaction has been decided, as transaction `txn_id` of block `block_id`.
+    Committed { block_id: u64, txn_id: u64 },
+    /// The transaction was dropped and can never be committed.
+    Rejected { error: ValidationError },
+    /// The transaction was dropped from a full mempool in favor of transactions paying higher fees.
+    ///
+    /// It can still be committed if it is submitted again.
+    Evicted,
+}
@@ -0,0 +1,199 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Espresso library.
+
+//! # Offline transactions
+//!
+//! Build transactions on an air-gapped machine and submit them from an online one. See
+//! [espresso_client::offline] for the protocol.
+//!
+//! On the online machine, take a snapshot of the ledger including the records to spend:
+//! ```text
+//! offline-wallet snapshot --uid 12 --uid 15 --out snapshot.bin
+//! ```
+//! Carry `snapshot.bin` to the offline machine and build a transaction:
+//! ```text
+//! offline-wallet build --snapshot snapshot.bin --key key_file --receiver USERPUBKEY~... \
+//!     --amount 100 --fee 1 --out txn.b64
+//! ```
+//! Carry `txn.b64` back to the online machine and submit it:
+//! ```text
+//! offline-wallet submit-file txn.b64
+//! ```
+
+use async_std::task::block_on;
+use clap::{Parser, Subcommand, ValueEnum};
+use espresso_client::{
+    offline::{build_transfer, OfflineSnapshot, OfflineTransaction},
+    options::{err, NetworkOptions},
+};
+use espresso_core::{state::ValidatorState, universal_params::PROVER_CRS};
+use jf_cap::{
+    keys::{UserKeyPair, UserPubKey},
+    structs::Amount,
+};
+use rand::SeedableRng;
+use rand_chacha::ChaChaRng;
+use std::fs;
+use std::path::PathBuf;
+use std::process::exit;
+
+#[derive(Parser)]
+#[command(
+    name = "Espresso offline wallet",
+    about = "Build transactions offline and submit them later"
+)]
+struct Options {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Fetch a snapshot of the ledger for building a transaction offline (online).
+    Snapshot {
+        /// UID of a record to include in the snapshot, to be spent offline.
+        #[arg(long = "uid", required = true)]
+        uids: Vec<u64>,
+
+        /// File to write the snapshot to.
+        #[arg(long)]
+        out: PathBuf,
+
+        #[command(flatten)]
+        network: NetworkOptions,
+    },
+
+    /// Build and prove a native asset transfer from a snapshot (offline).
+    Build {
+        /// Snapshot produced by the `snapshot` command.
+        #[arg(long)]
+        snapshot: PathBuf,
+
+        /// Key pair owning the records in the snapshot, as generated by `wallet-cli -g`.
+        #[arg(long)]
+        key: PathBuf,
+
+        /// Public key of the receiver.
+        #[arg(long)]
+        receiver: String,
+
+        #[arg(long)]
+        amount: u64,
+
+        #[arg(long)]
+        fee: u64,
+
+        /// File to write the transaction to.
+        #[arg(long)]
+        out: PathBuf,
+
+        #[arg(long, value_enum, default_value = "tagged-base64")]
+        format: ExportFormat,
+    },
+
+    /// Submit a transaction built by the `build` command (online).
+    SubmitFile {
+        /// Transaction file, in either export format.
+        file: PathBuf,
+
+        #[command(flatten)]
+        network: NetworkOptions,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    TaggedBase64,
+    Bincode,
+}
+
+async fn snapshot(uids: Vec<u64>, out: PathBuf, network: NetworkOptions) -> Result<(), String> {
+    let backend = network.backend().await.map_err(err)?;
+    let snapshot = backend.get_offline_snapshot(&uids).await.map_err(err)?;
+    fs::write(&out, bincode::serialize(&snapshot).unwrap()).map_err(err)?;
+    println!(
+        "Wrote snapshot of block {} with {} records to {}",
+        snapshot.state.block_id,
+        snapshot.records.len(),
+        out.display()
+    );
+    Ok(())
+}
+
+fn build(
+    snapshot: PathBuf,
+    key: PathBuf,
+    receiver: String,
+    amount: u64,
+    fee: u64,
+    out: PathBuf,
+    format: ExportFormat,
+) -> Result<(), String> {
+    let snapshot: OfflineSnapshot =
+        bincode::deserialize(&fs::read(snapshot).map_err(err)?).map_err(err)?;
+    let key_pair: UserKeyPair = bincode::deserialize(&fs::read(key).map_err(err)?).map_err(err)?;
+    let receiver: UserPubKey = receiver
+        .parse()
+        .map_err(|e| format!("invalid receiver public key: {}", e))?;
+
+    let txn = build_transfer(
+        &mut ChaChaRng::from_entropy(),
+        &snapshot,
+        &key_pair,
+        receiver,
+        Amount::from(amount),
+        Amount::from(fee),
+        &PROVER_CRS,
+    )
+    .map_err(err)?;
+    let contents = match format {
+        ExportFormat::TaggedBase64 => txn.to_tagged_base64().into_bytes(),
+        ExportFormat::Bincode => txn.to_bincode(),
+    };
+    fs::write(&out, contents).map_err(err)?;
+    println!(
+        "Wrote transaction built against block {} to {}",
+        txn.block_id,
+        out.display()
+    );
+    Ok(())
+}
+
+async fn submit_file(file: PathBuf, network: NetworkOptions) -> Result<(), String> {
+    let txn = OfflineTransaction::from_file_contents(&fs::read(file).map_err(err)?).map_err(err)?;
+    let backend = network.backend().await.map_err(err)?;
+    let state = backend.get_verified_state().await.map_err(err)?;
+    if !txn.is_root_recent(&state.state) {
+        eprintln!(
+            "Warning: this transaction was built against block {}, which is more than {} blocks \
+             older than the latest verified block {}. Validators will probably reject it; build \
+             it again from a new snapshot.",
+            txn.block_id,
+            ValidatorState::HISTORY_SIZE,
+            state.block_id
+        );
+    }
+    let hash = backend.submit_offline(&state, txn).await.map_err(err)?;
+    println!("Submitted transaction {}", hash);
+    Ok(())
+}
+
+fn main() {
+    let res = match Options::parse().command {
+        Command::Snapshot { uids, out, network } => block_on(snapshot(uids, out, network)),
+        Command::Build {
+            snapshot,
+            key,
+            receiver,
+            amount,
+            fee,
+            out,
+            format,
+        } => build(snapshot, key, receiver, amount, fee, out, format),
+        Command::SubmitFile { file, network } => block_on(submit_file(file, network)),
+    };
+    if let Err(err) = res {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
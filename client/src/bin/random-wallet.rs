@@ -264,7 +264,7 @@ async fn main() {
     };
     let backend = NetworkBackend::new(
         &UNIVERSAL_PARAM,
-        args.esqs_url.clone(),
+        vec![args.esqs_url.clone()],
         args.address_book_url.clone(),
         args.validator_url.clone(),
     )
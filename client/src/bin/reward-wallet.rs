@@ -0,0 +1,185 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Espresso library.
+
+//! # Staking rewards
+//!
+//! Find and collect the staking rewards earned by a validator's staking key. See
+//! [espresso_client::rewards] for how the proofs are built.
+//!
+//! The staking key is read from a file. For a validator whose key is derived from a secret key
+//! seed, export the key once, on a machine holding the seed:
+//! ```text
+//! reward-wallet export-key --secret-key-seed SEED~... --id 3 --out staking.key
+//! ```
+//! Then, from any machine holding the key file:
+//! ```text
+//! reward-wallet eligible --staking-key staking.key --from-view 1000
+//! reward-wallet collect --staking-key staking.key --receiver USERPUBKEY~...
+//! ```
+
+use async_std::task::block_on;
+use clap::{Args, Parser, Subcommand};
+use espresso_client::{
+    network::NetworkBackend,
+    options::{err, NetworkOptions},
+    rewards::RewardHistory,
+};
+use espresso_core::stake_table::StakingPrivKey;
+use espresso_validator::{gen_keys, SecretKeySeed};
+use jf_cap::keys::UserPubKey;
+use rand::SeedableRng;
+use rand_chacha::ChaChaRng;
+use std::fs;
+use std::path::PathBuf;
+use std::process::exit;
+
+#[derive(Parser)]
+#[command(
+    name = "Espresso reward wallet",
+    about = "Find and collect staking rewards"
+)]
+struct Options {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the views in which the staking key earned a reward which has not been collected.
+    Eligible {
+        #[command(flatten)]
+        key: KeyOptions,
+
+        #[command(flatten)]
+        network: NetworkOptions,
+    },
+
+    /// Collect all uncollected rewards earned by the staking key.
+    Collect {
+        #[command(flatten)]
+        key: KeyOptions,
+
+        /// Public key of the receiver of the rewards.
+        #[arg(long)]
+        receiver: String,
+
+        #[command(flatten)]
+        network: NetworkOptions,
+    },
+
+    /// Derive a validator's staking key from its secret key seed, and write it to a file.
+    ExportKey {
+        /// Seed used by the validators to generate their staking keys.
+        #[arg(long, env = "ESPRESSO_VALIDATOR_SECRET_KEY_SEED")]
+        secret_key_seed: SecretKeySeed,
+
+        /// ID of the validator whose staking key to export.
+        #[arg(long, env = "ESPRESSO_VALIDATOR_ID")]
+        id: usize,
+
+        /// File to write the staking key to.
+        #[arg(long)]
+        out: PathBuf,
+    },
+}
+
+#[derive(Args)]
+struct KeyOptions {
+    /// File containing the staking key which earned the rewards, as written by `export-key`.
+    #[arg(long, env = "ESPRESSO_STAKING_KEY_FILE")]
+    staking_key: PathBuf,
+
+    /// Only consider rewards from this view onwards.
+    #[arg(long, default_value = "0")]
+    from_view: u64,
+
+    /// Only consider rewards up to and including this view.
+    #[arg(long, default_value_t = u64::MAX)]
+    to_view: u64,
+}
+
+impl KeyOptions {
+    fn staking_key(&self) -> Result<StakingPrivKey, String> {
+        let bytes = fs::read(&self.staking_key)
+            .map_err(|e| format!("failed to read {}: {}", self.staking_key.display(), e))?;
+        bincode::deserialize(&bytes).map_err(|e| format!("invalid staking key file: {}", e))
+    }
+
+    /// Load the staking key and the reward history for the requested views.
+    async fn reward_history(
+        &self,
+        network: NetworkOptions,
+    ) -> Result<(StakingPrivKey, NetworkBackend<'static>, RewardHistory), String> {
+        let staking_key = self.staking_key()?;
+        let backend = network.backend().await.map_err(err)?;
+        let history = backend
+            .get_reward_history(self.from_view..=self.to_view)
+            .await
+            .map_err(err)?;
+        Ok((staking_key, backend, history))
+    }
+}
+
+async fn eligible(key: KeyOptions, network: NetworkOptions) -> Result<(), String> {
+    let (staking_key, _, history) = key.reward_history(network).await?;
+    let rewards = history.eligible_rewards(&staking_key);
+    for reward in &rewards {
+        println!("view {} (block {})", *reward.view, reward.block_id);
+    }
+    println!(
+        "{} uncollected rewards as of block {}",
+        rewards.len(),
+        history.state.block_id
+    );
+    Ok(())
+}
+
+async fn collect(key: KeyOptions, receiver: String, network: NetworkOptions) -> Result<(), String> {
+    let receiver: UserPubKey = receiver
+        .parse()
+        .map_err(|e| format!("invalid receiver public key: {}", e))?;
+    let (staking_key, backend, history) = key.reward_history(network).await?;
+    let rewards = history.eligible_rewards(&staking_key);
+    if rewards.is_empty() {
+        println!("No uncollected rewards");
+        return Ok(());
+    }
+
+    let mut rng = ChaChaRng::from_entropy();
+    for reward in rewards {
+        let view = reward.view;
+        let txn = history
+            .collect_reward(&mut rng, &staking_key, receiver.clone(), reward)
+            .map_err(|e| format!("failed to build reward claim for view {}: {}", *view, e))?;
+        let hash = backend.submit_transaction(&txn).await.map_err(err)?;
+        println!("Submitted reward claim for view {}: {}", *view, hash);
+    }
+    Ok(())
+}
+
+fn export_key(secret_key_seed: SecretKeySeed, id: usize, out: PathBuf) -> Result<(), String> {
+    let staking_key = gen_keys(Some(secret_key_seed), id + 1).pop().unwrap();
+    fs::write(&out, bincode::serialize(&staking_key).map_err(err)?).map_err(err)?;
+    println!("Wrote staking key of validator {} to {}", id, out.display());
+    Ok(())
+}
+
+fn main() {
+    let res = match Options::parse().command {
+        Command::Eligible { key, network } => block_on(eligible(key, network)),
+        Command::Collect {
+            key,
+            receiver,
+            network,
+        } => block_on(collect(key, receiver, network)),
+        Command::ExportKey {
+            secret_key_seed,
+            id,
+            out,
+        } => export_key(secret_key_seed, id, out),
+    };
+    if let Err(err) = res {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
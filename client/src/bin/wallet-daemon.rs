@@ -0,0 +1,558 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Espresso library.
+
+//! # The Espresso Wallet Daemon
+//!
+//! A long-running wallet service which holds a keystore and exposes it over a REST API, so that
+//! other services can hold and spend funds programmatically. The API is documented in
+//! `api/wallet-daemon.toml`.
+//!
+//! The API is only served on the loopback interface unless `--bind-address` says otherwise, and
+//! every request must present the API token in an `Authorization: Bearer <token>` header. The
+//! mnemonic, the keystore password and the token are secrets, so they are read from files or the
+//! environment, never from the command line.
+
+use async_std::sync::{Arc, Mutex};
+use clap::Parser;
+use espresso_client::{
+    events::{EventIndex, EventSource},
+    hd::Mnemonic,
+    ledger_state::{TransactionStatus, TransactionUID},
+    loader::{MnemonicPasswordLogin, RecoveryLoader},
+    network::NetworkBackend,
+    options::{read_secret, NetworkOptions},
+    EspressoKeystore, EspressoKeystoreError, RecordAmount,
+};
+use espresso_core::{ledger::EspressoLedger, state::TransactionCommitment};
+use futures::FutureExt;
+use jf_cap::{
+    keys::{FreezerPubKey, UserAddress, UserPubKey, ViewerPubKey},
+    structs::{AssetCode, FreezeFlag},
+};
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    SeedableRng,
+};
+use rand_chacha::ChaChaRng;
+use reef::traits::Validator;
+use serde::{Deserialize, Serialize};
+use snafu::Snafu;
+use std::env;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use subtle::ConstantTimeEq;
+use tide_disco::{App, RequestError, RequestParams, StatusCode};
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "Espresso Wallet Daemon",
+    about = "Serves a REST API for a wallet holding Espresso assets"
+)]
+struct Options {
+    /// File containing the mnemonic phrase for the wallet.
+    ///
+    /// If not given, the mnemonic is read from the environment variable `ESPRESSO_WALLET_MNEMONIC`.
+    /// If a wallet already exists at the keystore path, it must have been created from this
+    /// mnemonic. Otherwise, a new wallet is created from the mnemonic.
+    #[arg(long, env = "ESPRESSO_WALLET_MNEMONIC_FILE")]
+    mnemonic_file: Option<PathBuf>,
+
+    /// File containing the token which clients must present to use the API.
+    ///
+    /// If not given, the token is read from the environment variable `ESPRESSO_WALLET_API_TOKEN`.
+    #[arg(long, env = "ESPRESSO_WALLET_API_TOKEN_FILE")]
+    api_token_file: Option<PathBuf>,
+
+    /// Path to the wallet's keystore.
+    #[arg(long, env = "ESPRESSO_WALLET_STORE_PATH")]
+    keystore_path: Option<PathBuf>,
+
+    /// File containing the password for the wallet's keystore.
+    ///
+    /// If not given, the password is read from the environment variable `ESPRESSO_WALLET_PASSWORD`.
+    /// If neither is set, a random password is used. The keystore can always be reopened with the
+    /// mnemonic.
+    #[arg(long, env = "ESPRESSO_WALLET_PASSWORD_FILE")]
+    password_file: Option<PathBuf>,
+
+    /// Address on which to serve the wallet API.
+    ///
+    /// By default, the API is only available to clients on this machine.
+    #[arg(
+        long,
+        env = "ESPRESSO_WALLET_BIND_ADDRESS",
+        default_value = "127.0.0.1"
+    )]
+    bind_address: IpAddr,
+
+    /// Port on which to serve the wallet API.
+    #[arg(long, env = "ESPRESSO_WALLET_PORT", default_value = "50077")]
+    port: u16,
+
+    /// Override path to API specification.
+    #[arg(long, env = "ESPRESSO_WALLET_API_PATH")]
+    api_path: Option<PathBuf>,
+
+    #[command(flatten)]
+    network: NetworkOptions,
+}
+
+impl Options {
+    fn keystore_path(&self) -> PathBuf {
+        self.keystore_path.clone().unwrap_or_else(|| {
+            dirs::data_local_dir()
+                .unwrap_or_else(|| std::env::current_dir().unwrap_or_else(|_| PathBuf::from("./")))
+                .join(".espresso")
+                .join("espresso")
+                .join("wallet-daemon")
+                .join("keystore")
+        })
+    }
+
+    fn mnemonic(&self) -> Result<Mnemonic, String> {
+        read_secret(self.mnemonic_file.as_deref(), "ESPRESSO_WALLET_MNEMONIC")?
+            .parse()
+            .map_err(|err| format!("invalid mnemonic: {}", err))
+    }
+
+    fn password(&self) -> Result<Option<String>, String> {
+        if self.password_file.is_none() && env::var_os("ESPRESSO_WALLET_PASSWORD").is_none() {
+            return Ok(None);
+        }
+        read_secret(self.password_file.as_deref(), "ESPRESSO_WALLET_PASSWORD").map(Some)
+    }
+
+    fn api_token(&self) -> Result<String, String> {
+        read_secret(self.api_token_file.as_deref(), "ESPRESSO_WALLET_API_TOKEN")
+    }
+}
+
+#[derive(Debug, Snafu, Serialize, Deserialize)]
+enum WalletError {
+    #[snafu(display("bad request: {}", source))]
+    Request { source: RequestError },
+
+    #[snafu(display("missing or invalid API token"))]
+    Unauthorized,
+
+    #[snafu(display("invalid amount {:?}: amounts are decimal strings", amount))]
+    InvalidAmount { amount: String },
+
+    #[snafu(display("wallet error: {}", msg))]
+    Keystore { msg: String },
+
+    #[snafu(display("network error: {}", msg))]
+    Network { msg: String },
+
+    #[snafu(display("internal server error: {}", msg))]
+    Internal { msg: String, status: StatusCode },
+}
+
+impl tide_disco::Error for WalletError {
+    fn catch_all(status: StatusCode, msg: String) -> Self {
+        Self::Internal { status, msg }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::Request { .. } => StatusCode::BadRequest,
+            Self::Unauthorized => StatusCode::Unauthorized,
+            Self::InvalidAmount { .. } => StatusCode::BadRequest,
+            Self::Keystore { .. } => StatusCode::BadRequest,
+            Self::Network { .. } => StatusCode::BadGateway,
+            Self::Internal { status, .. } => *status,
+        }
+    }
+}
+
+impl From<RequestError> for WalletError {
+    fn from(source: RequestError) -> Self {
+        Self::Request { source }
+    }
+}
+
+impl From<EspressoKeystoreError> for WalletError {
+    fn from(source: EspressoKeystoreError) -> Self {
+        match source {
+            // The network backend reports every failure to reach the EsQS, address book or
+            // validator, or to verify their responses, as `Failed`.
+            EspressoKeystoreError::Failed { msg } => Self::Network { msg },
+            source => Self::Keystore {
+                msg: source.to_string(),
+            },
+        }
+    }
+}
+
+type Keystore = EspressoKeystore<'static, NetworkBackend<'static>, MnemonicPasswordLogin>;
+
+#[derive(Clone)]
+struct WalletState {
+    keystore: Arc<Mutex<Keystore>>,
+    api_token: Arc<String>,
+}
+
+impl WalletState {
+    /// Check that `req` carries the API token.
+    fn authorize(&self, req: &RequestParams) -> Result<(), WalletError> {
+        check_token(
+            req.header("Authorization").map(|value| value.as_str()),
+            &self.api_token,
+        )
+    }
+}
+
+/// Check the `Authorization` header of a request against the API token.
+///
+/// The token is compared in constant time, so that response times do not reveal how much of a
+/// guess is correct.
+fn check_token(authorization: Option<&str>, api_token: &str) -> Result<(), WalletError> {
+    match authorization.and_then(|value| value.strip_prefix("Bearer ")) {
+        Some(token) if bool::from(token.trim().as_bytes().ct_eq(api_token.as_bytes())) => Ok(()),
+        _ => Err(WalletError::Unauthorized),
+    }
+}
+
+/// Parse an amount given as a decimal string.
+fn parse_amount(amount: &str) -> Result<RecordAmount, WalletError> {
+    amount
+        .parse::<u128>()
+        .map(RecordAmount::from)
+        .map_err(|_| WalletError::InvalidAmount {
+            amount: amount.to_string(),
+        })
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct RecordInfo {
+    owner: UserAddress,
+    asset: AssetCode,
+    amount: String,
+    frozen: bool,
+    /// Whether the record is reserved as an input to a pending transaction.
+    on_hold: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct TransferRequest {
+    from: Option<UserAddress>,
+    asset: AssetCode,
+    receiver: UserPubKey,
+    amount: String,
+    fee: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct FreezeRequest {
+    from: Option<UserAddress>,
+    asset: AssetCode,
+    owner: UserAddress,
+    amount: String,
+    fee: String,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum KeyKind {
+    Sending,
+    Viewing,
+    Freezing,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct NewKeyRequest {
+    kind: KeyKind,
+    description: String,
+    /// Event index to scan for records belonging to the new key from.
+    ///
+    /// If not provided, the key will only discover records created after it.
+    scan_from: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum NewKey {
+    Sending(UserPubKey),
+    Viewing(ViewerPubKey),
+    Freezing(FreezerPubKey),
+}
+
+async fn get_addresses(
+    req: RequestParams,
+    state: &WalletState,
+) -> Result<Vec<UserPubKey>, WalletError> {
+    state.authorize(&req)?;
+    Ok(state
+        .keystore
+        .lock()
+        .await
+        .sending_keys()
+        .await
+        .into_iter()
+        .map(|key| key.pub_key())
+        .collect())
+}
+
+async fn get_balance(req: RequestParams, state: &WalletState) -> Result<String, WalletError> {
+    state.authorize(&req)?;
+    let asset: AssetCode = req.blob_param("asset")?;
+    let keystore = state.keystore.lock().await;
+    let balance = match req.opt_blob_param::<UserAddress>("address")? {
+        Some(address) => keystore.balance_breakdown(&address, &asset).await,
+        None => keystore.balance(&asset).await,
+    };
+    Ok(balance.to_string())
+}
+
+async fn get_records(
+    req: RequestParams,
+    state: &WalletState,
+) -> Result<Vec<RecordInfo>, WalletError> {
+    state.authorize(&req)?;
+    let keystore = state.keystore.lock().await;
+    let now = keystore.read().await.state().validator.block_height();
+    Ok(keystore
+        .records()
+        .await
+        .into_iter()
+        .map(|record| RecordInfo {
+            owner: record.pub_key().address(),
+            asset: record.asset_code(),
+            amount: record.amount().to_string(),
+            frozen: record.freeze_flag() == FreezeFlag::Frozen,
+            on_hold: record.on_hold(now),
+        })
+        .collect())
+}
+
+async fn transfer(
+    req: RequestParams,
+    state: &WalletState,
+) -> Result<TransactionCommitment, WalletError> {
+    state.authorize(&req)?;
+    let body: TransferRequest = req.body_auto()?;
+    let amount = parse_amount(&body.amount)?;
+    let fee = parse_amount(&body.fee)?;
+    let txn = state
+        .keystore
+        .lock()
+        .await
+        .transfer(
+            body.from.as_ref(),
+            &body.asset,
+            &[(body.receiver, amount)],
+            fee,
+        )
+        .await?;
+    Ok(TransactionCommitment(txn.0))
+}
+
+async fn mint(
+    req: RequestParams,
+    state: &WalletState,
+) -> Result<TransactionCommitment, WalletError> {
+    state.authorize(&req)?;
+    let body: TransferRequest = req.body_auto()?;
+    let amount = parse_amount(&body.amount)?;
+    let fee = parse_amount(&body.fee)?;
+    let txn = state
+        .keystore
+        .lock()
+        .await
+        .mint(body.from.as_ref(), fee, &body.asset, amount, body.receiver)
+        .await?;
+    Ok(TransactionCommitment(txn.0))
+}
+
+async fn freeze(
+    req: RequestParams,
+    state: &WalletState,
+    freeze: bool,
+) -> Result<TransactionCommitment, WalletError> {
+    state.authorize(&req)?;
+    let body: FreezeRequest = req.body_auto()?;
+    let amount = parse_amount(&body.amount)?;
+    let fee = parse_amount(&body.fee)?;
+    let mut keystore = state.keystore.lock().await;
+    let txn = if freeze {
+        keystore
+            .freeze(body.from.as_ref(), fee, &body.asset, amount, body.owner)
+            .await?
+    } else {
+        keystore
+            .unfreeze(body.from.as_ref(), fee, &body.asset, amount, body.owner)
+            .await?
+    };
+    Ok(TransactionCommitment(txn.0))
+}
+
+async fn new_key(req: RequestParams, state: &WalletState) -> Result<NewKey, WalletError> {
+    state.authorize(&req)?;
+    let body: NewKeyRequest = req.body_auto()?;
+    let scan_from = body
+        .scan_from
+        .map(|index| EventIndex::from_source(EventSource::QueryService, index as usize));
+    let mut keystore = state.keystore.lock().await;
+    Ok(match body.kind {
+        KeyKind::Sending => NewKey::Sending(
+            keystore
+                .generate_sending_account(body.description, scan_from)
+                .await?,
+        ),
+        KeyKind::Viewing => NewKey::Viewing(
+            keystore
+                .generate_viewing_account(body.description, scan_from)
+                .await?,
+        ),
+        KeyKind::Freezing => NewKey::Freezing(
+            keystore
+                .generate_freezing_account(body.description, scan_from)
+                .await?,
+        ),
+    })
+}
+
+async fn transaction_status(
+    req: RequestParams,
+    state: &WalletState,
+) -> Result<TransactionStatus, WalletError> {
+    state.authorize(&req)?;
+    let hash: TransactionCommitment = req.blob_param("hash")?;
+    Ok(state
+        .keystore
+        .lock()
+        .await
+        .transaction_status(&TransactionUID::<EspressoLedger>(hash.0))
+        .await?)
+}
+
+async fn init_web_server(opt: &Options) -> io::Result<()> {
+    let invalid_input = |msg| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    let mnemonic = opt.mnemonic().map_err(invalid_input)?;
+    let api_token = opt.api_token().map_err(invalid_input)?;
+    let password = opt.password().map_err(invalid_input)?;
+
+    let mut rng = ChaChaRng::from_entropy();
+    let password = password.unwrap_or_else(|| Alphanumeric.sample_string(&mut rng, 16));
+    let mut loader = RecoveryLoader::new(&mut rng, opt.keystore_path(), mnemonic, password);
+    let backend = opt
+        .network
+        .clone()
+        .backend()
+        .await
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+    let keystore = Keystore::new(backend, &mut loader)
+        .await
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+    let state = WalletState {
+        keystore: Arc::new(Mutex::new(keystore)),
+        api_token: Arc::new(api_token),
+    };
+
+    let mut app = App::<WalletState, WalletError>::with_state(state);
+    let api = match &opt.api_path {
+        Some(path) => toml::from_slice(&fs::read(path)?).unwrap(),
+        None => toml::from_str(include_str!("../../api/wallet-daemon.toml")).unwrap(),
+    };
+    app.module("wallet", api)
+        .unwrap()
+        .at("getaddresses", |req, state| {
+            get_addresses(req, state).boxed()
+        })
+        .unwrap()
+        .at("getbalance", |req, state| get_balance(req, state).boxed())
+        .unwrap()
+        .at("getrecords", |req, state| get_records(req, state).boxed())
+        .unwrap()
+        .at("transfer", |req, state| transfer(req, state).boxed())
+        .unwrap()
+        .at("mint", |req, state| mint(req, state).boxed())
+        .unwrap()
+        .at("freeze", |req, state| freeze(req, state, true).boxed())
+        .unwrap()
+        .at("unfreeze", |req, state| freeze(req, state, false).boxed())
+        .unwrap()
+        .at("newkey", |req, state| new_key(req, state).boxed())
+        .unwrap()
+        .at("transaction", |req, state| {
+            transaction_status(req, state).boxed()
+        })
+        .unwrap();
+    app.serve(format!("{}:{}", opt.bind_address, opt.port))
+        .await
+}
+
+#[async_std::main]
+async fn main() -> std::io::Result<()> {
+    tracing_subscriber::fmt()
+        .with_ansi(false)
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .init();
+    init_web_server(&Options::parse()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_options() {
+        // The API is only served locally by default.
+        let opt = Options::try_parse_from(["wallet-daemon"]).unwrap();
+        assert_eq!(opt.bind_address, IpAddr::from([127, 0, 0, 1]));
+
+        // Secrets cannot be given on the command line.
+        assert!(Options::try_parse_from(["wallet-daemon", "--mnemonic", "words"]).is_err());
+        assert!(Options::try_parse_from(["wallet-daemon", "--password", "secret"]).is_err());
+    }
+
+    #[test]
+    fn test_check_token() {
+        check_token(Some("Bearer secret"), "secret").unwrap();
+        for authorization in [
+            None,
+            Some("secret"),
+            Some("Bearer other"),
+            Some("Bearer secret2"),
+            Some("Bearer "),
+            Some("Basic secret"),
+        ] {
+            let err = check_token(authorization, "secret").unwrap_err();
+            assert_eq!(
+                tide_disco::Error::status(&err),
+                StatusCode::Unauthorized,
+                "{:?}",
+                authorization
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_amount() {
+        // Amounts beyond the range of a JSON number are exact.
+        assert_eq!(
+            parse_amount("340282366920938463463374607431768211455").unwrap(),
+            RecordAmount::from(u128::MAX)
+        );
+        for amount in ["", "-1", "1.5", "0x10", "1e3"] {
+            assert!(matches!(
+                parse_amount(amount),
+                Err(WalletError::InvalidAmount { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_error_status() {
+        let status =
+            |err: EspressoKeystoreError| tide_disco::Error::status(&WalletError::from(err));
+        assert_eq!(
+            status(EspressoKeystoreError::Failed {
+                msg: "EsQS request failed".into()
+            }),
+            StatusCode::BadGateway
+        );
+    }
+}
@@ -2,6 +2,7 @@
 // Copyright (c) 2022 Espresso Systems (espressosys.com)
 // This file is part of the Espresso library.
 
+use crate::json_io::{Output, Response};
 use async_std::task::{block_on, spawn_blocking};
 use cld::ClDuration;
 use escargot::CargoBuild;
@@ -10,7 +11,9 @@ use espresso_validator::{testing::AddressBook, MINIMUM_BOOTSTRAP_NODES, MINIMUM_
 use itertools::Itertools;
 use jf_cap::keys::UserPubKey;
 use portpicker::pick_unused_port;
+use rand_chacha::{rand_core::SeedableRng, ChaChaRng};
 use regex::Regex;
+use seahorse::hd::KeyTree;
 use std::collections::HashMap;
 use std::env;
 use std::ffi::OsStr;
@@ -97,6 +100,48 @@ impl CliClient {
         Ok(self)
     }
 
+    /// Open an existing keystore in `--json` mode.
+    ///
+    /// Each command issued to the keystore produces a single JSON [Response], which can be
+    /// retrieved using [json](#method.json).
+    pub fn open_json(
+        &mut self,
+        keystore: usize,
+        mnemonic: &str,
+        password: &str,
+    ) -> Result<&mut Self, String> {
+        while keystore >= self.keystores.len() {
+            self.load(None)?;
+        }
+        let dir = self.keystores[keystore].storage();
+        let mnemonic_file = dir.join("mnemonic");
+        let password_file = dir.join("password");
+        fs::write(&mnemonic_file, mnemonic).map_err(err)?;
+        fs::write(&password_file, password).map_err(err)?;
+        self.open_with_args(
+            keystore,
+            [
+                OsStr::new("--json"),
+                OsStr::new("--mnemonic-file"),
+                mnemonic_file.as_os_str(),
+                OsStr::new("--password-file"),
+                password_file.as_os_str(),
+            ],
+        )
+    }
+
+    /// Create a new keystore in `--json` mode, from a new mnemonic.
+    ///
+    /// Returns the mnemonic, which can be used to reopen or recover the keystore with
+    /// [open_json](#method.open_json).
+    pub fn create_json(&mut self, keystore: usize, password: &str) -> Result<String, String> {
+        let mnemonic = KeyTree::random(&mut ChaChaRng::from_entropy())
+            .1
+            .to_string();
+        self.open_json(keystore, &mnemonic, password)?;
+        Ok(mnemonic)
+    }
+
     pub fn close(&mut self, keystore: usize) -> Result<&mut Self, String> {
         if let Some(keystore) = self.keystores.get_mut(keystore) {
             keystore.close();
@@ -183,6 +228,43 @@ impl CliClient {
         ))
     }
 
+    /// Parse the output of the previous command issued to a keystore in `--json` mode.
+    pub fn json(&self) -> Result<Response, String> {
+        let line = self
+            .prev_output
+            .last()
+            .ok_or("previous command produced no output")?;
+        serde_json::from_str(line).map_err(err)
+    }
+
+    /// Issue a command to a keystore in `--json` mode and return its result.
+    ///
+    /// Fails if the command reports an error.
+    pub fn run(&mut self, keystore: usize, command: impl AsRef<str>) -> Result<Output, String> {
+        let res = self.command(keystore, command)?.json()?;
+        match res.result {
+            Some(output) => Ok(output),
+            None => Err(format!(
+                "command {:?} failed: {}",
+                res.command,
+                res.error.unwrap_or_default()
+            )),
+        }
+    }
+
+    /// Issue a command to a keystore in `--json` mode which is expected to fail, and return its
+    /// error.
+    pub fn run_err(&mut self, keystore: usize, command: impl AsRef<str>) -> Result<String, String> {
+        let res = self.command(keystore, command)?.json()?;
+        match res.error {
+            Some(err) => Ok(err),
+            None => Err(format!(
+                "expected command {:?} to fail, got {:?}",
+                res.command, res.result
+            )),
+        }
+    }
+
     pub fn last_output(&self) -> impl Iterator<Item = &String> {
         self.prev_output.iter()
     }
@@ -287,6 +369,7 @@ struct OpenKeystore {
     stdin: ChildStdin,
     stdout: BufReader<ChildStdout>,
     process: Child,
+    json: bool,
 }
 
 pub struct Keystore {
@@ -358,6 +441,11 @@ impl Keystore {
         if self.process.is_some() {
             return Err(String::from("keystore is already open"));
         }
+        let args = args
+            .into_iter()
+            .map(|arg| arg.as_ref().to_os_string())
+            .collect::<Vec<_>>();
+        let json = args.iter().any(|arg| arg == "--json");
         let mut child = cargo_run("espresso-client", "wallet-cli")?
             .args([
                 "--storage",
@@ -392,6 +480,7 @@ impl Keystore {
             process: child,
             stdin,
             stdout: BufReader::new(stdout),
+            json,
         });
         self.read_until_prompt()
     }
@@ -429,6 +518,13 @@ impl Keystore {
                 if !line.is_empty() {
                     lines.push(String::from(line));
                 }
+                if child.json {
+                    // In JSON mode, each line of input produces exactly one line of output.
+                    if line.is_empty() {
+                        continue;
+                    }
+                    break;
+                }
                 match line {
                     ">"
                     | "Enter password:"
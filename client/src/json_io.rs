@@ -0,0 +1,772 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Espresso library.
+
+//! Machine-readable mode for the keystore CLI.
+//!
+//! In `--json` mode, the CLI opens the keystore non-interactively, from a mnemonic, and then reads
+//! one command per line from stdin. It prints exactly one JSON object (on a single line) in response
+//! to each line of input, built directly from the values the command produced:
+//!
+//! ```json
+//! {"command": "balance ASSET_CODE~...", "result": {"balance": {"balances": {"ADDR~...": "100"}, "total": "100"}}}
+//! {"command": "transfer ASSET_CODE~... USERPUBKEY~... 100 1", "error": "insufficient balance"}
+//! ```
+//!
+//! * `command` is the line of input this object responds to. It is `null` for the first object,
+//!   which is printed once the keystore is open and lists its addresses.
+//! * `result` is present if the command succeeded. It has a single field, named after the kind of
+//!   value it contains: see [Output].
+//! * `error` is present if the command failed, and contains the error message.
+//!
+//! The commands are the ones of the interactive CLI, with the same names and arguments:
+//!
+//! * `address`: list the keystore's addresses
+//! * `info` or `keys`: list the keystore's sending, viewing and freezing keys
+//! * `assets`: list the assets known to the keystore
+//! * `asset ASSET`: details of the asset with code `ASSET`
+//! * `balance ASSET [ADDRESS]`: balance of `ASSET`, by address and in total
+//! * `records`: list the records owned by the keystore
+//! * `gen_key sending|viewing|freezing [DESCRIPTION] [scan_from=EVENT]`: generate a new key, which
+//!   scans for records from event `EVENT` if it is given
+//! * `load_key sending PATH [scan_from=EVENT]`: add the sending key pair stored in `PATH`
+//! * `create_asset NAME [viewing_key=KEY] [freezing_key=KEY]`: define a new asset, which this
+//!   keystore can mint
+//! * `import_memo MEMO COMMITMENT UID PROOF`: add the record opened by an owner memo
+//! * `transfer ASSET RECEIVER AMOUNT FEE [wait=true]`: transfer `AMOUNT` of `ASSET` to the public
+//!   key `RECEIVER`
+//! * `mint ASSET RECEIVER AMOUNT FEE [wait=true]`: mint `AMOUNT` of `ASSET`, which this keystore
+//!   defined
+//! * `freeze ASSET OWNER AMOUNT FEE [wait=true]` and `unfreeze ASSET OWNER AMOUNT FEE [wait=true]`:
+//!   freeze or unfreeze the records of `ASSET` owned by the address `OWNER`
+//! * `wait RECEIPT`: wait for a transaction submitted by this keystore to complete
+//! * `transaction RECEIPT`: status of a transaction submitted by this keystore
+//! * `now`: the index of the latest EsQS event the keystore has processed
+//! * `sync EVENT`: wait until the keystore has processed the event with index `EVENT`
+//!
+//! As in the CLI, key kinds can be abbreviated (`send`, `spend`, `view`, `freeze`). With
+//! `wait=true`, a transaction command responds once the transaction is complete, and fails if it was
+//! rejected.
+//!
+//! Amounts, in commands and in results, are decimal strings, since they may not fit in a JSON
+//! number.
+
+use crate::{network::NetworkBackend, options::err, EspressoKeystore, RecordAmount};
+use espresso_core::{ledger::EspressoLedger, state::TransactionCommitment};
+use jf_cap::{
+    keys::{FreezerPubKey, UserAddress, UserKeyPair, UserPubKey, ViewerPubKey},
+    structs::{
+        AssetCode, AssetDefinition, AssetPolicy, FreezeFlag, ReceiverMemo, RecordCommitment,
+    },
+    MerklePath,
+};
+use reef::traits::Validator;
+use seahorse::{
+    events::{EventIndex, EventSource},
+    ledger_state::{TransactionStatus, TransactionUID},
+    loader::MnemonicPasswordLogin,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+pub type JsonKeystore<'a> = EspressoKeystore<'a, NetworkBackend<'a>, MnemonicPasswordLogin>;
+
+/// The response to a single line of input.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub command: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Output>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Response {
+    fn new(command: Option<String>, res: Result<Output, String>) -> Self {
+        match res {
+            Ok(output) => Self {
+                command,
+                result: Some(output),
+                error: None,
+            },
+            Err(err) => Self {
+                command,
+                result: None,
+                error: Some(err),
+            },
+        }
+    }
+}
+
+/// The result of a successful command.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Output {
+    Addresses(Vec<Account>),
+    Keys(Keys),
+    Assets(Vec<AssetDetails>),
+    Asset(AssetDetails),
+    Balance(Balance),
+    Records(Vec<RecordInfo>),
+    Key(NewKey),
+    /// The account of a loaded key.
+    Account(Account),
+    /// The receipt of a submitted transaction, which can be passed to `wait` and `transaction`.
+    Receipt(TransactionCommitment),
+    Status(TransactionStatus),
+    /// An index into the EsQS event stream, which can be passed to `sync`.
+    Time(usize),
+    /// A command with no other result, such as `import_memo`, succeeded.
+    Done,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Account {
+    pub address: UserAddress,
+    pub pub_key: UserPubKey,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Keys {
+    pub sending: Vec<Account>,
+    pub viewing: Vec<ViewerPubKey>,
+    pub freezing: Vec<FreezerPubKey>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AssetDetails {
+    pub code: AssetCode,
+    /// The name given to the asset by this keystore, if it knows one.
+    pub name: Option<String>,
+    pub native: bool,
+    pub viewer: Option<ViewerPubKey>,
+    pub freezer: Option<FreezerPubKey>,
+    /// Whether this keystore can mint the asset.
+    pub mintable: bool,
+}
+
+impl AssetDetails {
+    fn new(definition: &AssetDefinition, name: Option<String>, mintable: bool) -> Self {
+        let policy = definition.policy_ref();
+        Self {
+            code: definition.code,
+            name,
+            native: definition.code == AssetCode::native(),
+            viewer: if policy.is_viewer_pub_key_set() {
+                Some(policy.viewer_pub_key().clone())
+            } else {
+                None
+            },
+            freezer: if policy.is_freezer_pub_key_set() {
+                Some(policy.freezer_pub_key().clone())
+            } else {
+                None
+            },
+            mintable,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Balance {
+    /// The balance held by each address.
+    pub balances: BTreeMap<String, String>,
+    pub total: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordInfo {
+    pub owner: UserAddress,
+    pub asset: AssetCode,
+    pub amount: String,
+    pub frozen: bool,
+    /// Whether the record is reserved as an input to a pending transaction.
+    pub on_hold: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NewKey {
+    Sending(UserPubKey),
+    Viewing(ViewerPubKey),
+    Freezing(FreezerPubKey),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyKind {
+    Sending,
+    Viewing,
+    Freezing,
+}
+
+impl FromStr for KeyKind {
+    type Err = String;
+
+    fn from_str(kind: &str) -> Result<Self, String> {
+        match kind {
+            "sending" | "send" | "spend" => Ok(Self::Sending),
+            "viewing" | "view" => Ok(Self::Viewing),
+            "freezing" | "freeze" => Ok(Self::Freezing),
+            _ => Err(format!("invalid key kind {}", kind)),
+        }
+    }
+}
+
+/// The kind of transaction built by a transaction command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransactionKind {
+    Transfer,
+    Mint,
+    Freeze,
+    Unfreeze,
+}
+
+/// A parsed line of input.
+#[derive(Clone, Debug)]
+pub enum Command {
+    Address,
+    Keys,
+    Assets,
+    Asset {
+        asset: AssetCode,
+    },
+    Balance {
+        asset: AssetCode,
+        address: Option<UserAddress>,
+    },
+    Records,
+    GenKey {
+        kind: KeyKind,
+        description: String,
+        scan_from: Option<usize>,
+    },
+    LoadKey {
+        path: PathBuf,
+        scan_from: usize,
+    },
+    CreateAsset {
+        name: String,
+        viewing_key: Option<ViewerPubKey>,
+        freezing_key: Option<FreezerPubKey>,
+    },
+    ImportMemo {
+        memo: ReceiverMemo,
+        commitment: RecordCommitment,
+        uid: u64,
+        proof: MerklePath,
+    },
+    /// A `transfer` or `mint` to a public key, or a `freeze` or `unfreeze` of an address.
+    Transaction {
+        kind: TransactionKind,
+        asset: AssetCode,
+        /// The receiver of a transfer or mint, or the owner of the records to freeze.
+        account: String,
+        amount: RecordAmount,
+        fee: RecordAmount,
+        wait: bool,
+    },
+    Wait {
+        receipt: TransactionCommitment,
+    },
+    Status {
+        receipt: TransactionCommitment,
+    },
+    Now,
+    Sync {
+        time: usize,
+    },
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let command = words.next().ok_or("empty command")?;
+        // As in the CLI, optional arguments are given as `name=value`, after the positional ones.
+        let (options, args): (Vec<_>, Vec<_>) = words.partition(|word| word.contains('='));
+        let mut options = options
+            .into_iter()
+            .map(|option| {
+                let (name, value) = option.split_at(option.find('=').unwrap());
+                (name, &value[1..])
+            })
+            .collect::<BTreeMap<_, _>>();
+        let usage = |usage: &str| format!("usage: {} {}", command, usage);
+        let cmd = match (command, args.as_slice()) {
+            ("address", []) => Self::Address,
+            ("info" | "keys", []) => Self::Keys,
+            ("assets", []) => Self::Assets,
+            ("asset", [asset]) => Self::Asset {
+                asset: parse_arg("asset", asset)?,
+            },
+            ("asset", _) => return Err(usage("ASSET")),
+            ("balance", [asset]) => Self::Balance {
+                asset: parse_arg("asset", asset)?,
+                address: None,
+            },
+            ("balance", [asset, address]) => Self::Balance {
+                asset: parse_arg("asset", asset)?,
+                address: Some(parse_arg("address", address)?),
+            },
+            ("balance", _) => return Err(usage("ASSET [ADDRESS]")),
+            ("records", []) => Self::Records,
+            ("gen_key", [kind, description @ ..]) => Self::GenKey {
+                kind: kind.parse()?,
+                description: description.join(" "),
+                scan_from: take_option(&mut options, "scan_from")?,
+            },
+            ("gen_key", _) => {
+                return Err(usage(
+                    "sending|viewing|freezing [DESCRIPTION] [scan_from=EVENT]",
+                ))
+            }
+            ("load_key", [kind, path]) => {
+                if kind.parse::<KeyKind>()? != KeyKind::Sending {
+                    return Err("only sending keys can be loaded".to_string());
+                }
+                Self::LoadKey {
+                    path: PathBuf::from(path),
+                    scan_from: take_option(&mut options, "scan_from")?.unwrap_or_default(),
+                }
+            }
+            ("load_key", _) => return Err(usage("sending PATH [scan_from=EVENT]")),
+            ("create_asset", [name]) => Self::CreateAsset {
+                name: name.to_string(),
+                viewing_key: take_option(&mut options, "viewing_key")?,
+                freezing_key: take_option(&mut options, "freezing_key")?,
+            },
+            ("create_asset", _) => return Err(usage("NAME [viewing_key=KEY] [freezing_key=KEY]")),
+            ("import_memo", [memo, commitment, uid, proof]) => Self::ImportMemo {
+                memo: parse_arg("memo", memo)?,
+                commitment: parse_arg("commitment", commitment)?,
+                uid: parse_arg("uid", uid)?,
+                proof: parse_arg("proof", proof)?,
+            },
+            ("import_memo", _) => return Err(usage("MEMO COMMITMENT UID PROOF")),
+            ("transfer" | "mint" | "freeze" | "unfreeze", [asset, account, amount, fee]) => {
+                Self::Transaction {
+                    kind: match command {
+                        "transfer" => TransactionKind::Transfer,
+                        "mint" => TransactionKind::Mint,
+                        "freeze" => TransactionKind::Freeze,
+                        _ => TransactionKind::Unfreeze,
+                    },
+                    asset: parse_arg("asset", asset)?,
+                    account: account.to_string(),
+                    amount: parse_amount(amount)?,
+                    fee: parse_amount(fee)?,
+                    wait: take_option(&mut options, "wait")?.unwrap_or(false),
+                }
+            }
+            ("transfer" | "mint", _) => return Err(usage("ASSET RECEIVER AMOUNT FEE [wait=true]")),
+            ("freeze" | "unfreeze", _) => return Err(usage("ASSET OWNER AMOUNT FEE [wait=true]")),
+            ("wait", [receipt]) => Self::Wait {
+                receipt: parse_arg("receipt", receipt)?,
+            },
+            ("transaction", [receipt]) => Self::Status {
+                receipt: parse_arg("receipt", receipt)?,
+            },
+            ("wait" | "transaction", _) => return Err(usage("RECEIPT")),
+            ("now", []) => Self::Now,
+            ("sync", [time]) => Self::Sync {
+                time: parse_arg("event index", time)?,
+            },
+            ("sync", _) => return Err(usage("EVENT")),
+            ("address" | "info" | "keys" | "assets" | "records" | "now", _) => {
+                return Err(format!("{} takes no arguments", command))
+            }
+            _ => return Err(format!("unknown command {}", command)),
+        };
+        if let Some(name) = options.keys().next() {
+            return Err(format!("{} has no option {}", command, name));
+        }
+        Ok(cmd)
+    }
+}
+
+/// Remove the option `name` from `options` and parse its value, if it was given.
+fn take_option<T: FromStr>(
+    options: &mut BTreeMap<&str, &str>,
+    name: &str,
+) -> Result<Option<T>, String>
+where
+    T::Err: Display,
+{
+    options
+        .remove(name)
+        .map(|value| parse_arg(name, value))
+        .transpose()
+}
+
+fn parse_arg<T: FromStr>(name: &str, arg: &str) -> Result<T, String>
+where
+    T::Err: Display,
+{
+    arg.parse()
+        .map_err(|err| format!("invalid {} {}: {}", name, arg, err))
+}
+
+/// Parse an amount given as a decimal string.
+fn parse_amount(amount: &str) -> Result<RecordAmount, String> {
+    amount
+        .parse::<u128>()
+        .map(RecordAmount::from)
+        .map_err(|_| format!("invalid amount {}", amount))
+}
+
+async fn accounts(keystore: &JsonKeystore<'_>) -> Vec<Account> {
+    keystore
+        .sending_keys()
+        .await
+        .into_iter()
+        .map(|key| Account {
+            address: key.address(),
+            pub_key: key.pub_key(),
+        })
+        .collect()
+}
+
+/// The index of the `index`-th event from the EsQS, which is the only source of events.
+fn event_index(index: usize) -> EventIndex {
+    EventIndex::from_source(EventSource::QueryService, index)
+}
+
+/// Run a command against the keystore.
+pub async fn execute(keystore: &mut JsonKeystore<'_>, command: Command) -> Result<Output, String> {
+    Ok(match command {
+        Command::Address => Output::Addresses(accounts(keystore).await),
+        Command::Keys => Output::Keys(Keys {
+            sending: accounts(keystore).await,
+            viewing: keystore.viewing_pub_keys().await,
+            freezing: keystore.freezing_pub_keys().await,
+        }),
+        Command::Assets => Output::Assets(
+            keystore
+                .assets()
+                .await
+                .into_iter()
+                .map(|info| {
+                    AssetDetails::new(info.definition(), info.name(), info.mint_info().is_some())
+                })
+                .collect(),
+        ),
+        Command::Asset { asset } => {
+            let info = keystore
+                .asset(asset)
+                .await
+                .ok_or_else(|| format!("unknown asset {}", asset))?;
+            Output::Asset(AssetDetails::new(
+                info.definition(),
+                info.name(),
+                info.mint_info().is_some(),
+            ))
+        }
+        Command::Balance { asset, address } => {
+            let mut balances = BTreeMap::new();
+            let total = match address {
+                Some(address) => {
+                    let amount = keystore.balance_breakdown(&address, &asset).await;
+                    balances.insert(address.to_string(), amount.to_string());
+                    amount
+                }
+                None => {
+                    for key in keystore.sending_keys().await {
+                        let amount = keystore.balance_breakdown(&key.address(), &asset).await;
+                        balances.insert(key.address().to_string(), amount.to_string());
+                    }
+                    keystore.balance(&asset).await
+                }
+            };
+            Output::Balance(Balance {
+                balances,
+                total: total.to_string(),
+            })
+        }
+        Command::Records => {
+            let now = keystore.read().await.state().validator.block_height();
+            Output::Records(
+                keystore
+                    .records()
+                    .await
+                    .into_iter()
+                    .map(|record| RecordInfo {
+                        owner: record.pub_key().address(),
+                        asset: record.asset_code(),
+                        amount: record.amount().to_string(),
+                        frozen: record.freeze_flag() == FreezeFlag::Frozen,
+                        on_hold: record.on_hold(now),
+                    })
+                    .collect(),
+            )
+        }
+        Command::GenKey {
+            kind,
+            description,
+            scan_from,
+        } => Output::Key(match kind {
+            KeyKind::Sending => NewKey::Sending(
+                keystore
+                    .generate_sending_account(description, scan_from.map(event_index))
+                    .await
+                    .map_err(err)?,
+            ),
+            KeyKind::Viewing => NewKey::Viewing(
+                keystore
+                    .generate_viewing_account(description, scan_from.map(event_index))
+                    .await
+                    .map_err(err)?,
+            ),
+            KeyKind::Freezing => NewKey::Freezing(
+                keystore
+                    .generate_freezing_account(description, scan_from.map(event_index))
+                    .await
+                    .map_err(err)?,
+            ),
+        }),
+        Command::LoadKey { path, scan_from } => {
+            let bytes = fs::read(&path)
+                .map_err(|source| format!("failed to read {}: {}", path.display(), source))?;
+            let key: UserKeyPair = bincode::deserialize(&bytes)
+                .map_err(|source| format!("invalid key file {}: {}", path.display(), source))?;
+            let account = Account {
+                address: key.address(),
+                pub_key: key.pub_key(),
+            };
+            keystore
+                .add_sending_account(key, path.display().to_string(), event_index(scan_from))
+                .await
+                .map_err(err)?;
+            Output::Account(account)
+        }
+        Command::CreateAsset {
+            name,
+            viewing_key,
+            freezing_key,
+        } => {
+            let mut policy = AssetPolicy::default();
+            if let Some(viewing_key) = viewing_key {
+                policy = policy.set_viewer_pub_key(viewing_key);
+            }
+            if let Some(freezing_key) = freezing_key {
+                policy = policy.set_freezer_pub_key(freezing_key);
+            }
+            let definition = keystore
+                .define_asset(name.clone(), &[], policy)
+                .await
+                .map_err(err)?;
+            Output::Asset(AssetDetails::new(&definition, Some(name), true))
+        }
+        Command::ImportMemo {
+            memo,
+            commitment,
+            uid,
+            proof,
+        } => {
+            keystore
+                .import_memo(memo, commitment, uid, proof)
+                .await
+                .map_err(err)?;
+            Output::Done
+        }
+        Command::Transaction {
+            kind,
+            asset,
+            account,
+            amount,
+            fee,
+            wait,
+        } => {
+            let txn = match kind {
+                TransactionKind::Transfer => {
+                    let receiver: UserPubKey = parse_arg("receiver", &account)?;
+                    keystore
+                        .transfer(None, &asset, &[(receiver, amount)], fee)
+                        .await
+                }
+                TransactionKind::Mint => {
+                    let receiver: UserPubKey = parse_arg("receiver", &account)?;
+                    keystore.mint(None, fee, &asset, amount, receiver).await
+                }
+                TransactionKind::Freeze => {
+                    let owner: UserAddress = parse_arg("owner", &account)?;
+                    keystore.freeze(None, fee, &asset, amount, owner).await
+                }
+                TransactionKind::Unfreeze => {
+                    let owner: UserAddress = parse_arg("owner", &account)?;
+                    keystore.unfreeze(None, fee, &asset, amount, owner).await
+                }
+            }
+            .map_err(err)?;
+            let receipt = TransactionCommitment(txn.0);
+            if wait {
+                let status = keystore.await_transaction(&txn).await.map_err(err)?;
+                if !status.succeeded() {
+                    return Err(format!("transaction {} was rejected", receipt));
+                }
+            }
+            Output::Receipt(receipt)
+        }
+        Command::Wait { receipt } => Output::Status(
+            keystore
+                .await_transaction(&TransactionUID::<EspressoLedger>(receipt.0))
+                .await
+                .map_err(err)?,
+        ),
+        Command::Status { receipt } => Output::Status(
+            keystore
+                .transaction_status(&TransactionUID::<EspressoLedger>(receipt.0))
+                .await
+                .map_err(err)?,
+        ),
+        Command::Now => Output::Time(keystore.now().await.index(EventSource::QueryService)),
+        Command::Sync { time } => {
+            keystore.sync(event_index(time)).await.map_err(err)?;
+            Output::Time(time)
+        }
+    })
+}
+
+/// Serve commands from `input` until it is closed, writing one line of JSON to `output` for each.
+///
+/// The first line of output, printed before any input is read, lists the keystore's addresses.
+pub async fn json_main(
+    keystore: &mut JsonKeystore<'_>,
+    input: impl BufRead,
+    mut output: impl Write,
+) -> io::Result<()> {
+    let addresses = execute(keystore, Command::Address).await;
+    emit(&mut output, Response::new(None, addresses))?;
+    for line in input.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let res = match line.parse() {
+            Ok(command) => execute(keystore, command).await,
+            Err(err) => Err(err),
+        };
+        emit(&mut output, Response::new(Some(line.to_string()), res))?;
+    }
+    Ok(())
+}
+
+fn emit(output: &mut impl Write, res: Response) -> io::Result<()> {
+    serde_json::to_writer(&mut *output, &res)?;
+    writeln!(output)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        let asset = AssetCode::native().to_string();
+
+        assert!(matches!("address".parse::<Command>(), Ok(Command::Address)));
+        assert!(matches!("info".parse::<Command>(), Ok(Command::Keys)));
+        assert!(matches!(
+            format!(" balance  {} ", asset).parse::<Command>(),
+            Ok(Command::Balance { address: None, .. })
+        ));
+        assert!(matches!(
+            "gen_key view my key".parse::<Command>(),
+            Ok(Command::GenKey {
+                kind: KeyKind::Viewing,
+                description,
+                scan_from: None,
+            }) if description == "my key"
+        ));
+        assert!(matches!(
+            "gen_key send scan_from=0".parse::<Command>(),
+            Ok(Command::GenKey {
+                kind: KeyKind::Sending,
+                scan_from: Some(_),
+                ..
+            })
+        ));
+        assert!(matches!(
+            "create_asset coin".parse::<Command>(),
+            Ok(Command::CreateAsset {
+                viewing_key: None,
+                freezing_key: None,
+                ..
+            })
+        ));
+        assert!(matches!(
+            format!("mint {} USERPUBKEY~key 100 1 wait=true", asset).parse::<Command>(),
+            Ok(Command::Transaction {
+                kind: TransactionKind::Mint,
+                wait: true,
+                ..
+            })
+        ));
+
+        for bad in [
+            "",
+            "address now",
+            "balance",
+            "balance ASSET_CODE~bogus",
+            "gen_key spending",
+            "load_key viewing key.pub",
+            "create_asset",
+            "create_asset coin viewing_key=AUDPUBKEY~bogus",
+            "transfer",
+            "transaction TXN~bogus",
+            "records mine=true",
+            "bogus",
+        ] {
+            assert!(bad.parse::<Command>().is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_parse_amount() {
+        // Amounts beyond the range of a JSON number are exact.
+        assert_eq!(
+            parse_amount("340282366920938463463374607431768211455").unwrap(),
+            RecordAmount::from(u128::MAX)
+        );
+        for amount in ["", "-1", "1.5", "0x10", "1e3"] {
+            assert!(parse_amount(amount).is_err(), "{:?}", amount);
+        }
+    }
+
+    #[test]
+    fn test_response_format() {
+        let asset = AssetCode::native();
+        let mut balance = Balance {
+            total: "5".into(),
+            ..Default::default()
+        };
+        balance.balances.insert("ADDR~test".into(), "5".into());
+        let res = Response::new(
+            Some(format!("balance {}", asset)),
+            Ok(Output::Balance(balance)),
+        );
+        let json = serde_json::to_value(&res).unwrap();
+        assert_eq!(json["result"]["balance"]["total"], "5");
+        assert!(json.get("error").is_none());
+
+        // Each response is exactly one line, and can be parsed back.
+        let mut buf = Vec::new();
+        emit(&mut buf, res).unwrap();
+        emit(&mut buf, Response::new(None, Err("no\nway".into()))).unwrap();
+        let lines = std::str::from_utf8(&buf)
+            .unwrap()
+            .lines()
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let res: Response = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(res.command, None);
+        assert_eq!(res.error.as_deref(), Some("no\nway"));
+        assert!(res.result.is_none());
+    }
+}
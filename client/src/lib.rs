@@ -2,7 +2,11 @@
 // This file is part of the Espresso library.
 
 pub mod cli_client;
+pub mod json_io;
 pub mod network;
+pub mod offline;
+pub mod options;
+pub mod rewards;
 #[cfg(any(test, feature = "testing"))]
 pub mod testing;
 
@@ -8,23 +8,29 @@
 // allows the user to enter commands for a keystore interactively.
 //
 
-mod cli_client;
-
 use async_trait::async_trait;
 use clap::Parser;
-use espresso_client::network::NetworkBackend;
+use espresso_client::{
+    json_io::{json_main, JsonKeystore},
+    network::NetworkBackend,
+    options::{err, read_secret, NetworkOptions},
+};
 use espresso_core::ledger::EspressoLedger;
 use jf_cap::proof::UniversalParam;
+use rand::SeedableRng;
+use rand_chacha::ChaChaRng;
 use seahorse::{
     cli::*,
+    hd::Mnemonic,
     io::SharedIO,
-    loader::{InteractiveLoader, MnemonicPasswordLogin},
+    loader::{InteractiveLoader, MnemonicPasswordLogin, RecoveryLoader},
     reader::Reader,
     KeystoreError,
 };
+use std::io::{stdin, stdout, BufReader};
 use std::path::PathBuf;
 use std::process::exit;
-use surf_disco::Url;
+use tempdir::TempDir;
 
 #[derive(Parser)]
 pub struct Args {
@@ -54,29 +60,29 @@ pub struct Args {
     /// followed by a newline, and the input will be read without an editor.
     pub non_interactive: bool,
 
-    /// URL for the Espresso Query Service.
-    #[arg(
-        long,
-        env = "ESPRESSO_ESQS_URL",
-        default_value = "http://localhost:50087"
-    )]
-    pub esqs_url: Url,
-
-    /// URL for the Espresso address book.
-    #[arg(
-        long,
-        env = "ESPRESSO_ADDRESS_BOOK_URL",
-        default_value = "http://localhost:50088"
-    )]
-    pub address_book_url: Url,
-
-    /// URL for a validator to submit transactions to.
-    #[arg(
-        long,
-        env = "ESPRESSO_SUBMIT_URL",
-        default_value = "http://localhost:50089"
-    )]
-    pub submit_url: Url,
+    /// Run in machine-readable mode.
+    ///
+    /// The keystore is opened without prompting, using the mnemonic and password given by
+    /// --mnemonic-file and --password-file. Each line of input is a command, and produces exactly
+    /// one line of output: a JSON object with the result of the command or its error. See the
+    /// `json_io` module for the available commands.
+    #[arg(long)]
+    pub json: bool,
+
+    /// File containing the mnemonic phrase of the keystore, in --json mode.
+    ///
+    /// If not given, the mnemonic is read from the environment variable `ESPRESSO_WALLET_MNEMONIC`.
+    #[arg(long, requires("json"))]
+    pub mnemonic_file: Option<PathBuf>,
+
+    /// File containing the password of the keystore, in --json mode.
+    ///
+    /// If not given, the password is read from the environment variable `ESPRESSO_WALLET_PASSWORD`.
+    #[arg(long, requires("json"))]
+    pub password_file: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub network: NetworkOptions,
 }
 
 impl CLIArgs for Args {
@@ -115,13 +121,17 @@ impl<'a> CLI<'a> for EspressoCli {
         univ_param: &'a UniversalParam,
         args: Self::Args,
     ) -> Result<Self::Backend, KeystoreError<EspressoLedger>> {
-        NetworkBackend::new(
+        let backend = NetworkBackend::new(
             univ_param,
-            args.esqs_url,
-            args.address_book_url,
-            args.submit_url,
+            args.network.esqs_urls,
+            args.network.address_book_url,
+            args.network.submit_url,
         )
-        .await
+        .await?;
+        Ok(match args.network.genesis_commitment {
+            Some(genesis) => backend.with_genesis(genesis),
+            None => backend,
+        })
     }
 
     async fn init_loader(
@@ -132,9 +142,47 @@ impl<'a> CLI<'a> for EspressoCli {
     }
 }
 
+/// Open the keystore without prompting and serve commands from stdin in JSON mode.
+async fn run_json(args: Args) -> Result<(), String> {
+    let mnemonic: Mnemonic =
+        read_secret(args.mnemonic_file.as_deref(), "ESPRESSO_WALLET_MNEMONIC")?
+            .parse()
+            .map_err(|err| format!("invalid mnemonic: {}", err))?;
+    let password = read_secret(args.password_file.as_deref(), "ESPRESSO_WALLET_PASSWORD")?;
+
+    // Keep the temporary directory, if any, alive until the keystore is closed.
+    let tmp_dir = if args.tmp_storage {
+        Some(TempDir::new("keystore").map_err(err)?)
+    } else {
+        None
+    };
+    let storage = match (&tmp_dir, args.storage) {
+        (Some(dir), _) => dir.path().to_path_buf(),
+        (None, Some(storage)) => storage,
+        (None, None) => dirs::home_dir()
+            .ok_or("no home directory; use --storage")?
+            .join(".translucence")
+            .join("keystore"),
+    };
+
+    let mut rng = ChaChaRng::from_entropy();
+    let mut loader = RecoveryLoader::new(&mut rng, storage, mnemonic, password);
+    let backend = args.network.backend().await.map_err(err)?;
+    let mut keystore: JsonKeystore = JsonKeystore::new(backend, &mut loader).await.map_err(err)?;
+    json_main(&mut keystore, BufReader::new(stdin()), stdout())
+        .await
+        .map_err(err)
+}
+
 #[async_std::main]
 async fn main() {
-    if let Err(err) = cli_main::<EspressoLedger, EspressoCli>(Args::parse()).await {
+    let args = Args::parse();
+    if args.json {
+        if let Err(err) = run_json(args).await {
+            println!("{}", serde_json::json!({ "command": null, "error": err }));
+            exit(1);
+        }
+    } else if let Err(err) = cli_main::<EspressoLedger, EspressoCli>(args).await {
         println!("{}", err);
         exit(1);
     }
@@ -1,25 +1,35 @@
 // Copyright (c) 2022 Espresso Systems (espressosys.com)
 // This file is part of the Espresso library.
 
+use crate::offline::{OfflineRecord, OfflineSnapshot, OfflineTransaction};
+use crate::rewards::{RewardHistory, RewardHistoryBuilder};
 use address_book::{error::AddressBookError, InsertPubKey};
 use async_std::sync::Arc;
+use async_std::task;
 use async_trait::async_trait;
-use espresso_availability_api::query_data::StateQueryData;
+use espresso_availability_api::query_data::{
+    BlockQueryData, BlockSummaryQueryData, QuorumCertificateQueryData, RangeQueryData,
+    RecordFrontierQueryData, RecordQueryData, StateQueryData, TransactionQueryData,
+};
 use espresso_core::{
     ledger::EspressoLedger,
     set_merkle_tree::{SetMerkleProof, SetMerkleTree},
-    state::ElaboratedTransaction,
-    universal_params::MERKLE_HEIGHT,
+    state::{
+        ConsensusTime, ElaboratedTransaction, EspressoTransaction, EspressoTxnHelperProofs,
+        LedgerStateCommitment, TransactionCommitment, ValidatorState,
+    },
+    universal_params::{circuit_keys, key_cache_path},
 };
 use espresso_esqs::ApiError;
+use espresso_light_client::LightClient;
 use espresso_metastate_api::api::NullifierCheck;
-use futures::future::ready;
 use futures::prelude::*;
+use futures::stream::{self, BoxStream};
 use jf_cap::keys::{UserAddress, UserKeyPair, UserPubKey};
-use jf_cap::proof::{freeze::FreezeProvingKey, transfer::TransferProvingKey, UniversalParam};
+use jf_cap::proof::UniversalParam;
 use jf_cap::structs::Nullifier;
-use jf_cap::MerkleTree;
-use key_set::{ProverKeySet, SizedKey};
+use jf_cap::{MerkleCommitment, MerkleLeafProof, MerkleTree};
+use key_set::SizedKey;
 use reef::Ledger;
 use seahorse::transactions::Transaction;
 use seahorse::{
@@ -30,52 +40,221 @@ use seahorse::{
 };
 use serde::{de::DeserializeOwned, Serialize};
 use snafu::ResultExt;
+use std::cmp::min;
+use std::fmt::Display;
+use std::ops::RangeInclusive;
 use std::pin::Pin;
-use std::time::Duration;
-use surf_disco::{Client, Url};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use surf_disco::{Client, Error as _, Url};
+
+/// The EsQS servers available to a [NetworkBackend], in order of preference.
+///
+/// All requests go to the current server. When a server fails, the backend fails over to the next
+/// one, wrapping around to the first after the last. Once [PREFERRED_RETRY_INTERVAL] has passed
+/// since failing over from the preferred server, requests go to the preferred server again.
+#[derive(Clone)]
+struct QueryServers {
+    servers: Arc<Vec<(Url, Client<ApiError>)>>,
+    current: Arc<AtomicUsize>,
+    /// When we last failed over from the preferred server, if we have not returned to it since.
+    left_preferred: Arc<Mutex<Option<Instant>>>,
+    retry_preferred: Duration,
+}
+
+impl QueryServers {
+    fn new(urls: Vec<Url>) -> Self {
+        Self {
+            servers: Arc::new(
+                urls.into_iter()
+                    .map(|url| (url.clone(), NetworkBackend::client(url)))
+                    .collect(),
+            ),
+            current: Default::default(),
+            left_preferred: Default::default(),
+            retry_preferred: PREFERRED_RETRY_INTERVAL,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.servers.len()
+    }
+
+    /// The index and client of the current server.
+    fn current(&self) -> (usize, &Client<ApiError>) {
+        self.restore_preferred();
+        let index = self.current.load(Ordering::SeqCst);
+        (index, &self.servers[index % self.servers.len()].1)
+    }
+
+    /// Switch to the next server after a failure of server `index`.
+    ///
+    /// If another task has already failed over from `index`, this has no effect, so that
+    /// concurrent failures do not skip servers.
+    fn fail_over(&self, index: usize) {
+        let failed = &self.servers[index % self.servers.len()].0;
+        if self
+            .current
+            .compare_exchange(index, index + 1, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            let next = (index + 1) % self.servers.len();
+            let mut left_preferred = self.left_preferred.lock().unwrap();
+            if next == 0 {
+                *left_preferred = None;
+            } else if index % self.servers.len() == 0 {
+                *left_preferred = Some(Instant::now());
+            }
+            tracing::warn!(
+                "EsQS {} failed, failing over to {}",
+                failed,
+                self.servers[next].0
+            );
+        }
+    }
+
+    /// Go back to the preferred server if it has been long enough since it failed.
+    fn restore_preferred(&self) {
+        let mut left_preferred = self.left_preferred.lock().unwrap();
+        if matches!(*left_preferred, Some(left) if left.elapsed() >= self.retry_preferred) {
+            // Advance to the next index of the preferred server, so that the index keeps
+            // increasing and concurrent failures of the old server are still ignored.
+            let len = self.servers.len();
+            let index = self.current.load(Ordering::SeqCst);
+            self.current
+                .store(index + (len - index % len) % len, Ordering::SeqCst);
+            *left_preferred = None;
+            tracing::info!("returning to preferred EsQS {}", self.servers[0].0);
+        }
+    }
+}
+
+/// Whether a failed EsQS request should be retried on another server.
+///
+/// Transport errors are reported with a server error status, like errors inside the server. Any
+/// other error is a response to the request itself, which another server would give too.
+fn should_fail_over(err: &ApiError) -> bool {
+    err.status().is_server_error()
+}
+
+/// The part of the chain which a [NetworkBackend] has verified.
+#[derive(Default)]
+struct VerifiedChain {
+    /// The commitment to the genesis state which all verified states descend from.
+    ///
+    /// If no genesis was trusted when the backend was created, this is set from the first EsQS
+    /// response, and every later response must agree with it.
+    genesis: Option<LedgerStateCommitment>,
+    /// A light client at the latest verified state, once a block after genesis is certified.
+    client: Option<LightClient>,
+    /// The latest verified state.
+    latest: Option<StateQueryData>,
+}
 
 pub struct NetworkBackend<'a> {
     univ_param: &'a UniversalParam,
-    query_client: Client<ApiError>,
+    query_servers: QueryServers,
     address_book_client: Client<AddressBookError>,
     validator_client: Client<ApiError>,
+    verified: Mutex<VerifiedChain>,
 }
 
 impl<'a> NetworkBackend<'a> {
+    /// Create a backend using the EsQS servers in `query_urls`, in order of preference.
     pub async fn new(
         univ_param: &'a UniversalParam,
-        query_url: Url,
+        query_urls: Vec<Url>,
         address_book_url: Url,
         validator_url: Url,
     ) -> Result<NetworkBackend<'a>, KeystoreError<EspressoLedger>> {
+        if query_urls.is_empty() {
+            return Err(KeystoreError::Failed {
+                msg: "at least one EsQS URL is required".to_string(),
+            });
+        }
         let backend = Self {
-            query_client: Self::client(query_url),
+            query_servers: QueryServers::new(query_urls),
             address_book_client: Self::client(address_book_url),
             validator_client: Self::client(validator_url),
             univ_param,
+            verified: Default::default(),
         };
         backend.wait_for_esqs().await?;
         Ok(backend)
     }
 
+    /// Only accept states descending from the genesis state with commitment `genesis`.
+    ///
+    /// Without a trusted genesis, the genesis state reported by the first EsQS to answer is trusted,
+    /// and all other servers must agree with it.
+    pub fn with_genesis(mut self, genesis: LedgerStateCommitment) -> Self {
+        self.verified.get_mut().unwrap().genesis = Some(genesis);
+        self
+    }
+
+    /// Make a GET request to the EsQS, failing over to each of the other servers in turn if it
+    /// fails with a transport or server error.
     async fn get<T: DeserializeOwned>(
         &self,
         uri: impl AsRef<str>,
     ) -> Result<T, KeystoreError<EspressoLedger>> {
-        self.query_client
-            .get(uri.as_ref())
-            .send()
-            .await
-            .map_err(|source| KeystoreError::Failed {
-                msg: format!("EsQS request GET {} failed: {}", uri.as_ref(), source),
-            })
+        let mut errors = vec![];
+        for _ in 0..self.query_servers.len() {
+            let (index, client) = self.query_servers.current();
+            match client.get(uri.as_ref()).send().await {
+                Ok(res) => return Ok(res),
+                Err(source) if should_fail_over(&source) => {
+                    errors.push(source.to_string());
+                    self.query_servers.fail_over(index);
+                }
+                Err(source) => {
+                    errors.push(source.to_string());
+                    break;
+                }
+            }
+        }
+        Err(KeystoreError::Failed {
+            msg: format!(
+                "EsQS request GET {} failed: {}",
+                uri.as_ref(),
+                errors.join(", ")
+            ),
+        })
+    }
+
+    /// Run `f`, failing over to each of the other EsQS servers in turn if it fails.
+    ///
+    /// This is used for requests whose responses are verified, so that a server which returns
+    /// invalid data is treated the same as one which is down.
+    async fn with_failover<'b, T, F, Fut>(
+        &'b self,
+        f: F,
+    ) -> Result<T, KeystoreError<EspressoLedger>>
+    where
+        F: Fn(&'b Self) -> Fut,
+        Fut: Future<Output = Result<T, KeystoreError<EspressoLedger>>>,
+    {
+        let mut attempts = self.query_servers.len();
+        loop {
+            let (index, _) = self.query_servers.current();
+            match f(self).await {
+                Ok(res) => return Ok(res),
+                Err(err) if attempts > 1 => {
+                    tracing::warn!("EsQS request failed: {}", err);
+                    self.query_servers.fail_over(index);
+                    attempts -= 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
     }
 
-    async fn post<T: Serialize, E: surf_disco::Error>(
+    async fn post<T: Serialize, R: DeserializeOwned, E: surf_disco::Error>(
         client: &Client<E>,
         uri: impl AsRef<str>,
         body: &T,
-    ) -> Result<(), KeystoreError<EspressoLedger>> {
+    ) -> Result<R, KeystoreError<EspressoLedger>> {
         client
             .post(uri.as_ref())
             .body_binary(body)
@@ -89,15 +268,528 @@ impl<'a> NetworkBackend<'a> {
             })
     }
 
+    /// Get a recent state from the EsQS, checking that it was decided by consensus.
+    ///
+    /// States are verified by a [LightClient] following the chain from the trusted genesis (see
+    /// [with_genesis](Self::with_genesis)). A state is accepted if it is decided by a leaf certified
+    /// by a quorum of the genesis stake table, and the commitment to that leaf is recomputed from
+    /// the state and from the certificate of the previous block, which the leaf carries, so the
+    /// server cannot pair valid votes with a different state. Since the certificate for a block is
+    /// carried by the next leaf, this is the state before the latest block.
+    ///
+    /// The latest verified state is cached, and returned without being fetched again until there
+    /// is a newer one to verify.
+    pub async fn get_verified_state(
+        &self,
+    ) -> Result<StateQueryData, KeystoreError<EspressoLedger>> {
+        let latest_block_id: u64 = self.get("status/latest_block_id").await?;
+        let block_id = latest_block_id.saturating_sub(1);
+        let (client, latest) = {
+            let verified = self.verified.lock().unwrap();
+            (verified.client.clone(), verified.latest.clone())
+        };
+        if let Some(latest) = latest {
+            if latest.block_id >= block_id {
+                return Ok(latest);
+            }
+        }
+
+        let snapshot: StateQueryData = self
+            .get(format!("availability/getstate/{}", block_id))
+            .await?;
+        if snapshot.block_id != block_id || snapshot.state.commit() != snapshot.commitment {
+            return Err(Self::invalid_response(format!(
+                "state {} does not match its commitment",
+                block_id
+            )));
+        }
+        if latest_block_id == 0 {
+            // There is no certificate yet, but the genesis state itself is trusted.
+            if snapshot.commitment != self.trusted_genesis(snapshot.commitment) {
+                return Err(Self::invalid_response(
+                    "state 0 does not match the trusted genesis",
+                ));
+            }
+            return Ok(snapshot);
+        }
+
+        let mut client = match client {
+            Some(client) => client,
+            None => self.genesis_light_client().await?,
+        };
+        if block_id == 0 {
+            if snapshot.commitment != client.commitment() {
+                return Err(Self::invalid_response(
+                    "state 0 does not match the trusted genesis",
+                ));
+            }
+        } else {
+            let qc: QuorumCertificateQueryData =
+                self.get(format!("availability/getqc/{}", block_id)).await?;
+            let justify: QuorumCertificateQueryData = self
+                .get(format!("availability/getqc/{}", block_id - 1))
+                .await?;
+            client
+                .skip_to(
+                    snapshot.state.clone(),
+                    &qc.certificate,
+                    &justify.certificate,
+                )
+                .map_err(|err| {
+                    Self::invalid_response(format!("state {} is not verified: {}", block_id, err))
+                })?;
+        }
+
+        let mut verified = self.verified.lock().unwrap();
+        // Another request may have verified a newer state in the meantime.
+        if !matches!(&verified.latest, Some(latest) if latest.block_id >= block_id) {
+            verified.client = Some(client);
+            verified.latest = Some(snapshot.clone());
+        }
+        Ok(snapshot)
+    }
+
+    /// The latest state verified by [get_verified_state](Self::get_verified_state), if any.
+    fn latest_verified_state(&self) -> Option<StateQueryData> {
+        self.verified.lock().unwrap().latest.clone()
+    }
+
+    /// Start a light client from the genesis block, which must produce the trusted genesis state.
+    async fn genesis_light_client(&self) -> Result<LightClient, KeystoreError<EspressoLedger>> {
+        let genesis: BlockQueryData = self.get("availability/getblock/0").await?;
+        let note = match &genesis.raw_block.block.0[..] {
+            [EspressoTransaction::Genesis(note)] => note.clone(),
+            _ => return Err(Self::invalid_response("block 0 is not a genesis block")),
+        };
+        let qc: QuorumCertificateQueryData = self.get("availability/getqc/0").await?;
+        let trusted = self.trusted_genesis(ValidatorState::genesis(note.clone()).commit());
+        LightClient::new(trusted, note, qc.certificate).map_err(Self::invalid_response)
+    }
+
+    /// The trusted genesis commitment.
+    ///
+    /// If none has been trusted yet, `reported` is trusted from now on.
+    fn trusted_genesis(&self, reported: LedgerStateCommitment) -> LedgerStateCommitment {
+        *self
+            .verified
+            .lock()
+            .unwrap()
+            .genesis
+            .get_or_insert_with(|| {
+                tracing::warn!(
+                    "no trusted genesis state was given, trusting {} from the EsQS",
+                    reported
+                );
+                reported
+            })
+    }
+
+    /// Get a proof of whether `nullifier` is spent, relative to the verified `state`.
+    ///
+    /// `state` should be a result of [get_verified_state](Self::get_verified_state). The proof is
+    /// only accepted if it checks against the nullifier set root of `state`, which is bound to a
+    /// quorum certificate.
+    async fn get_verified_nullifier_proof(
+        &self,
+        state: &StateQueryData,
+        nullifier: Nullifier,
+    ) -> Result<(bool, SetMerkleProof), KeystoreError<EspressoLedger>> {
+        let NullifierCheck { proof, spent } = self
+            .get(format!(
+                "/metastate/check_nullifier/{}/{}",
+                state.block_id, nullifier
+            ))
+            .await?;
+        if proof.check(nullifier, &state.state.nullifiers_root()) != Ok(spent) {
+            return Err(Self::invalid_response(format!(
+                "invalid proof for nullifier {}",
+                nullifier
+            )));
+        }
+        Ok((spent, proof))
+    }
+
+    /// Fetch a snapshot of the ledger for building a transaction offline.
+    ///
+    /// The snapshot includes a verified state and, for each of `uids`, the owner memo of the record
+    /// with that UID and a proof that it is in the snapshot state.
+    pub async fn get_offline_snapshot(
+        &self,
+        uids: &[u64],
+    ) -> Result<OfflineSnapshot, KeystoreError<EspressoLedger>> {
+        let state = self
+            .with_failover(|backend| backend.get_verified_state())
+            .await?;
+        let root = state.state.record_merkle_commitment.root_value;
+        let mut records = vec![];
+        for &uid in uids {
+            let record: RecordQueryData = self
+                .get(format!("availability/getrecord/uid/{}", uid))
+                .await?;
+            if record.block_id > state.block_id {
+                return Err(KeystoreError::Failed {
+                    msg: format!(
+                        "record {} was created after the latest verified block {}",
+                        uid, state.block_id
+                    ),
+                });
+            }
+            let txn: TransactionQueryData = self
+                .get(format!(
+                    "availability/gettransaction/{}/{}",
+                    record.block_id, record.txn_id
+                ))
+                .await?;
+            let output = record.output_index as usize;
+            if txn.raw_transaction.txn.output_commitments().get(output) != Some(&record.commitment)
+            {
+                return Err(Self::invalid_response(format!(
+                    "record {} is not an output of its transaction",
+                    uid
+                )));
+            }
+            let memo = txn
+                .raw_transaction
+                .memos
+                .and_then(|(memos, _)| memos.get(output).cloned())
+                .ok_or_else(|| KeystoreError::Failed {
+                    msg: format!("record {} has no owner memo", uid),
+                })?;
+            let proof: MerkleLeafProof = self
+                .get(format!(
+                    "availability/getrecordproof/{}/{}",
+                    uid, state.block_id
+                ))
+                .await?;
+            if proof.leaf.0 != record.commitment.to_field_element()
+                || MerkleTree::check_proof(root, uid, &proof).is_err()
+            {
+                return Err(Self::invalid_response(format!(
+                    "invalid proof for record {}",
+                    uid
+                )));
+            }
+            records.push(OfflineRecord {
+                uid,
+                commitment: record.commitment,
+                memo,
+                proof,
+            });
+        }
+        Ok(OfflineSnapshot { state, records })
+    }
+
+    /// Submit a transaction built offline.
+    ///
+    /// Proofs that the inputs of the transaction are unspent are added relative to `state`, which
+    /// should be a recent result of [get_verified_state](Self::get_verified_state).
+    pub async fn submit_offline(
+        &self,
+        state: &StateQueryData,
+        txn: OfflineTransaction,
+    ) -> Result<TransactionCommitment, KeystoreError<EspressoLedger>> {
+        let mut txn = txn.txn;
+        let mut proofs = vec![];
+        for nullifier in txn.txn.input_nullifiers() {
+            let (spent, proof) = self
+                .with_failover(|backend| backend.get_verified_nullifier_proof(state, nullifier))
+                .await?;
+            if spent {
+                return Err(KeystoreError::Failed {
+                    msg: format!("input {} has already been spent", nullifier),
+                });
+            }
+            proofs.push(proof);
+        }
+        txn.proofs = EspressoTxnHelperProofs::CAP(proofs);
+        self.submit_transaction(&txn).await
+    }
+
+    /// Submit a fully proven transaction directly to the validator.
+    pub async fn submit_transaction(
+        &self,
+        txn: &ElaboratedTransaction,
+    ) -> Result<TransactionCommitment, KeystoreError<EspressoLedger>> {
+        Self::post(&self.validator_client, "/validator/submit", txn).await
+    }
+
+    /// Reconstruct the history needed to collect staking rewards in the views in `view_range`.
+    ///
+    /// The historical stake tables are resumed from the frontier of the state just before the first
+    /// block in range, so only the views from that block on are fetched. The collected rewards are
+    /// still rebuilt from every block since genesis, one page at a time. Both are checked against
+    /// the commitments in a verified state (see [get_verified_state](Self::get_verified_state)).
+    pub async fn get_reward_history(
+        &self,
+        view_range: RangeInclusive<u64>,
+    ) -> Result<RewardHistory, KeystoreError<EspressoLedger>> {
+        self.with_failover(|backend| {
+            backend.get_reward_history_from_current_server(view_range.clone())
+        })
+        .await
+    }
+
+    async fn get_reward_history_from_current_server(
+        &self,
+        view_range: RangeInclusive<u64>,
+    ) -> Result<RewardHistory, KeystoreError<EspressoLedger>> {
+        let state = self.get_verified_state().await?;
+        let first = self
+            .first_block_in_view_range(state.block_id, *view_range.start())
+            .await?;
+        let builder = if first == 0 {
+            RewardHistoryBuilder::new(state, view_range)
+        } else {
+            let prev: StateQueryData = self
+                .get(format!("availability/getstate/{}", first - 1))
+                .await?;
+            RewardHistoryBuilder::resume(state, view_range, &prev)
+                .map_err(Self::invalid_response)?
+        };
+        let num_blocks = builder.expected_blocks();
+
+        let builder = self
+            .fold_range(
+                "getblocks",
+                0,
+                num_blocks,
+                builder,
+                |mut builder, block: BlockQueryData| {
+                    builder
+                        .add_block(&block.raw_block.block)
+                        .map_err(Self::invalid_response)?;
+                    Ok(builder)
+                },
+            )
+            .await?;
+
+        // Every block after genesis is applied at the view of the leaf proposing it, which is the
+        // view of the certificate deciding it, reported in its summary.
+        let builder = self
+            .fold_range(
+                "getblocksummaries",
+                first.max(1),
+                num_blocks,
+                builder,
+                |mut builder, summary: BlockSummaryQueryData| {
+                    builder.add_view(ConsensusTime::new(Self::summary_view(&summary)?));
+                    Ok(builder)
+                },
+            )
+            .await?;
+
+        builder.finish().map_err(Self::invalid_response)
+    }
+
+    /// The first block whose view is at least `start`, or `block_id + 1` if there is none up to
+    /// `block_id`.
+    ///
+    /// View numbers increase with block height, so this is a binary search over the block
+    /// summaries. The result is not trusted: resuming the history from the block before it checks
+    /// that block's view, and the history is checked against the verified state.
+    async fn first_block_in_view_range(
+        &self,
+        block_id: u64,
+        start: u64,
+    ) -> Result<u64, KeystoreError<EspressoLedger>> {
+        // The genesis block is at view 0.
+        if start == 0 {
+            return Ok(0);
+        }
+        let (mut lo, mut hi) = (1, block_id + 1);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let summaries: Vec<BlockSummaryQueryData> = self
+                .get(format!("availability/getblocksummary/{}/1", mid))
+                .await?;
+            let summary = match &summaries[..] {
+                [summary] if summary.block_id == mid => summary,
+                _ => {
+                    return Err(Self::invalid_response(format!(
+                        "getblocksummary/{}/1 did not return the summary of block {}",
+                        mid, mid
+                    )))
+                }
+            };
+            if Self::summary_view(summary)? >= start {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+        Ok(lo)
+    }
+
+    /// The view of a block which has been decided, from its summary.
+    fn summary_view(summary: &BlockSummaryQueryData) -> Result<u64, KeystoreError<EspressoLedger>> {
+        summary.view_number.ok_or_else(|| {
+            Self::invalid_response(format!("block {} has no view number", summary.block_id))
+        })
+    }
+
+    /// Fold `f` over all the results of a paginated availability query for the range
+    /// `[from, until)`, one page at a time.
+    async fn fold_range<T: DeserializeOwned, A>(
+        &self,
+        route: &str,
+        from: u64,
+        until: u64,
+        mut acc: A,
+        mut f: impl FnMut(A, T) -> Result<A, KeystoreError<EspressoLedger>>,
+    ) -> Result<A, KeystoreError<EspressoLedger>> {
+        let mut next = from;
+        while next < until {
+            let page: RangeQueryData<T> = self
+                .get(format!("availability/{}/{}/{}", route, next, until))
+                .await?;
+            let num_items = page.items.len() as u64;
+            if num_items > until - next {
+                break;
+            }
+            for item in page.items {
+                acc = f(acc, item)?;
+            }
+            next += num_items;
+            match page.next {
+                Some(page_next) if page_next == next => {}
+                _ => break,
+            }
+        }
+        if next != until {
+            return Err(Self::invalid_response(format!(
+                "{}/{}/{} returned {} results",
+                route,
+                from,
+                until,
+                next - from
+            )));
+        }
+        Ok(acc)
+    }
+
+    /// The record Merkle tree from which to scan for records starting at EsQS event `from`.
+    ///
+    /// The scan starts from the end of the last block before `from`, so that we only replay the
+    /// events of at most one block before the requested point. The record frontier of that block
+    /// is only used if its commitment is part of the verified chain; if that block is newer than
+    /// the latest verified state, the scan starts from the verified state instead.
+    async fn get_initial_scan_state_from_current_server(
+        &self,
+        from: usize,
+    ) -> Result<(MerkleTree, EventIndex), KeystoreError<EspressoLedger>> {
+        let frontier: Option<RecordFrontierQueryData> = self
+            .get(format!("availability/getfrontier/event/{}", from))
+            .await?;
+        let frontier = match frontier {
+            Some(frontier) => frontier,
+            // `from` is within the genesis block, so we have to scan from the beginning.
+            None => {
+                return Ok((
+                    MerkleTree::new(EspressoLedger::merkle_height()).unwrap(),
+                    Default::default(),
+                ))
+            }
+        };
+
+        let verified = self.get_verified_state().await?;
+        let frontier = if frontier.block_id > verified.block_id {
+            RecordFrontierQueryData::from(verified)
+        } else {
+            self.verify_record_commitment(
+                &verified,
+                frontier.block_id,
+                frontier.record_merkle_commitment,
+            )
+            .await?;
+            frontier
+        };
+        Ok((
+            MerkleTree::restore_from_frontier(
+                frontier.record_merkle_commitment,
+                &frontier.record_merkle_frontier,
+            )
+            .ok_or_else(|| {
+                Self::invalid_response(format!(
+                    "record frontier for block {} does not match its commitment",
+                    frontier.block_id
+                ))
+            })?,
+            EventIndex::from_source(
+                EventSource::QueryService,
+                frontier.continuation_event_index as usize,
+            ),
+        ))
+    }
+
+    /// Check that `commitment` is the record Merkle commitment after block `block_id` in the chain
+    /// leading to the `verified` state.
+    ///
+    /// Each state remembers the record Merkle roots of the last
+    /// [HISTORY_SIZE](ValidatorState::HISTORY_SIZE) blocks. Older roots are found by following the
+    /// chain of states back from `verified`, checking each against the `prev_state` commitment of
+    /// its successor.
+    async fn verify_record_commitment(
+        &self,
+        verified: &StateQueryData,
+        block_id: u64,
+        commitment: MerkleCommitment,
+    ) -> Result<(), KeystoreError<EspressoLedger>> {
+        let mut state = verified.state.clone();
+        let mut state_id = verified.block_id;
+        let valid = loop {
+            if state_id == block_id {
+                break state.record_merkle_commitment == commitment;
+            }
+            // The root after block `block_id` is remembered `state_id - block_id - 1` blocks back.
+            if let Some(root) = state
+                .past_record_merkle_roots
+                .0
+                .get((state_id - block_id - 1) as usize)
+            {
+                break *root == commitment.root_value
+                    && commitment.height == state.record_merkle_commitment.height;
+            }
+            let parent: StateQueryData = self
+                .get(format!("availability/getstate/{}", state_id - 1))
+                .await?;
+            if state.prev_state != Some(parent.state.commit()) {
+                return Err(Self::invalid_response(format!(
+                    "state {} does not follow state {}",
+                    state_id,
+                    state_id - 1
+                )));
+            }
+            state = parent.state;
+            state_id -= 1;
+        };
+        if !valid {
+            return Err(Self::invalid_response(format!(
+                "record frontier for block {} is not in the verified chain",
+                block_id
+            )));
+        }
+        Ok(())
+    }
+
+    fn invalid_response(msg: impl Display) -> KeystoreError<EspressoLedger> {
+        let msg = format!("invalid response from EsQS: {}", msg);
+        tracing::error!("{}", msg);
+        KeystoreError::Failed { msg }
+    }
+
     async fn wait_for_esqs(&self) -> Result<(), KeystoreError<EspressoLedger>> {
         let timeout = Duration::from_secs(300);
-        if self.query_client.connect(Some(timeout)).await {
-            Ok(())
-        } else {
-            let msg = format!("failed to connect to EQS after {:?}", timeout);
-            tracing::error!("{}", msg);
-            Err(KeystoreError::Failed { msg })
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            let (index, client) = self.query_servers.current();
+            if client.connect(Some(CONNECT_TIMEOUT)).await {
+                return Ok(());
+            }
+            self.query_servers.fail_over(index);
         }
+        let msg = format!("failed to connect to EQS after {:?}", timeout);
+        tracing::error!("{}", msg);
+        Err(KeystoreError::Failed { msg })
     }
 
     fn client<E: surf_disco::Error>(url: Url) -> Client<E> {
@@ -115,55 +807,31 @@ impl<'a> KeystoreBackend<'a, EspressoLedger> for NetworkBackend<'a> {
     async fn create(
         &mut self,
     ) -> Result<LedgerState<'a, EspressoLedger>, KeystoreError<EspressoLedger>> {
-        let block_id: u64 = self.get("status/latest_block_id").await?;
-        let snapshot: StateQueryData = self
-            .get(format!("availability/getstate/{}", block_id))
+        let snapshot = self
+            .with_failover(|backend| backend.get_verified_state())
             .await?;
 
-        // Construct proving keys of the same arities as the verifier keys from the validator.
-        let univ_param = self.univ_param;
-        let proving_keys = Arc::new(ProverKeySet {
-            mint: jf_cap::proof::mint::preprocess(univ_param, MERKLE_HEIGHT)
-                .context(CryptoSnafu)?
-                .0,
-            freeze: snapshot
-                .state
-                .chain
-                .verif_crs
-                .freeze
-                .iter()
-                .map(|k| {
-                    Ok::<FreezeProvingKey, KeystoreError<EspressoLedger>>(
-                        jf_cap::proof::freeze::preprocess(
-                            univ_param,
-                            k.num_inputs(),
-                            MERKLE_HEIGHT,
-                        )
-                        .context(CryptoSnafu)?
-                        .0,
-                    )
-                })
-                .collect::<Result<_, _>>()?,
-            xfr: snapshot
-                .state
-                .chain
-                .verif_crs
-                .xfr
-                .iter()
-                .map(|k| {
-                    Ok::<TransferProvingKey, KeystoreError<EspressoLedger>>(
-                        jf_cap::proof::transfer::preprocess(
-                            univ_param,
-                            k.num_inputs(),
-                            k.num_outputs(),
-                            MERKLE_HEIGHT,
-                        )
-                        .context(CryptoSnafu)?
-                        .0,
-                    )
-                })
-                .collect::<Result<_, _>>()?,
-        });
+        // Construct proving keys of the same arities as the verifier keys from the validator. These
+        // are expensive to generate, so we load them from the key cache if possible.
+        let verif_crs = &snapshot.state.chain.verif_crs;
+        let xfr_sizes = verif_crs
+            .xfr
+            .iter()
+            .map(|k| (k.num_inputs(), k.num_outputs()))
+            .collect::<Vec<_>>();
+        let freeze_sizes = verif_crs
+            .freeze
+            .iter()
+            .map(|k| k.num_inputs())
+            .collect::<Vec<_>>();
+        let (proving_keys, _) = circuit_keys(
+            self.univ_param,
+            &xfr_sizes,
+            &freeze_sizes,
+            Some(&key_cache_path()),
+        )
+        .context(CryptoSnafu)?;
+        let proving_keys = Arc::new(proving_keys);
 
         let state = LedgerState::new(
             proving_keys,
@@ -189,31 +857,14 @@ impl<'a> KeystoreBackend<'a, EspressoLedger> for NetworkBackend<'a> {
         // All events come from a single source, the EsQS, which aggregates blocks and memos.
         let from = from.index(EventSource::QueryService);
         let to = to.map(|to| to.index(EventSource::QueryService));
-
-        //todo !jeb.bearer handle connection failures.
-        //      https://github.com/EspressoSystems/seahorse/issues/117
-        // This should only fail if the server is incorrect or down, so we should handle by retrying
-        // or failing over to a different server.
-        let all_events = self
-            .query_client
-            .socket(&format!("catchup/subscribe_for_events/{}", from))
-            .subscribe()
-            .await
-            .expect("failed to connect to server");
-        let chosen_events: Pin<Box<dyn Stream<Item = _> + Send>> = if let Some(to) = to {
-            Box::pin(all_events.take(to - from))
-        } else {
-            Box::pin(all_events)
+        let subscription = EventSubscription {
+            servers: self.query_servers.clone(),
+            next: from,
+            to,
+            events: None,
+            backoff: MIN_BACKOFF,
         };
-
-        Box::pin(
-            chosen_events
-                //todo !jeb.bearer handle stream errors
-                //      https://github.com/EspressoSystems/seahorse/issues/117
-                // If there is an error in the stream, or the server sends us invalid data, we
-                // should retry or fail over to a different server.
-                .filter_map(|msg| ready(msg.ok().map(|e| (e, EventSource::QueryService)))),
-        )
+        Box::pin(stream::unfold(subscription, EventSubscription::next_event).boxed())
     }
 
     async fn get_public_key(
@@ -240,27 +891,32 @@ impl<'a> KeystoreBackend<'a, EspressoLedger> for NetworkBackend<'a> {
         set: &mut SetMerkleTree,
         nullifier: Nullifier,
     ) -> Result<(bool, SetMerkleProof), KeystoreError<EspressoLedger>> {
-        if let Some(ret) = set.contains(nullifier) {
-            Ok(ret)
-        } else {
-            let (spent, proof) = if block_height == 0 {
-                // The nullifier set at block height 0 (i.e. before the genesis block) is always the
-                // default, empty set.
-                assert_eq!(*set, SetMerkleTree::default());
-                set.contains(nullifier).unwrap()
-            } else {
-                let NullifierCheck { proof, spent } = self
-                    .get(format!(
-                        "/metastate/check_nullifier/{}/{}",
-                        block_height - 1,
-                        nullifier
-                    ))
-                    .await?;
-                (spent, proof)
-            };
+        if block_height == 0 {
+            // The nullifier set at block height 0 (i.e. before the genesis block) is always the
+            // default, empty set.
+            assert_eq!(*set, SetMerkleTree::default());
+            return Ok(set.contains(nullifier).unwrap());
+        }
+
+        // The keystore's nullifier set is maintained from the event stream, which is not verified,
+        // so neither its cached proofs nor its root can be trusted. Instead, the proof must check
+        // against the root of a verified state. The cached state will do if it is at least as new
+        // as the keystore's view of the ledger.
+        let state = match self.latest_verified_state() {
+            Some(state) if state.state.block_height >= block_height => state,
+            _ => {
+                self.with_failover(|backend| backend.get_verified_state())
+                    .await?
+            }
+        };
+        let (spent, proof) = self
+            .with_failover(|backend| backend.get_verified_nullifier_proof(&state, nullifier))
+            .await?;
+        // Keep the proof in the keystore's set if it is also valid there.
+        if proof.check(nullifier, &set.hash()) == Ok(spent) {
             set.remember(nullifier, proof.clone()).unwrap();
-            Ok((spent, proof))
         }
+        Ok((spent, proof))
     }
 
     async fn register_user_key(
@@ -293,7 +949,9 @@ impl<'a> KeystoreBackend<'a, EspressoLedger> for NetworkBackend<'a> {
             ));
         }
 
-        Self::post(&self.validator_client, "/validator/submit", &txn).await
+        let _hash: TransactionCommitment =
+            Self::post(&self.validator_client, "/validator/submit", &txn).await?;
+        Ok(())
     }
 
     async fn finalize(&mut self, _txn: Transaction<EspressoLedger>, _txid: Option<(u64, u64)>) {
@@ -302,11 +960,165 @@ impl<'a> KeystoreBackend<'a, EspressoLedger> for NetworkBackend<'a> {
 
     async fn get_initial_scan_state(
         &self,
-        _from: EventIndex,
+        from: EventIndex,
     ) -> Result<(MerkleTree, EventIndex), KeystoreError<EspressoLedger>> {
-        Ok((
-            MerkleTree::new(EspressoLedger::merkle_height()).unwrap(),
-            Default::default(),
-        ))
+        let from = from.index(EventSource::QueryService);
+        self.with_failover(|backend| backend.get_initial_scan_state_from_current_server(from))
+            .await
+    }
+}
+
+/// How long to wait for an EsQS server to respond before failing over to the next one.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait after failing over from the preferred EsQS server before trying it again.
+const PREFERRED_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The initial delay before reconnecting to the event stream after a failure.
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The maximum delay before reconnecting to the event stream after a failure.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// A subscription to the EsQS event stream which survives server failures.
+///
+/// If the connection fails, the stream reports an error or disconnects, or the server sends an
+/// event which does not deserialize, the subscription fails over to the next server and resumes
+/// from the first event it has not yet delivered. Each retry waits with exponential backoff, which
+/// is reset once an event is delivered.
+struct EventSubscription {
+    servers: QueryServers,
+    next: usize,
+    to: Option<usize>,
+    events: Option<BoxStream<'static, Result<LedgerEvent<EspressoLedger>, ApiError>>>,
+    backoff: Duration,
+}
+
+impl EventSubscription {
+    async fn next_event(mut self) -> Option<((LedgerEvent<EspressoLedger>, EventSource), Self)> {
+        loop {
+            if Some(self.next) == self.to {
+                return None;
+            }
+            let (index, client) = self.servers.current();
+            let event = match &mut self.events {
+                Some(events) => events.next().await,
+                None => {
+                    match client
+                        .socket(&format!("catchup/subscribe_for_events/{}", self.next))
+                        .subscribe()
+                        .await
+                    {
+                        Ok(events) => {
+                            self.events = Some(events.boxed());
+                        }
+                        Err(err) => {
+                            tracing::warn!("failed to subscribe to events: {}", err);
+                            self.servers.fail_over(index);
+                            self.back_off().await;
+                        }
+                    }
+                    continue;
+                }
+            };
+            match event {
+                Some(Ok(event)) => {
+                    self.next += 1;
+                    self.backoff = MIN_BACKOFF;
+                    return Some(((event, EventSource::QueryService), self));
+                }
+                Some(Err(err)) => {
+                    tracing::warn!("error in event stream at event {}: {}", self.next, err);
+                }
+                None => {
+                    tracing::warn!("event stream disconnected at event {}", self.next);
+                }
+            }
+            self.events = None;
+            self.servers.fail_over(index);
+            self.back_off().await;
+        }
+    }
+
+    /// Wait before retrying, and increase the wait for the next retry.
+    async fn back_off(&mut self) {
+        task::sleep(self.backoff).await;
+        self.backoff = next_backoff(self.backoff);
+    }
+}
+
+fn next_backoff(backoff: Duration) -> Duration {
+    min(backoff * 2, MAX_BACKOFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use surf_disco::StatusCode;
+
+    fn servers(retry_preferred: Duration) -> QueryServers {
+        QueryServers {
+            retry_preferred,
+            ..QueryServers::new(vec![
+                "http://localhost:1".parse().unwrap(),
+                "http://localhost:2".parse().unwrap(),
+                "http://localhost:3".parse().unwrap(),
+            ])
+        }
+    }
+
+    #[test]
+    fn test_fail_over() {
+        let servers = servers(Duration::from_secs(3600));
+        assert_eq!(servers.current().0, 0);
+
+        // Concurrent failures of the same server only move to the next server once.
+        servers.fail_over(0);
+        servers.fail_over(0);
+        assert_eq!(servers.current().0, 1);
+
+        // Failing over past the last server wraps around to the preferred one.
+        servers.fail_over(1);
+        servers.fail_over(2);
+        assert_eq!(servers.current().0 % servers.len(), 0);
+        assert!(servers.left_preferred.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_return_to_preferred() {
+        let servers = servers(Duration::ZERO);
+        servers.fail_over(0);
+        servers.fail_over(1);
+
+        // Once the retry interval has passed, requests go to the preferred server again, and a late
+        // failure of the server we left is ignored.
+        let (index, _) = servers.current();
+        assert_eq!(index % servers.len(), 0);
+        servers.fail_over(2);
+        assert_eq!(servers.current().0, index);
+    }
+
+    #[test]
+    fn test_should_fail_over() {
+        let err = |status| ApiError::Internal {
+            status,
+            reason: String::new(),
+        };
+        assert!(should_fail_over(&err(StatusCode::InternalServerError)));
+        assert!(should_fail_over(&err(StatusCode::ServiceUnavailable)));
+        assert!(!should_fail_over(&err(StatusCode::NotFound)));
+        assert!(!should_fail_over(&err(StatusCode::BadRequest)));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = MIN_BACKOFF;
+        for _ in 0..20 {
+            let next = next_backoff(backoff);
+            assert!(next >= backoff);
+            assert!(next <= MAX_BACKOFF);
+            backoff = next;
+        }
+        assert_eq!(backoff, MAX_BACKOFF);
     }
 }
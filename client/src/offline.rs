@@ -0,0 +1,430 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Espresso library.
+
+//! Building transactions on a machine without network access.
+//!
+//! Keys which control large amounts of funds can be kept on an air-gapped machine. Spending from
+//! them takes three steps:
+//!
+//! 1. An online machine fetches an [OfflineSnapshot] from the EsQS
+//!    ([NetworkBackend::get_offline_snapshot](crate::network::NetworkBackend::get_offline_snapshot)).
+//!    The snapshot contains a verified ledger state, plus the encrypted owner memos and Merkle
+//!    proofs of the records to spend, identified by their UIDs. It contains no secrets.
+//! 2. The offline machine decrypts the records with its keys and builds and proves a transaction
+//!    against the snapshot ([build_transfer]), producing an [OfflineTransaction].
+//! 3. An online machine adds nullifier proofs for the current ledger state and submits the
+//!    transaction
+//!    ([NetworkBackend::submit_offline](crate::network::NetworkBackend::submit_offline)).
+//!
+//! A transaction proves that its inputs are in the record Merkle tree with a particular root, and
+//! validators only accept roots from the last
+//! [HISTORY_SIZE](espresso_core::state::ValidatorState::HISTORY_SIZE) blocks. Therefore, the
+//! transaction must be submitted soon after the snapshot is taken. An [OfflineTransaction] records
+//! the root it was built against, so the online side can check it before submitting.
+
+use espresso_availability_api::query_data::StateQueryData;
+use espresso_core::{
+    state::{ElaboratedTransaction, EspressoTransaction, EspressoTxnHelperProofs, ValidatorState},
+    universal_params::SUPPORTED_TRANSFER_SIZES,
+};
+use jf_cap::{
+    errors::TxnApiError,
+    keys::{UserKeyPair, UserPubKey},
+    sign_receiver_memos,
+    structs::{Amount, AssetDefinition, FreezeFlag, ReceiverMemo, RecordCommitment, RecordOpening},
+    transfer::{TransferNote, TransferNoteInput},
+    AccMemberWitness, MerkleLeafProof, MerkleTree, NodeValue, TransactionNote,
+};
+use key_set::ProverKeySet;
+use rand::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use tagged_base64::TaggedBase64;
+
+/// The tag used when exporting an [OfflineTransaction] as TaggedBase64.
+pub const OFFLINE_TRANSACTION_TAG: &str = "OFFLINETXN";
+
+/// The most native records a single offline transfer can spend.
+///
+/// This is the largest number of inputs of any of the
+/// [SUPPORTED_TRANSFER_SIZES](espresso_core::universal_params::SUPPORTED_TRANSFER_SIZES). A
+/// transfer needs at least two outputs, one for the receiver and one for the change; if the
+/// circuit for its number of inputs has more, the rest are padded with empty records.
+pub const MAX_OFFLINE_INPUTS: usize = 3;
+
+#[derive(Debug, Snafu)]
+pub enum OfflineError {
+    #[snafu(display("record {} cannot be opened with this key", uid))]
+    UnownedRecord { uid: u64 },
+
+    #[snafu(display("proof for record {} is not valid in the snapshot state", uid))]
+    InvalidRecordProof { uid: u64 },
+
+    #[snafu(display("insufficient balance: {} available, {} needed", available, needed))]
+    InsufficientBalance { available: u128, needed: u128 },
+
+    #[snafu(display(
+        "transfer needs {} input records, but at most {} are supported",
+        needed,
+        MAX_OFFLINE_INPUTS
+    ))]
+    TooManyInputs { needed: usize },
+
+    #[snafu(display("failed to build transaction: {}", source))]
+    BuildTransaction { source: TxnApiError },
+
+    #[snafu(display("invalid transaction file: {}", msg))]
+    InvalidFile { msg: String },
+}
+
+/// A record to spend offline, without its secret opening.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OfflineRecord {
+    pub uid: u64,
+    pub commitment: RecordCommitment,
+    /// The owner memo of the record, which the owner can decrypt to get the record opening.
+    pub memo: ReceiverMemo,
+    /// Proof that the record is in the snapshot state.
+    pub proof: MerkleLeafProof,
+}
+
+/// Everything needed to build a transaction offline.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OfflineSnapshot {
+    pub state: StateQueryData,
+    pub records: Vec<OfflineRecord>,
+}
+
+impl OfflineSnapshot {
+    /// Open the records in this snapshot which belong to `key_pair`.
+    ///
+    /// Fails if any record does not belong to `key_pair`, or if its proof is not valid relative to
+    /// the snapshot state.
+    pub fn open_records(
+        &self,
+        key_pair: &UserKeyPair,
+    ) -> Result<Vec<(RecordOpening, &OfflineRecord)>, OfflineError> {
+        let root = self.state.state.record_merkle_commitment.root_value;
+        self.records
+            .iter()
+            .map(|record| {
+                let ro = record
+                    .memo
+                    .decrypt(key_pair, &record.commitment, &[])
+                    .ok()
+                    .filter(|ro| RecordCommitment::from(ro) == record.commitment)
+                    .ok_or(OfflineError::UnownedRecord { uid: record.uid })?;
+                if record.proof.leaf.0 != record.commitment.to_field_element()
+                    || MerkleTree::check_proof(root, record.uid, &record.proof).is_err()
+                {
+                    return Err(OfflineError::InvalidRecordProof { uid: record.uid });
+                }
+                Ok((ro, record))
+            })
+            .collect()
+    }
+}
+
+/// A transaction built offline, ready to be submitted.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OfflineTransaction {
+    /// The block after which the snapshot this transaction was built against was taken.
+    pub block_id: u64,
+    /// The record Merkle root this transaction was built against.
+    pub merkle_root: NodeValue,
+    /// The transaction, without nullifier proofs.
+    ///
+    /// Proofs that the inputs are unspent depend on the nullifier set at the time the transaction
+    /// is submitted, so they are added by the online machine.
+    pub txn: ElaboratedTransaction,
+}
+
+impl OfflineTransaction {
+    /// Whether validators in `state` will still accept a transaction built against
+    /// [merkle_root](Self::merkle_root).
+    pub fn is_root_recent(&self, state: &ValidatorState) -> bool {
+        state.record_merkle_commitment.root_value == self.merkle_root
+            || state.past_record_merkle_roots.0.contains(&self.merkle_root)
+    }
+
+    pub fn to_bincode(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    pub fn to_tagged_base64(&self) -> String {
+        TaggedBase64::new(OFFLINE_TRANSACTION_TAG, &self.to_bincode())
+            .unwrap()
+            .to_string()
+    }
+
+    /// Parse a transaction exported with either [to_bincode](Self::to_bincode) or
+    /// [to_tagged_base64](Self::to_tagged_base64).
+    pub fn from_file_contents(bytes: &[u8]) -> Result<Self, OfflineError> {
+        let bytes = match std::str::from_utf8(bytes)
+            .ok()
+            .and_then(|s| TaggedBase64::parse(s.trim()).ok())
+        {
+            Some(tb64) if tb64.tag() == OFFLINE_TRANSACTION_TAG => tb64.value(),
+            Some(tb64) => {
+                return Err(OfflineError::InvalidFile {
+                    msg: format!("unexpected tag {}", tb64.tag()),
+                })
+            }
+            None => bytes.to_vec(),
+        };
+        bincode::deserialize(&bytes).map_err(|err| OfflineError::InvalidFile {
+            msg: err.to_string(),
+        })
+    }
+}
+
+/// Build a transfer of native assets from the records in `snapshot` owned by `key_pair`.
+///
+/// The largest records are spent first. Change, and any outputs needed to pad the transaction to a
+/// supported size, are returned to `key_pair`.
+pub fn build_transfer(
+    rng: &mut (impl RngCore + CryptoRng),
+    snapshot: &OfflineSnapshot,
+    key_pair: &UserKeyPair,
+    receiver: UserPubKey,
+    amount: Amount,
+    fee: Amount,
+    proving_keys: &ProverKeySet,
+) -> Result<OfflineTransaction, OfflineError> {
+    let mut records = snapshot
+        .open_records(key_pair)?
+        .into_iter()
+        .filter(|(ro, _)| {
+            ro.asset_def == AssetDefinition::native() && ro.freeze_flag == FreezeFlag::Unfrozen
+        })
+        .collect::<Vec<_>>();
+    records.sort_by_key(|(ro, _)| std::cmp::Reverse(u128::from(ro.amount)));
+
+    // Select inputs to cover the amount and the fee.
+    let needed = u128::from(amount) + u128::from(fee);
+    let mut total = 0;
+    let mut inputs = vec![];
+    for (ro, record) in &records {
+        if total >= needed {
+            break;
+        }
+        total += u128::from(ro.amount);
+        inputs.push((ro.clone(), *record));
+    }
+    if total < needed {
+        return Err(OfflineError::InsufficientBalance {
+            available: total,
+            needed,
+        });
+    }
+    if inputs.len() > MAX_OFFLINE_INPUTS {
+        return Err(OfflineError::TooManyInputs {
+            needed: inputs.len(),
+        });
+    }
+
+    // The note has a fee change output in addition to the outputs we give it, so pick the smallest
+    // circuit for this many inputs with room for the receiver output and the change.
+    let num_inputs = inputs.len();
+    let num_outputs = SUPPORTED_TRANSFER_SIZES
+        .iter()
+        .filter(|(i, o)| *i == num_inputs && *o >= 2)
+        .map(|(_, o)| *o)
+        .min()
+        .ok_or(OfflineError::TooManyInputs { needed: num_inputs })?;
+
+    let merkle_root = snapshot.state.state.record_merkle_commitment.root_value;
+    let inputs = inputs
+        .into_iter()
+        .map(|(ro, record)| TransferNoteInput {
+            ro,
+            owner_keypair: key_pair,
+            cred: None,
+            acc_member_witness: AccMemberWitness {
+                merkle_path: record.proof.path.clone(),
+                root: merkle_root,
+                uid: record.uid,
+            },
+        })
+        .collect();
+    let mut outputs = vec![RecordOpening::new(
+        rng,
+        amount,
+        AssetDefinition::native(),
+        receiver,
+        FreezeFlag::Unfrozen,
+    )];
+    while outputs.len() + 1 < num_outputs {
+        outputs.push(RecordOpening::new(
+            rng,
+            Amount::from(0u64),
+            AssetDefinition::native(),
+            key_pair.pub_key(),
+            FreezeFlag::Unfrozen,
+        ));
+    }
+    // The change, including the change from the fee, goes to the fee change output, which belongs
+    // to the owner of the first input.
+    let (note, memo_key_pair, change) = TransferNote::generate_native(
+        rng,
+        inputs,
+        &outputs,
+        fee,
+        2u64.pow(jf_cap::constants::MAX_TIMESTAMP_LEN as u32) - 1,
+        proving_keys
+            .xfr
+            .key_for_size(num_inputs, num_outputs)
+            .ok_or(OfflineError::TooManyInputs { needed: num_inputs })?,
+    )
+    .context(BuildTransactionSnafu)?;
+
+    let memos = std::iter::once(&change)
+        .chain(&outputs)
+        .map(|ro| ReceiverMemo::from_ro(rng, ro, &[]))
+        .collect::<Result<Vec<_>, _>>()
+        .context(BuildTransactionSnafu)?;
+    let sig = sign_receiver_memos(&memo_key_pair, &memos).context(BuildTransactionSnafu)?;
+
+    Ok(OfflineTransaction {
+        block_id: snapshot.state.block_id,
+        merkle_root,
+        txn: ElaboratedTransaction {
+            txn: EspressoTransaction::CAP(TransactionNote::Transfer(Box::new(note))),
+            proofs: EspressoTxnHelperProofs::CAP(vec![]),
+            memos: Some((memos, sig)),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use espresso_core::universal_params::{MERKLE_HEIGHT, PROVER_CRS};
+    use rand::SeedableRng;
+    use rand_chacha::ChaChaRng;
+
+    /// A snapshot containing native records of the given amounts, owned by `key_pair`.
+    fn snapshot(rng: &mut ChaChaRng, key_pair: &UserKeyPair, amounts: &[u64]) -> OfflineSnapshot {
+        let ros = amounts
+            .iter()
+            .map(|amount| {
+                RecordOpening::new(
+                    rng,
+                    Amount::from(*amount),
+                    AssetDefinition::native(),
+                    key_pair.pub_key(),
+                    FreezeFlag::Unfrozen,
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut tree = MerkleTree::new(MERKLE_HEIGHT).unwrap();
+        for ro in &ros {
+            tree.push(RecordCommitment::from(ro).to_field_element());
+        }
+        let records = ros
+            .iter()
+            .enumerate()
+            .map(|(uid, ro)| OfflineRecord {
+                uid: uid as u64,
+                commitment: RecordCommitment::from(ro),
+                memo: ReceiverMemo::from_ro(rng, ro, &[]).unwrap(),
+                proof: tree.get_leaf(uid as u64).expect_ok().unwrap().1,
+            })
+            .collect();
+        let mut state = ValidatorState::default();
+        state.record_merkle_commitment = tree.commitment();
+        OfflineSnapshot {
+            state: StateQueryData {
+                commitment: state.commit(),
+                state,
+                block_id: 5,
+                continuation_event_index: 0,
+            },
+            records,
+        }
+    }
+
+    #[test]
+    fn test_max_inputs() {
+        assert_eq!(
+            SUPPORTED_TRANSFER_SIZES.iter().map(|(i, _)| *i).max(),
+            Some(MAX_OFFLINE_INPUTS)
+        );
+    }
+
+    #[test]
+    fn test_build_export_parse() {
+        let mut rng = ChaChaRng::from_seed([42; 32]);
+        let key_pair = UserKeyPair::generate(&mut rng);
+        let receiver = UserKeyPair::generate(&mut rng).pub_key();
+        let snapshot = snapshot(&mut rng, &key_pair, &[10, 10, 10, 10]);
+
+        // Spending 1, 2 and 3 records uses the (1, 2), (2, 2) and (3, 3) circuits.
+        for (amount, num_inputs, num_outputs) in [(5, 1, 2), (15, 2, 2), (25, 3, 3)] {
+            let txn = build_transfer(
+                &mut rng,
+                &snapshot,
+                &key_pair,
+                receiver.clone(),
+                Amount::from(amount as u64),
+                Amount::from(1u64),
+                &PROVER_CRS,
+            )
+            .unwrap();
+            assert_eq!(txn.block_id, 5);
+            assert_eq!(
+                txn.merkle_root,
+                snapshot.state.state.record_merkle_commitment.root_value
+            );
+            assert!(txn.is_root_recent(&snapshot.state.state));
+            match &txn.txn.txn {
+                EspressoTransaction::CAP(TransactionNote::Transfer(note)) => {
+                    assert_eq!(note.inputs_nullifiers.len(), num_inputs);
+                    assert_eq!(note.output_commitments.len(), num_outputs);
+                }
+                txn => panic!("expected a transfer, got {:?}", txn),
+            }
+            assert_eq!(txn.txn.memos.as_ref().unwrap().0.len(), num_outputs);
+
+            // Both export formats parse back to the same transaction.
+            for contents in [txn.to_tagged_base64().into_bytes(), txn.to_bincode()] {
+                let parsed = OfflineTransaction::from_file_contents(&contents).unwrap();
+                assert_eq!(parsed.to_bincode(), txn.to_bincode());
+            }
+        }
+
+        // Spending all 4 records is not supported.
+        assert!(matches!(
+            build_transfer(
+                &mut rng,
+                &snapshot,
+                &key_pair,
+                receiver.clone(),
+                Amount::from(35u64),
+                Amount::from(1u64),
+                &PROVER_CRS,
+            ),
+            Err(OfflineError::TooManyInputs { needed: 4 })
+        ));
+        assert!(matches!(
+            build_transfer(
+                &mut rng,
+                &snapshot,
+                &key_pair,
+                receiver,
+                Amount::from(40u64),
+                Amount::from(1u64),
+                &PROVER_CRS,
+            ),
+            Err(OfflineError::InsufficientBalance {
+                available: 40,
+                needed: 41
+            })
+        ));
+
+        // Files with the wrong tag, or which are not transactions, are rejected.
+        let other = TaggedBase64::new("TXN", &[0; 8]).unwrap().to_string();
+        assert!(OfflineTransaction::from_file_contents(other.as_bytes()).is_err());
+        assert!(OfflineTransaction::from_file_contents(b"garbage").is_err());
+    }
+}
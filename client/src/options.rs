@@ -0,0 +1,135 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Espresso library.
+
+//! Command line options and helpers shared by the client binaries.
+
+use crate::{network::NetworkBackend, EspressoKeystoreError};
+use clap::Args;
+use espresso_core::{state::LedgerStateCommitment, universal_params::UNIVERSAL_PARAM};
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+use surf_disco::Url;
+
+/// Options for connecting to the Espresso services.
+#[derive(Clone, Debug, Args)]
+pub struct NetworkOptions {
+    /// URLs for the Espresso Query Service.
+    ///
+    /// Multiple servers can be given, separated by commas or by repeating the option. They are used
+    /// in order of preference: if a server fails, the client fails over to the next one.
+    #[arg(
+        long = "esqs-url",
+        env = "ESPRESSO_ESQS_URL",
+        value_delimiter = ',',
+        default_value = "http://localhost:50087"
+    )]
+    pub esqs_urls: Vec<Url>,
+
+    /// URL for the Espresso address book.
+    #[arg(
+        long,
+        env = "ESPRESSO_ADDRESS_BOOK_URL",
+        default_value = "http://localhost:50088"
+    )]
+    pub address_book_url: Url,
+
+    /// URL for a validator to submit transactions to.
+    #[arg(
+        long,
+        env = "ESPRESSO_SUBMIT_URL",
+        default_value = "http://localhost:50089"
+    )]
+    pub submit_url: Url,
+
+    /// Commitment to the genesis state of the chain to follow.
+    ///
+    /// States from the query service are only trusted if they descend from this genesis. If it is
+    /// not given, the genesis state reported by the first query service to answer is trusted.
+    #[arg(long, env = "ESPRESSO_GENESIS_COMMITMENT")]
+    pub genesis_commitment: Option<LedgerStateCommitment>,
+}
+
+impl NetworkOptions {
+    pub async fn backend(self) -> Result<NetworkBackend<'static>, EspressoKeystoreError> {
+        let backend = NetworkBackend::new(
+            &UNIVERSAL_PARAM,
+            self.esqs_urls,
+            self.address_book_url,
+            self.submit_url,
+        )
+        .await?;
+        Ok(match self.genesis_commitment {
+            Some(genesis) => backend.with_genesis(genesis),
+            None => backend,
+        })
+    }
+}
+
+/// Convert an error to a message for the user.
+pub fn err(err: impl Display) -> String {
+    err.to_string()
+}
+
+/// Read a secret from `file`, or from the environment variable `var` if no file is given.
+///
+/// Secrets are never taken from the command line, where other users of the machine could see them.
+pub fn read_secret(file: Option<&Path>, var: &str) -> Result<String, String> {
+    let secret = match file {
+        Some(path) => fs::read_to_string(path)
+            .map_err(|err| format!("failed to read {}: {}", path.display(), err))?,
+        None => {
+            env::var(var).map_err(|_| format!("{} or a file containing it is required", var))?
+        }
+    };
+    let secret = secret.trim();
+    if secret.is_empty() {
+        return Err(format!("{} is empty", var));
+    }
+    Ok(secret.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Options {
+        #[command(flatten)]
+        network: NetworkOptions,
+    }
+
+    #[test]
+    fn test_network_options() {
+        let opt = Options::try_parse_from([
+            "test",
+            "--esqs-url",
+            "http://esqs1:1,http://esqs2:2",
+            "--esqs-url",
+            "http://esqs3:3",
+        ])
+        .unwrap();
+        assert_eq!(
+            opt.network
+                .esqs_urls
+                .iter()
+                .map(Url::as_str)
+                .collect::<Vec<_>>(),
+            ["http://esqs1:1/", "http://esqs2:2/", "http://esqs3:3/"]
+        );
+    }
+
+    #[test]
+    fn test_read_secret() {
+        let dir = tempdir::TempDir::new("read_secret").unwrap();
+        let path = dir.path().join("secret");
+        fs::write(&path, "secret\n").unwrap();
+        assert_eq!(read_secret(Some(&path), "UNUSED").unwrap(), "secret");
+
+        fs::write(&path, "  \n").unwrap();
+        assert!(read_secret(Some(&path), "UNUSED").is_err());
+        assert!(read_secret(Some(&dir.path().join("missing")), "UNUSED").is_err());
+    }
+}
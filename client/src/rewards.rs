@@ -0,0 +1,697 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Espresso library.
+
+//! Collecting staking rewards from outside of a validator.
+//!
+//! A staking key is eligible for a reward in each view in which it is selected for the committee.
+//! To claim a reward, the owner of the key submits a [CollectRewardNote] proving its eligibility,
+//! along with proofs that the stake table for that view included the key and that the reward has
+//! not been collected yet.
+//!
+//! A validator can only build these proofs for recent views from its own state. A [RewardHistory]
+//! instead reconstructs the history of stake tables and collected rewards from the EsQS
+//! ([NetworkBackend::get_reward_history](crate::network::NetworkBackend::get_reward_history)), so
+//! rewards can be claimed from any machine holding the staking key, for any view since genesis.
+//!
+//! The history is built one block at a time by a [RewardHistoryBuilder], which only keeps in memory
+//! the proofs for the range of views the caller is interested in. The historical stake tables can be
+//! resumed from the frontier of a state just before that range, but the collected rewards cannot be
+//! restored from a frontier, so every block since genesis is still needed to rebuild them.
+
+use espresso_availability_api::query_data::StateQueryData;
+use espresso_core::{
+    merkle_tree::MerkleFrontier,
+    reward::{
+        eligibility, CollectRewardNote, CollectedRewards, CollectedRewardsSet, EligibilityWitness,
+        RewardError,
+    },
+    stake_table::StakeTableCommitment,
+    stake_table::{StakeTableMap, StakeTableSetMT, StakingKey, StakingPrivKey},
+    state::{
+        amount_to_nonzerou64, Block, ConsensusTime, ElaboratedTransaction, EspressoTransaction,
+        EspressoTxnHelperProofs,
+    },
+    universal_params::MERKLE_HEIGHT,
+};
+use jf_cap::keys::UserPubKey;
+use rand::{CryptoRng, RngCore};
+use snafu::Snafu;
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Snafu)]
+pub enum RewardHistoryError {
+    #[snafu(display("block 0 is not a genesis block"))]
+    MissingGenesis,
+
+    #[snafu(display("expected {} blocks, got {}", expected, actual))]
+    WrongBlockCount { expected: u64, actual: u64 },
+
+    #[snafu(display("expected {} views, got {}", expected, actual))]
+    WrongViewCount { expected: u64, actual: u64 },
+
+    #[snafu(display("state does not commit to the genesis stake table"))]
+    StakeTableMismatch,
+
+    #[snafu(display("collected rewards do not match the verified state"))]
+    CollectedRewardsMismatch,
+
+    #[snafu(display("historical stake tables do not match the verified state"))]
+    StakeTableHistoryMismatch,
+
+    #[snafu(display(
+        "cannot resume the history after block {} at view {}, which is not before the requested range",
+        block_id,
+        view
+    ))]
+    ResumeInRange { block_id: u64, view: u64 },
+}
+
+/// The history of the ledger needed to claim rewards for past views.
+#[derive(Clone, Debug)]
+pub struct RewardHistory {
+    /// The verified state in which rewards will be claimed.
+    pub state: StateQueryData,
+    pub stake_table: StakeTableMap,
+    /// All historical stake tables, with a leaf for each block.
+    ///
+    /// Only the leaves for blocks in [views](Self::views) are kept in memory.
+    pub stake_table_history: StakeTableSetMT,
+    /// All rewards collected up to and including [state](Self::state).
+    pub collected_rewards: CollectedRewardsSet,
+    /// The view number of each block whose view is in the requested range, by block ID.
+    pub views: BTreeMap<u64, ConsensusTime>,
+}
+
+/// A reward which a staking key is eligible for and has not yet collected.
+#[derive(Clone, Debug)]
+pub struct EligibleReward {
+    pub block_id: u64,
+    pub view: ConsensusTime,
+    pub witness: EligibilityWitness,
+}
+
+impl RewardHistory {
+    /// Find uncollected rewards for `key` in the views covered by this history.
+    pub fn eligible_rewards(&self, key: &StakingPrivKey) -> Vec<EligibleReward> {
+        let staking_key = StakingKey::from(key);
+        // Eligibility in each past view depends on the stake at that view. This uses the current
+        // stake table for every view, which relies on the stake table never changing after genesis:
+        // [RewardHistoryBuilder::finish] checks that every historical stake table leaf commits to
+        // the same stake table as the verified state.
+        let stake = match self.stake_table.lookup(staking_key.clone()) {
+            Some((Some(stake), _)) => stake,
+            _ => return vec![],
+        };
+        let chain = &self.state.state.chain;
+        self.views
+            .iter()
+            .map(|(block_id, view)| (*block_id, *view))
+            .filter(|(_, view)| {
+                let claim = CollectedRewards {
+                    staking_key: staking_key.clone(),
+                    time: *view,
+                };
+                !matches!(self.collected_rewards.lookup(claim), Some((Some(()), _)))
+            })
+            .filter_map(|(block_id, view)| {
+                let witness = eligibility::prove_eligibility(
+                    chain.committee_size,
+                    chain.vrf_seed,
+                    view,
+                    key,
+                    stake,
+                    amount_to_nonzerou64(self.state.state.total_stake),
+                )?;
+                Some(EligibleReward {
+                    block_id,
+                    view,
+                    witness,
+                })
+            })
+            .collect()
+    }
+
+    /// Build a transaction collecting `reward` for `key`, paid to `receiver`.
+    pub fn collect_reward(
+        &self,
+        rng: &mut (impl RngCore + CryptoRng),
+        key: &StakingPrivKey,
+        receiver: UserPubKey,
+        reward: EligibleReward,
+    ) -> Result<ElaboratedTransaction, RewardError> {
+        let staking_key = StakingKey::from(key);
+        let (_, stake_proof) = self
+            .stake_table
+            .lookup(staking_key.clone())
+            .ok_or(RewardError::StakingKeyNotFound {})?;
+        let uncollected_proof = match self.collected_rewards.lookup(CollectedRewards {
+            staking_key,
+            time: reward.view,
+        }) {
+            Some((None, proof)) => proof,
+            Some((Some(()), _)) => return Err(RewardError::RewardAlreadyCollected {}),
+            None => return Err(RewardError::ProofNotInMemory {}),
+        };
+
+        // The stake table for the view of block `b` is the leaf after the genesis leaf, at index
+        // `b + 1`. `CollectRewardNote::generate` proves the last leaf of a frontier, so pass it a
+        // frontier ending at that leaf.
+        let leaf = reward.block_id + 1;
+        let (_, leaf_proof) = self
+            .stake_table_history
+            .get_leaf(leaf)
+            .expect_ok()
+            .map_err(|_| RewardError::ProofNotInMemory {})?;
+
+        let (note, proofs) = CollectRewardNote::generate(
+            rng,
+            &MerkleFrontier::Proof(leaf_proof),
+            leaf + 1,
+            self.state.state.chain.committee_size,
+            self.state.state.block_height,
+            key,
+            receiver,
+            stake_proof,
+            uncollected_proof,
+            reward.witness,
+        )?;
+        Ok(ElaboratedTransaction {
+            txn: EspressoTransaction::Reward(Box::new(note)),
+            proofs: EspressoTxnHelperProofs::Reward(Box::new(proofs)),
+            memos: None,
+        })
+    }
+}
+
+/// Builds a [RewardHistory] from the blocks and views of the ledger, in order.
+pub struct RewardHistoryBuilder {
+    state: StateQueryData,
+    view_range: RangeInclusive<u64>,
+    num_blocks: u64,
+    stake_table: Option<StakeTableMap>,
+    collected_rewards: CollectedRewardsSet,
+    stake_table_history: StakeTableSetMT,
+    num_views: u64,
+    views: BTreeMap<u64, ConsensusTime>,
+}
+
+impl RewardHistoryBuilder {
+    /// Start building the history leading to the verified `state`, for the views in `view_range`.
+    pub fn new(state: StateQueryData, view_range: RangeInclusive<u64>) -> Self {
+        // The historical stake table set has a leaf for the genesis stake table, followed by a leaf
+        // for each block.
+        let mut stake_table_history = StakeTableSetMT::new(MERKLE_HEIGHT).unwrap();
+        stake_table_history.push((
+            state.state.stake_table_root,
+            state.state.total_stake,
+            ConsensusTime::genesis(),
+        ));
+        let mut builder = Self {
+            state,
+            view_range,
+            num_blocks: 0,
+            stake_table: None,
+            collected_rewards: Default::default(),
+            stake_table_history,
+            num_views: 0,
+            views: Default::default(),
+        };
+        // The genesis block is applied at the genesis view.
+        builder.add_view(ConsensusTime::genesis());
+        builder
+    }
+
+    /// Resume building the history leading to the verified `state` from an earlier state `prev`.
+    ///
+    /// The historical stake tables are restored from the frontier in `prev`, so only the views of
+    /// the blocks after `prev` need to be added. All of the blocks are still needed to rebuild the
+    /// collected rewards. `prev` does not need to be verified, since [finish](Self::finish) checks
+    /// the restored history against `state`, but the last block in `prev` must come before
+    /// `view_range`, so that no block in range is skipped.
+    pub fn resume(
+        state: StateQueryData,
+        view_range: RangeInclusive<u64>,
+        prev: &StateQueryData,
+    ) -> Result<Self, RewardHistoryError> {
+        let frontier = &prev.state.historical_stake_tables;
+        let stake_table_history = StakeTableSetMT::restore_from_frontier(
+            prev.state.historical_stake_tables_commitment,
+            frontier,
+        )
+        .ok_or(RewardHistoryError::StakeTableHistoryMismatch)?;
+        let view = match frontier {
+            MerkleFrontier::Proof(proof) => proof.leaf.0 .2,
+            MerkleFrontier::Empty { .. } => {
+                return Err(RewardHistoryError::StakeTableHistoryMismatch)
+            }
+        };
+        if *view >= *view_range.start() {
+            return Err(RewardHistoryError::ResumeInRange {
+                block_id: prev.block_id,
+                view: *view,
+            });
+        }
+        Ok(Self {
+            state,
+            view_range,
+            num_blocks: 0,
+            stake_table: None,
+            collected_rewards: Default::default(),
+            stake_table_history,
+            num_views: prev.block_id + 1,
+            views: Default::default(),
+        })
+    }
+
+    /// The number of blocks in the history, including the genesis block.
+    pub fn expected_blocks(&self) -> u64 {
+        self.state.block_id + 1
+    }
+
+    /// Add the next block.
+    pub fn add_block(&mut self, block: &Block) -> Result<(), RewardHistoryError> {
+        if self.num_blocks == 0 {
+            let note = match &block.0[..] {
+                [EspressoTransaction::Genesis(note)] => note,
+                _ => return Err(RewardHistoryError::MissingGenesis),
+            };
+            let mut stake_table = StakeTableMap::default();
+            for (key, amount) in note.stake_table.iter() {
+                stake_table.insert(key.clone(), *amount).unwrap();
+            }
+            self.stake_table = Some(stake_table);
+        }
+        for txn in &block.0 {
+            if let EspressoTransaction::Reward(note) = txn {
+                self.collected_rewards
+                    .insert(
+                        CollectedRewards {
+                            staking_key: note.staking_key(),
+                            time: note.time(),
+                        },
+                        (),
+                    )
+                    .unwrap();
+            }
+        }
+        self.num_blocks += 1;
+        Ok(())
+    }
+
+    /// Add the view of the next block after the genesis block.
+    pub fn add_view(&mut self, view: ConsensusTime) {
+        // Each block has a leaf in the historical stake table set. Once it is no longer the last
+        // leaf, forget the previous one unless its view is in range.
+        let block_id = self.num_views;
+        self.stake_table_history.push((
+            self.state.state.stake_table_root,
+            self.state.state.total_stake,
+            view,
+        ));
+        let prev_leaf = block_id;
+        if prev_leaf == 0 || !self.views.contains_key(&(prev_leaf - 1)) {
+            let _ = self.stake_table_history.forget(prev_leaf);
+        }
+        if self.view_range.contains(&*view) {
+            self.views.insert(block_id, view);
+        }
+        self.num_views += 1;
+    }
+
+    /// Check the history against the verified state.
+    pub fn finish(self) -> Result<RewardHistory, RewardHistoryError> {
+        let expected = self.expected_blocks();
+        if self.num_blocks != expected {
+            return Err(RewardHistoryError::WrongBlockCount {
+                expected,
+                actual: self.num_blocks,
+            });
+        }
+        if self.num_views != expected {
+            return Err(RewardHistoryError::WrongViewCount {
+                expected,
+                actual: self.num_views,
+            });
+        }
+        let stake_table = self.stake_table.ok_or(RewardHistoryError::MissingGenesis)?;
+        if StakeTableCommitment(stake_table.hash()) != self.state.state.stake_table_root {
+            return Err(RewardHistoryError::StakeTableMismatch);
+        }
+        if self.collected_rewards.hash() != self.state.state.collected_rewards.current_root() {
+            return Err(RewardHistoryError::CollectedRewardsMismatch);
+        }
+        if self.stake_table_history.commitment()
+            != self.state.state.historical_stake_tables_commitment
+        {
+            return Err(RewardHistoryError::StakeTableHistoryMismatch);
+        }
+        Ok(RewardHistory {
+            state: self.state,
+            stake_table,
+            stake_table_history: self.stake_table_history,
+            collected_rewards: self.collected_rewards,
+            views: self.views,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use commit::Committable;
+    use espresso_core::{
+        genesis::GenesisNote,
+        state::{ChainVariables, ValidatorState},
+        universal_params::VERIF_CRS,
+    };
+    use jf_cap::{keys::UserKeyPair, structs::Amount, MerkleTree};
+    use rand::SeedableRng;
+    use rand_chacha::ChaChaRng;
+    use std::sync::Arc;
+
+    /// The stake of the only validator, which is also the committee size, so that the validator
+    /// is selected for the committee in every view.
+    const STAKE: u64 = 10;
+
+    struct Ledger {
+        rng: ChaChaRng,
+        key: StakingPrivKey,
+        chain: ChainVariables,
+        stake_table_root: StakeTableCommitment,
+        state: StateQueryData,
+        blocks: Vec<Block>,
+        /// The view of each block after the genesis block.
+        views: Vec<ConsensusTime>,
+    }
+
+    impl Ledger {
+        /// A ledger with a genesis block followed by `num_blocks` empty blocks, in views 10, 20,
+        /// etc.
+        fn new(num_blocks: u64) -> Self {
+            let mut rng = ChaChaRng::from_seed([7; 32]);
+            let (staking_key, key) = StakingKey::generate(&mut rng);
+            let chain = ChainVariables::new(0, VERIF_CRS.clone(), STAKE);
+            let genesis = GenesisNote::new(
+                chain.clone(),
+                Arc::new(vec![]),
+                std::iter::once((staking_key.clone(), Amount::from(STAKE))).collect(),
+            );
+            let mut stake_table = StakeTableMap::default();
+            stake_table
+                .insert(staking_key, Amount::from(STAKE))
+                .unwrap();
+            let stake_table_root = StakeTableCommitment(stake_table.hash());
+
+            let views = (1..=num_blocks)
+                .map(|i| ConsensusTime::new(10 * i))
+                .collect::<Vec<_>>();
+            let mut blocks = vec![Block(vec![EspressoTransaction::Genesis(genesis)])];
+            blocks.extend((0..num_blocks).map(|_| Block::default()));
+            let state = Self::build_state(&chain, stake_table_root, &views);
+            Self {
+                rng,
+                key,
+                chain,
+                stake_table_root,
+                state,
+                blocks,
+                views,
+            }
+        }
+
+        /// The state after block `block_id`.
+        fn state_after(&self, block_id: u64) -> StateQueryData {
+            Self::build_state(
+                &self.chain,
+                self.stake_table_root,
+                &self.views[..block_id as usize],
+            )
+        }
+
+        /// The state after the blocks applied in `views`, with a historical stake table for each.
+        fn build_state(
+            chain: &ChainVariables,
+            stake_table_root: StakeTableCommitment,
+            views: &[ConsensusTime],
+        ) -> StateQueryData {
+            let mut stake_table_history = StakeTableSetMT::new(MERKLE_HEIGHT).unwrap();
+            for view in [ConsensusTime::genesis(), ConsensusTime::genesis()]
+                .iter()
+                .chain(views)
+            {
+                stake_table_history.push((stake_table_root, Amount::from(STAKE), *view));
+            }
+            let state = ValidatorState::new(
+                chain.clone(),
+                MerkleTree::new(MERKLE_HEIGHT).unwrap(),
+                stake_table_root,
+                Amount::from(STAKE),
+                stake_table_history,
+            );
+            StateQueryData {
+                commitment: state.commit(),
+                state,
+                block_id: views.len() as u64,
+                continuation_event_index: 0,
+            }
+        }
+
+        fn history(
+            &self,
+            view_range: RangeInclusive<u64>,
+        ) -> Result<RewardHistory, RewardHistoryError> {
+            let builder = RewardHistoryBuilder::new(self.state.clone(), view_range);
+            self.finish(builder, &self.views)
+        }
+
+        /// Build the history resuming from the state after block `prev`.
+        fn resumed_history(
+            &self,
+            view_range: RangeInclusive<u64>,
+            prev: u64,
+        ) -> Result<RewardHistory, RewardHistoryError> {
+            let builder = RewardHistoryBuilder::resume(
+                self.state.clone(),
+                view_range,
+                &self.state_after(prev),
+            )?;
+            self.finish(builder, &self.views[prev as usize..])
+        }
+
+        fn finish(
+            &self,
+            mut builder: RewardHistoryBuilder,
+            views: &[ConsensusTime],
+        ) -> Result<RewardHistory, RewardHistoryError> {
+            for block in &self.blocks {
+                builder.add_block(block)?;
+            }
+            for view in views {
+                builder.add_view(*view);
+            }
+            builder.finish()
+        }
+    }
+
+    #[test]
+    fn test_reward_history() {
+        let mut ledger = Ledger::new(5);
+        let history = ledger.history(15..=35).unwrap();
+
+        // Only the blocks in the requested views are kept, along with their stake table proofs.
+        assert_eq!(
+            history.views.iter().collect::<Vec<_>>(),
+            [(&2, &ConsensusTime::new(20)), (&3, &ConsensusTime::new(30))]
+        );
+        for leaf in [3, 4] {
+            history
+                .stake_table_history
+                .get_leaf(leaf)
+                .expect_ok()
+                .unwrap();
+        }
+        for leaf in [0, 1, 2, 5] {
+            assert!(history
+                .stake_table_history
+                .get_leaf(leaf)
+                .expect_ok()
+                .is_err());
+        }
+
+        // The only validator is eligible in every view.
+        let rewards = history.eligible_rewards(&ledger.key);
+        assert_eq!(
+            rewards
+                .iter()
+                .map(|reward| (reward.block_id, *reward.view))
+                .collect::<Vec<_>>(),
+            [(2, 20), (3, 30)]
+        );
+
+        // A key which is not in the stake table is not eligible.
+        let (_, other_key) = StakingKey::generate(&mut ledger.rng);
+        assert!(history.eligible_rewards(&other_key).is_empty());
+
+        // Rewards can be claimed for views in range.
+        let receiver = UserKeyPair::generate(&mut ledger.rng).pub_key();
+        let txn = history
+            .collect_reward(
+                &mut ledger.rng,
+                &ledger.key,
+                receiver.clone(),
+                rewards[0].clone(),
+            )
+            .unwrap();
+        assert!(matches!(txn.txn, EspressoTransaction::Reward(_)));
+
+        // Once a reward is collected, it is no longer eligible, and cannot be claimed again.
+        let mut history = history;
+        history
+            .collected_rewards
+            .insert(
+                CollectedRewards {
+                    staking_key: StakingKey::from(&ledger.key),
+                    time: rewards[0].view,
+                },
+                (),
+            )
+            .unwrap();
+        assert_eq!(
+            history
+                .eligible_rewards(&ledger.key)
+                .iter()
+                .map(|reward| reward.block_id)
+                .collect::<Vec<_>>(),
+            [3]
+        );
+        assert!(matches!(
+            history.collect_reward(&mut ledger.rng, &ledger.key, receiver, rewards[0].clone()),
+            Err(RewardError::RewardAlreadyCollected {})
+        ));
+    }
+
+    #[test]
+    fn test_resumed_reward_history() {
+        let ledger = Ledger::new(5);
+
+        // Resuming just before the range gives the same history as building it from genesis.
+        let history = ledger.resumed_history(25..=45, 2).unwrap();
+        let full = ledger.history(25..=45).unwrap();
+        assert_eq!(history.views, full.views);
+        assert_eq!(
+            history.stake_table_history.commitment(),
+            full.stake_table_history.commitment()
+        );
+        for leaf in [4, 5] {
+            history
+                .stake_table_history
+                .get_leaf(leaf)
+                .expect_ok()
+                .unwrap();
+        }
+        assert_eq!(
+            history
+                .eligible_rewards(&ledger.key)
+                .iter()
+                .map(|reward| (reward.block_id, *reward.view))
+                .collect::<Vec<_>>(),
+            [(3, 30), (4, 40)]
+        );
+
+        // A range with no blocks in it yet can resume from the latest state.
+        let history = ledger.resumed_history(55..=65, 5).unwrap();
+        assert!(history.views.is_empty());
+
+        // Resuming from a state in the range could skip blocks in range.
+        assert!(matches!(
+            ledger.resumed_history(25..=45, 3),
+            Err(RewardHistoryError::ResumeInRange {
+                block_id: 3,
+                view: 30
+            })
+        ));
+
+        // A view after the resumed state which does not match the historical stake tables.
+        let mut views = ledger.views.clone();
+        views[3] = ConsensusTime::new(45);
+        let builder =
+            RewardHistoryBuilder::resume(ledger.state.clone(), 25..=45, &ledger.state_after(2))
+                .unwrap();
+        assert!(matches!(
+            ledger.finish(builder, &views[2..]),
+            Err(RewardHistoryError::StakeTableHistoryMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_reward_history_checks() {
+        let ledger = Ledger::new(3);
+        ledger.history(0..=u64::MAX).unwrap();
+
+        // Missing blocks or views.
+        let mut bad = Ledger::new(3);
+        bad.blocks.pop();
+        assert!(matches!(
+            bad.history(0..=u64::MAX),
+            Err(RewardHistoryError::WrongBlockCount {
+                expected: 4,
+                actual: 3
+            })
+        ));
+        let mut bad = Ledger::new(3);
+        bad.views.pop();
+        assert!(matches!(
+            bad.history(0..=u64::MAX),
+            Err(RewardHistoryError::WrongViewCount {
+                expected: 4,
+                actual: 3
+            })
+        ));
+
+        // A first block which is not a genesis block.
+        let mut bad = Ledger::new(3);
+        bad.blocks[0] = Block::default();
+        assert!(matches!(
+            bad.history(0..=u64::MAX),
+            Err(RewardHistoryError::MissingGenesis)
+        ));
+
+        // A genesis block with a different stake table.
+        let mut bad = Ledger::new(3);
+        match &mut bad.blocks[0].0[0] {
+            EspressoTransaction::Genesis(note) => {
+                for stake in note.stake_table.values_mut() {
+                    *stake = Amount::from(STAKE + 1);
+                }
+            }
+            _ => unreachable!(),
+        }
+        assert!(matches!(
+            bad.history(0..=u64::MAX),
+            Err(RewardHistoryError::StakeTableMismatch)
+        ));
+
+        // A view which does not match the historical stake tables.
+        let mut bad = Ledger::new(3);
+        bad.views[1] = ConsensusTime::new(25);
+        assert!(matches!(
+            bad.history(0..=u64::MAX),
+            Err(RewardHistoryError::StakeTableHistoryMismatch)
+        ));
+
+        // A reward collection which the state does not include.
+        let mut bad = Ledger::new(3);
+        let history = bad.history(0..=u64::MAX).unwrap();
+        let reward = history.eligible_rewards(&bad.key).pop().unwrap();
+        let receiver = UserKeyPair::generate(&mut bad.rng).pub_key();
+        let txn = history
+            .collect_reward(&mut bad.rng, &bad.key, receiver, reward)
+            .unwrap();
+        bad.blocks[3].0.push(txn.txn);
+        assert!(matches!(
+            bad.history(0..=u64::MAX),
+            Err(RewardHistoryError::CollectedRewardsMismatch)
+        ));
+    }
+}
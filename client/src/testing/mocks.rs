@@ -75,6 +75,7 @@ impl<'a> MockNetwork<'a, EspressoLedger> for MockEspressoNetwork<'a> {
             block.parent_state,
             block.block.clone(),
             block.proofs.clone(),
+            block.memos.clone(),
         ) {
             Ok(ValidationOutputs { mut uids, .. }) => {
                 // Add nullifiers
@@ -160,6 +161,7 @@ impl<'a> MockNetwork<'a, EspressoLedger> for MockEspressoNetwork<'a> {
                 }
             }
             EspressoTransaction::Reward(_) => {}
+            EspressoTransaction::AppData(_) => {}
         }
 
         if memos.len() != txn.output_len() {
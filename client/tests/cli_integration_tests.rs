@@ -4,203 +4,257 @@
 
 extern crate espresso_client;
 use espresso_client::cli_client::{cli_test, CliClient};
+use espresso_client::json_io::{Account, NewKey, Output};
+use espresso_core::state::TransactionCommitment;
+use jf_cap::keys::UserAddress;
+use jf_cap::structs::AssetCode;
+
+/// Match the result of a command against a pattern, failing with the result if it does not match.
+macro_rules! expect {
+    ($output:expr, $pattern:pat => $value:expr) => {
+        match $output {
+            $pattern => $value,
+            output => return Err(format!("unexpected result {:?}", output)),
+        }
+    };
+}
 
 fn await_transaction(
-    uid: &str,
-    client: &mut CliClient,
+    t: &mut CliClient,
+    receipt: &TransactionCommitment,
     sender: usize,
     receiver: usize,
 ) -> Result<(), String> {
-    client
-        .command(sender, format!("wait {}", uid))?
-        .output("accepted")?;
+    let status =
+        expect!(t.run(sender, format!("wait {}", receipt))?, Output::Status(status) => status);
+    if !status.succeeded() {
+        return Err(format!("transaction {} failed: {:?}", receipt, status));
+    }
 
-    client.command(sender, "now")?.output("(?P<time>.*)")?;
-    let now = client.var("time")?;
-    client.command(receiver, format!("sync {}", now))?;
+    // Just because one keystore has processed the completed transaction doesn't mean the other has.
+    let now = expect!(t.run(sender, "now")?, Output::Time(now) => now);
+    t.run(receiver, format!("sync {}", now))?;
     Ok(())
 }
 
-fn create_keystore(t: &mut CliClient, keystore: usize) -> Result<&mut CliClient, String> {
+/// Create a keystore from a new mnemonic, and add the sending key generated for it.
+fn create_keystore(t: &mut CliClient, keystore: usize) -> Result<(String, Account), String> {
+    let mnemonic = t.create_json(keystore, "test_password")?;
     let key_path = t.keystore_key_path(keystore)?;
-    let key_path = key_path.as_os_str().to_str().ok_or_else(|| {
-        format!(
-            "failed to convert key path {:?} for keystore {} to string",
-            key_path, keystore
-        )
-    })?;
-    t.open(keystore)?
-        .output("Your mnemonic phrase will be:")?
-        .output("^(?P<mnemonic>[a-zA-Z ]+)")?
-        .output("1\\) Accept phrase and create keystore")?
-        .output("2\\) Generate a new phrase")?
-        .output("3\\) Manually enter a mnemonic")?
-        .command(keystore, "1")?
-        .output("Create password:")?
-        .command(keystore, "test_password")?
-        .output("Retype password:")?
-        // Try typing the incorrect password, to check the error handling
-        .command(keystore, "wrong_password")?
-        .output("Passwords do not match.")?
-        .output("Create password:")?
-        .command(keystore, "test_password")?
-        .output("Retype password:")?
-        .command(keystore, "test_password")?
-        .output("Type 'help' for a list of commands.")?
-        .command(keystore, format!("load_key sending {}", key_path))?
-        .output(format!("(?P<default_addr{}>ADDR~.*)", keystore))?
-        .output(format!("(?P<default_pubkey{}>USERPUBKEY~.*)", keystore))
+    let account = expect!(
+        t.run(keystore, format!("load_key sending {}", key_path.display()))?,
+        Output::Account(account) => account
+    );
+    Ok((mnemonic, account))
+}
+
+fn balance(
+    t: &mut CliClient,
+    keystore: usize,
+    asset: &AssetCode,
+    address: &UserAddress,
+) -> Result<u128, String> {
+    let balance = expect!(
+        t.run(keystore, format!("balance {} {}", asset, address))?,
+        Output::Balance(balance) => balance
+    );
+    balance
+        .balances
+        .get(&address.to_string())
+        .ok_or_else(|| format!("no balance for {} in {:?}", address, balance))?
+        .parse()
+        .map_err(|err| format!("invalid balance: {}", err))
+}
+
+fn check_total(
+    t: &mut CliClient,
+    keystore: usize,
+    asset: &AssetCode,
+    expected: u128,
+) -> Result<(), String> {
+    let balance = expect!(
+        t.run(keystore, format!("balance {}", asset))?,
+        Output::Balance(balance) => balance
+    );
+    if balance.total != expected.to_string() {
+        return Err(format!(
+            "expected total balance {} of {} in keystore {}, got {:?}",
+            expected, asset, keystore, balance
+        ));
+    }
+    Ok(())
 }
 
 fn wait_for_native_balance(
     t: &mut CliClient,
     keystore: usize,
-    account: &str,
-) -> Result<usize, String> {
+    address: &UserAddress,
+) -> Result<u128, String> {
     loop {
-        t.command(keystore, "balance $native")?
-            .output(format!("${} (?P<balance>\\d+)", account))?;
-        let balance = t.var("balance").unwrap().parse().unwrap();
+        let balance = balance(t, keystore, &AssetCode::native(), address)?;
         if balance > 0 {
             break Ok(balance);
         }
     }
 }
 
-fn wait_for_starting_balance(t: &mut CliClient) -> Result<usize, String> {
-    wait_for_native_balance(t, 0, "default_addr0")
-}
-
-fn cli_basic_info(t: &mut CliClient) -> Result<(), String> {
-    t
-        // `info`
-        .command(0, "info")?
-        .output("Addresses:")?
-        .output("(?P<addr1>ADDR~.*)")?
-        .output("Sending keys:")?
-        .output("(?P<pubkey1>USERPUBKEY~.*)")?
-        // `address`
-        .command(0, "address")?
-        .output("$addr1")?
-        // `assets`
-        .command(0, "assets")?
-        .output("\\d. (?P<native>ASSET_CODE~.*) \\(native\\)")?;
+fn cli_basic_info(t: &mut CliClient, account: &Account) -> Result<(), String> {
+    let keys = expect!(t.run(0, "info")?, Output::Keys(keys) => keys);
+    if !keys
+        .sending
+        .iter()
+        .any(|key| key.pub_key == account.pub_key)
+    {
+        return Err(format!(
+            "expected keys including {}, got {:?}",
+            account.pub_key, keys
+        ));
+    }
+    let addresses = expect!(t.run(0, "address")?, Output::Addresses(addresses) => addresses);
+    if !addresses.iter().any(|key| key.address == account.address) {
+        return Err(format!(
+            "expected addresses including {}, got {:?}",
+            account.address, addresses
+        ));
+    }
+    let assets = expect!(t.run(0, "assets")?, Output::Assets(assets) => assets);
+    if !assets.iter().any(|asset| asset.native) {
+        return Err(format!("expected the native asset, got {:?}", assets));
+    }
 
-    // add keys and check that they are reported
-    t.command(0, "gen_key view")?
-        .output("(?P<audkey>AUDPUBKEY~.*)")?
-        .command(0, "gen_key freeze")?
-        .output("(?P<freezekey>FREEZEPUBKEY~.*)")?
-        .command(0, "gen_key send")?
-        .output("(?P<addr2>ADDR~.*)")?
-        .command(0, "info")?
-        .output("Addresses:")?
-        .output("$addr1")?
-        .output("$addr2")?
-        .command(0, "keys")?
-        .output("Sending keys:")?
-        .output("$pubkey1")?
-        .output("USERPUBKEY~.*")?
-        .output("Viewing keys:")?
-        .output("$audkey")?
-        .output("Freezing keys:")?
-        .output("$freezekey")?;
+    // Add keys and check that they are reported.
+    let viewing_key = expect!(t.run(0, "gen_key view")?, Output::Key(NewKey::Viewing(key)) => key);
+    let freezing_key =
+        expect!(t.run(0, "gen_key freeze")?, Output::Key(NewKey::Freezing(key)) => key);
+    let pub_key = expect!(t.run(0, "gen_key send")?, Output::Key(NewKey::Sending(key)) => key);
+    let keys = expect!(t.run(0, "keys")?, Output::Keys(keys) => keys);
+    if !keys.sending.iter().any(|key| key.pub_key == pub_key)
+        || !keys.viewing.contains(&viewing_key)
+        || !keys.freezing.contains(&freezing_key)
+    {
+        return Err(format!("expected the new keys, got {:?}", keys));
+    }
 
-    // native asset info
-    t.command(0, "asset $native")?
-        .output("Native $native")?
-        .output("Not viewable")?
-        .output("Not freezeable")?
-        .output("Not mintable")?;
+    // Native asset info.
+    let native = expect!(
+        t.run(0, format!("asset {}", AssetCode::native()))?,
+        Output::Asset(asset) => asset
+    );
+    if !native.native || native.viewer.is_some() || native.freezer.is_some() || native.mintable {
+        return Err(format!("unexpected native asset {:?}", native));
+    }
 
     Ok(())
 }
 
-fn cli_transfer_native(t: &mut CliClient) -> Result<(), String> {
-    let balance = wait_for_starting_balance(t)?;
-    t
-        // Get the balance of both keystores.
-        .command(0, "balance $native")?
-        .output(format!("Total {}", balance))?
-        .command(1, "balance $native")?
-        .output("Total 0")?
-        // Transfer some native coins from the primary keystore to the secondary.
-        .command(0, "transfer $native $default_pubkey1 500 1")?
-        .output("(?P<txn>TXUID~.*)")?;
-    // Wait for the transaction to complete in both keystores (just because one keystore has
-    // received and processed the completed transaction doesn't mean the other has).
-    await_transaction(&t.var("txn")?, t, 0, 1)?;
-    t.command(0, "balance $native")?
-        .output(format!("Total {}", balance - 501))?
-        .command(1, "balance $native")?
-        .output("Total 500")?
-        // Transfer part of the money back
-        .command(1, "transfer $native $default_pubkey0 200 2")?
-        .output("(?P<txn>TXUID~.*)")?;
-    await_transaction(&t.var("txn")?, t, 1, 0)?;
-    t.command(1, "balance $native")?
-        .output("Total 298")?
-        .command(0, "balance $native")?
-        .output(format!("Total {}", balance - 301))?;
+fn cli_transfer_native(t: &mut CliClient, accounts: &[Account]) -> Result<(), String> {
+    let native = AssetCode::native();
+    let balance = wait_for_native_balance(t, 0, &accounts[0].address)?;
+    check_total(t, 0, &native, balance)?;
+    check_total(t, 1, &native, 0)?;
+
+    // Transfer some native coins from the primary keystore to the secondary.
+    let receipt = expect!(
+        t.run(0, format!("transfer {} {} 500 1", native, accounts[1].pub_key))?,
+        Output::Receipt(receipt) => receipt
+    );
+    await_transaction(t, &receipt, 0, 1)?;
+    check_total(t, 0, &native, balance - 501)?;
+    check_total(t, 1, &native, 500)?;
+
+    // Transfer part of the money back.
+    let receipt = expect!(
+        t.run(1, format!("transfer {} {} 200 2", native, accounts[0].pub_key))?,
+        Output::Receipt(receipt) => receipt
+    );
+    await_transaction(t, &receipt, 1, 0)?;
+    check_total(t, 1, &native, 298)?;
+    check_total(t, 0, &native, balance - 301)?;
     Ok(())
 }
 
-fn cli_mint_and_transfer(t: &mut CliClient) -> Result<(), String> {
-    wait_for_starting_balance(t)?;
-    t
-        // Define a new asset and mint some for the receiver.
-        .command(0, "create_asset asset1")?
-        .output("(?P<asset1>ASSET_CODE~.*)")?
-        .command(0, "asset $asset1")?
-        .output("asset1 $asset1")?
-        .output("Not viewable")?
-        .output("Not freezeable")?
-        .output("Minter: me")?
-        .command(0, "mint $asset1 $default_pubkey1 100 1")?
-        .output("(?P<txn>TXUID~.*)")?;
-    await_transaction(&t.var("txn")?, t, 0, 1)?;
-    // Check on the receiving end that
-    //  (1) we have learned about the new asset after receiving it
-    //  (2) we have a balance of it
-    t.command(1, "asset $asset1")?
-        .output("Asset $asset1")? // Receiver doesn't know the description "asset1"
-        .output("Not viewable")?
-        .output("Not freezeable")?
-        .output("Minter: unknown")? // Receiver doesn't know who minted the asset for them
-        .command(1, "balance $asset1")?
-        .output("$default_addr1 100")?
-        // Do it again, this time specifiying view and freeze keys
-        .command(0, "gen_key viewing")?
-        .output("(?P<audkey0>AUDPUBKEY~.*)")?
-        .command(1, "gen_key freezing")?
-        .output("(?P<freezekey1>FREEZEPUBKEY~.*)")?
-        .command(
+fn cli_mint_and_transfer(t: &mut CliClient, accounts: &[Account]) -> Result<(), String> {
+    wait_for_native_balance(t, 0, &accounts[0].address)?;
+
+    // Define a new asset and mint some for the receiver.
+    let asset1 = expect!(t.run(0, "create_asset asset1")?, Output::Asset(asset) => asset);
+    let info = expect!(t.run(0, format!("asset {}", asset1.code))?, Output::Asset(asset) => asset);
+    if info.name.as_deref() != Some("asset1")
+        || info.viewer.is_some()
+        || info.freezer.is_some()
+        || !info.mintable
+    {
+        return Err(format!("unexpected asset {:?}", info));
+    }
+    let receipt = expect!(
+        t.run(0, format!("mint {} {} 100 1", asset1.code, accounts[1].pub_key))?,
+        Output::Receipt(receipt) => receipt
+    );
+    await_transaction(t, &receipt, 0, 1)?;
+    // The receiver learns about the new asset by receiving it, but cannot mint it.
+    let info = expect!(t.run(1, format!("asset {}", asset1.code))?, Output::Asset(asset) => asset);
+    if info.viewer.is_some() || info.freezer.is_some() || info.mintable {
+        return Err(format!("unexpected asset {:?}", info));
+    }
+    if balance(t, 1, &asset1.code, &accounts[1].address)? != 100 {
+        return Err(format!("expected a balance of 100 {}", asset1.code));
+    }
+
+    // Do it again, this time specifying viewing and freezing keys.
+    let viewing_key =
+        expect!(t.run(0, "gen_key viewing")?, Output::Key(NewKey::Viewing(key)) => key);
+    let freezing_key =
+        expect!(t.run(1, "gen_key freezing")?, Output::Key(NewKey::Freezing(key)) => key);
+    let asset2 = expect!(
+        t.run(
             0,
-            "create_asset asset2 viewing_key=$audkey0 freezing_key=$freezekey1",
-        )?
-        .output("(?P<asset2>ASSET_CODE~.*)")?
-        // Once there is more than 1 custom asset, we have to refer to it by code, not index,
-        // because the order of custom assets is non-deterministic. I should probably fix that.
-        .command(0, "asset $asset2")?
-        .output("asset2 $asset2")?
-        .output("Viewer: me")?
-        .output("Freezer: $freezekey1")?
-        .output("Minter: me")?
-        .command(0, "mint $asset2 $default_pubkey1 200 1")?
-        .output("(?P<txn>TXUID~.*)")?;
-    await_transaction(&t.var("txn")?, t, 0, 1)?;
-    // Check on the receiving end
-    t.command(1, "asset $asset2")?
-        .output("Asset $asset2")?
-        .output("Viewer: $audkey0")?
-        .output("Freezer: me")?
-        .output("Minter: unknown")?
-        .command(1, "balance $asset2")?
-        .output("Total 200")?;
+            format!(
+                "create_asset asset2 viewing_key={} freezing_key={}",
+                viewing_key, freezing_key
+            )
+        )?,
+        Output::Asset(asset) => asset
+    );
+    if asset2.viewer.as_ref() != Some(&viewing_key)
+        || asset2.freezer.as_ref() != Some(&freezing_key)
+        || !asset2.mintable
+    {
+        return Err(format!("unexpected asset {:?}", asset2));
+    }
+    let receipt = expect!(
+        t.run(0, format!("mint {} {} 200 1", asset2.code, accounts[1].pub_key))?,
+        Output::Receipt(receipt) => receipt
+    );
+    await_transaction(t, &receipt, 0, 1)?;
+    let info = expect!(t.run(1, format!("asset {}", asset2.code))?, Output::Asset(asset) => asset);
+    if info.viewer.as_ref() != Some(&viewing_key)
+        || info.freezer.as_ref() != Some(&freezing_key)
+        || info.mintable
+    {
+        return Err(format!("unexpected asset {:?}", info));
+    }
+    check_total(t, 1, &asset2.code, 200)?;
     Ok(())
 }
 
-fn cli_login(t: &mut CliClient) -> Result<(), String> {
+fn cli_errors(t: &mut CliClient, accounts: &[Account]) -> Result<(), String> {
+    t.run_err(1, "bogus")?;
+    t.run_err(1, "balance")?;
+    // Keystore 1 cannot afford this.
+    t.run_err(
+        1,
+        format!(
+            "transfer {} {} {} 1",
+            AssetCode::native(),
+            accounts[0].pub_key,
+            u64::MAX
+        ),
+    )?;
+    Ok(())
+}
+
+fn cli_login(t: &mut CliClient, mnemonic: &str, account: &Account) -> Result<(), String> {
+    // Logging in with a password is only done by the interactive CLI, so this checks its prompts.
     t.close(0)?
         .open(0)?
         .output("Forgot your password\\? Want to change it\\? \\[y/n\\]")?
@@ -213,22 +267,26 @@ fn cli_login(t: &mut CliClient) -> Result<(), String> {
         .command(0, "n")?
         .output("Enter password:")?
         .command(0, "test_password")?
-        .output("Type 'help' for a list of commands.")?;
+        .output("Type 'help' for a list of commands.")?
+        .close(0)?;
 
-    // Check that the keystore is functional.
-    cli_basic_info(t)
+    // Check that the keystore is still functional.
+    t.open_json(0, mnemonic, "test_password")?;
+    cli_basic_info(t, account)
 }
 
 #[test]
 fn cli_integration_tests() {
     cli_test(|t| {
-        create_keystore(t, 0)?;
-        create_keystore(t, 1)?;
+        let (mnemonic, account0) = create_keystore(t, 0)?;
+        let (_, account1) = create_keystore(t, 1)?;
+        let accounts = [account0, account1];
 
-        cli_basic_info(t)?;
-        cli_transfer_native(t)?;
-        cli_mint_and_transfer(t)?;
-        cli_login(t)?;
+        cli_basic_info(t, &accounts[0])?;
+        cli_transfer_native(t, &accounts)?;
+        cli_mint_and_transfer(t, &accounts)?;
+        cli_errors(t, &accounts)?;
+        cli_login(t, &mnemonic, &accounts[0])?;
 
         Ok(())
     });
@@ -237,60 +295,36 @@ fn cli_integration_tests() {
 #[test]
 fn recover_from_mnemonic() {
     cli_test(|t| {
-        let key_path = t.keystore_key_path(0)?;
-        let key_path = key_path.as_os_str().to_str().ok_or_else(|| {
-            format!(
-                "failed to convert key path {:?} for keystore {} to string",
-                key_path, 0
-            )
-        })?;
-        t.open(0)?
-            .output("Your mnemonic phrase will be:")?
-            .output("^(?P<mnemonic>(?:[a-z]+ ){11}(?:[a-z]+))$")?
-            .output("1\\) Accept phrase and create keystore")?
-            .output("2\\) Generate a new phrase")?
-            .output("3\\) Manually enter a mnemonic")?
-            // Ask for a new mnemonic just so we hit every code path
-            .command(0, "2")?
-            .output("Your mnemonic phrase will be:")?
-            .output("^(?P<mnemonic>(?:[a-z]+ ){11}(?:[a-z]+))$")?
-            .output("1\\) Accept phrase and create keystore")?
-            .output("2\\) Generate a new phrase")?
-            .output("3\\) Manually enter a mnemonic")?
-            .command(0, "1")?
-            .output("Create password")?
-            .command(0, "password")?
-            .output("Retype password")?
-            .command(0, "password")?
-            .output("Type 'help' for a list of commands.")?
-            .command(0, "assets")?
-            .output("\\d. (?P<native>ASSET_CODE~.*) \\(native\\)")?
-            .command(0, format!("load_key sending {}", key_path))?
-            .output("(?P<default_addr0>ADDR~.*)")?
-            .output("(?P<default_pubkey0>USERPUBKEY~.*)")?;
-        wait_for_starting_balance(t)?;
-        t
-            // Create a determinstic key
-            .command(0, "gen_key sending")?
-            .output("(?P<pubkey>USERPUBKEY~.*)")?
-            .output("(?P<addr>ADDR~.*)")?
-            // Give the key some assets
-            .command(0, "transfer $native $pubkey 100 1 wait=true")?
-            .command(0, "balance $native")?
-            .output("$addr 100")?
-            // Create a new keystore with the same mnemonic and check that we get the balance.
-            .open(1)?
-            .output("3\\) Manually enter a mnemonic")?
-            .command(1, "3")?
-            .output("Enter mnemonic phrase")?
-            .command(1, "$mnemonic")?
-            .output("Create password")?
-            .command(1, "password")?
-            .output("Retype password")?
-            .command(1, "password")?
-            .output("Type 'help' for a list of commands.")?
-            .command(1, "gen_key spend scan_from=0")?;
-        let balance = wait_for_native_balance(t, 0, "addr")?;
+        let native = AssetCode::native();
+        let (mnemonic, account) = create_keystore(t, 0)?;
+        wait_for_native_balance(t, 0, &account.address)?;
+
+        // Create a deterministic key and give it some assets.
+        let pub_key = expect!(
+            t.run(0, "gen_key sending")?,
+            Output::Key(NewKey::Sending(key)) => key
+        );
+        t.run(
+            0,
+            format!("transfer {} {} 100 1 wait=true", native, pub_key),
+        )?;
+        if balance(t, 0, &native, &pub_key.address())? != 100 {
+            return Err(format!("expected a balance of 100 for {}", pub_key));
+        }
+
+        // A new keystore with the same mnemonic generates the same key, and finds its balance.
+        t.open_json(1, &mnemonic, "password")?;
+        let recovered = expect!(
+            t.run(1, "gen_key sending scan_from=0")?,
+            Output::Key(NewKey::Sending(key)) => key
+        );
+        if recovered != pub_key {
+            return Err(format!(
+                "expected to recover {}, got {}",
+                pub_key, recovered
+            ));
+        }
+        let balance = wait_for_native_balance(t, 1, &pub_key.address())?;
         if balance != 100 {
             return Err(format!("incorrect balance (expected 100, got {})", balance));
         }
@@ -0,0 +1,277 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Espresso library.
+
+//! Application-data transactions.
+//!
+//! An application-data transaction carries an opaque payload tagged with a namespace. Validators
+//! order and commit to these transactions like any other, but they do not interpret the payload, so
+//! rollups and other applications can use Espresso purely for ordering and data availability.
+//!
+//! Each block commits to a [NamespaceTable], which maps each namespace to a commitment to the
+//! transactions from that namespace in the block. An application can fetch just its own
+//! transactions from a block, along with a [NamespaceProof] that they are all of them.
+
+use crate::kv_merkle_tree::{KVMerkleProof, KVMerkleTree};
+use crate::state::{
+    Block, CommitableHash, CommitableHashTag, ElaboratedTransaction, EspressoTransaction,
+    EspressoTxnHelperProofs, TransactionCommitment,
+};
+use crate::tree_hash::KVTreeHash;
+use arbitrary::Arbitrary;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use commit::{Commitment, Committable, RawCommitmentBuilder};
+use derive_more::{Display, From, FromStr, Into};
+use espresso_macros::ser_test;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// Identifies the application which an [AppDataNote] belongs to.
+#[ser_test(arbitrary)]
+#[derive(
+    Arbitrary,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Display,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    From,
+    FromStr,
+    Into,
+    Serialize,
+    Deserialize,
+    CanonicalSerialize,
+    CanonicalDeserialize,
+)]
+pub struct NamespaceId(pub u64);
+
+/// An opaque payload belonging to the application identified by `namespace`.
+#[ser_test(arbitrary)]
+#[derive(
+    Arbitrary,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    CanonicalSerialize,
+    CanonicalDeserialize,
+)]
+pub struct AppDataNote {
+    pub namespace: NamespaceId,
+    pub payload: Vec<u8>,
+}
+
+impl AppDataNote {
+    pub fn new(namespace: NamespaceId, payload: Vec<u8>) -> Self {
+        Self { namespace, payload }
+    }
+}
+
+impl From<AppDataNote> for ElaboratedTransaction {
+    fn from(note: AppDataNote) -> Self {
+        Self {
+            txn: EspressoTransaction::AppData(note),
+            proofs: EspressoTxnHelperProofs::AppData,
+            memos: None,
+        }
+    }
+}
+
+/// The transactions from one namespace in a block, each with its index in the block.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NamespaceTransactions(pub Vec<(u64, TransactionCommitment)>);
+
+impl Committable for NamespaceTransactions {
+    fn commit(&self) -> Commitment<Self> {
+        self.0
+            .iter()
+            .fold(
+                RawCommitmentBuilder::new("Namespace Txns").u64_field("count", self.0.len() as u64),
+                |builder, (index, hash)| builder.u64_field("index", *index).field("txn", hash.0),
+            )
+            .finalize()
+    }
+}
+
+/// Identifying tag for a namespace table
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Hash)]
+pub struct NamespaceTableTag();
+impl CommitableHashTag for NamespaceTableTag {
+    fn commitment_diversifier() -> &'static str {
+        "Namespace Table Input"
+    }
+}
+
+/// Hash function for namespace tables
+pub type NamespaceTableHash =
+    CommitableHash<NamespaceId, Commitment<NamespaceTransactions>, NamespaceTableTag>;
+
+/// The root hash of a [NamespaceTable].
+pub type NamespaceTableRoot = <NamespaceTableHash as KVTreeHash>::Digest;
+
+/// KeyValue Merkle tree mapping each namespace in a block to its [NamespaceTransactions].
+pub type NamespaceTable = KVMerkleTree<NamespaceTableHash>;
+
+impl Block {
+    /// The application-data transactions in this block, grouped by namespace.
+    pub fn namespaces(&self) -> BTreeMap<NamespaceId, NamespaceTransactions> {
+        let mut namespaces = BTreeMap::<_, NamespaceTransactions>::new();
+        for (index, txn) in self.0.iter().enumerate() {
+            if let EspressoTransaction::AppData(note) = txn {
+                namespaces
+                    .entry(note.namespace)
+                    .or_default()
+                    .0
+                    .push((index as u64, TransactionCommitment(txn.commit())));
+            }
+        }
+        namespaces
+    }
+
+    /// The root of the namespace table of this block, whose transactions commitment is
+    /// `transactions`.
+    ///
+    /// Every commitment to a block includes this root, and the same block is committed to many
+    /// times, so roots of recent blocks are cached rather than rebuilding the table each time.
+    pub fn namespace_table_root(&self, transactions: Commitment<Block>) -> NamespaceTableRoot {
+        if !self
+            .0
+            .iter()
+            .any(|txn| matches!(txn, EspressoTransaction::AppData(_)))
+        {
+            return NamespaceTable::default().hash();
+        }
+        if let Some(root) = NAMESPACE_TABLE_ROOTS.lock().unwrap().get(&transactions) {
+            return root;
+        }
+        let root = self.namespace_table().hash();
+        NAMESPACE_TABLE_ROOTS
+            .lock()
+            .unwrap()
+            .insert(transactions, root);
+        root
+    }
+
+    /// The namespace table of this block.
+    pub fn namespace_table(&self) -> NamespaceTable {
+        let mut table = NamespaceTable::default();
+        for (namespace, txns) in self.namespaces() {
+            // This unwrap will always succeed, since we are building the tree from scratch and the
+            // whole thing is in memory.
+            table.insert(namespace, txns.commit()).unwrap();
+        }
+        table
+    }
+
+    /// The indices of the transactions in this block which belong to `namespace`, and a proof that
+    /// there are no others.
+    pub fn namespace_proof(&self, namespace: NamespaceId) -> (Vec<u64>, NamespaceProof) {
+        let indices = self
+            .namespaces()
+            .remove(&namespace)
+            .unwrap_or_default()
+            .0
+            .into_iter()
+            .map(|(index, _)| index)
+            .collect();
+        let table = self.namespace_table();
+        // The whole table is in memory, so the lookup cannot fail.
+        let (_, proof) = table.lookup(namespace).unwrap();
+        (
+            indices,
+            NamespaceProof {
+                transactions_commitment: self.transactions_commitment(),
+                namespace_table_root: table.hash(),
+                proof,
+            },
+        )
+    }
+}
+
+/// The number of blocks whose namespace table roots are cached.
+const NAMESPACE_TABLE_ROOTS_CACHE_SIZE: usize = 1000;
+
+/// Namespace table roots of recently committed blocks, by transactions commitment.
+#[derive(Default)]
+struct NamespaceTableRoots {
+    roots: HashMap<Commitment<Block>, NamespaceTableRoot>,
+    order: VecDeque<Commitment<Block>>,
+}
+
+impl NamespaceTableRoots {
+    fn get(&self, transactions: &Commitment<Block>) -> Option<NamespaceTableRoot> {
+        self.roots.get(transactions).copied()
+    }
+
+    fn insert(&mut self, transactions: Commitment<Block>, root: NamespaceTableRoot) {
+        if self.roots.insert(transactions, root).is_none() {
+            self.order.push_back(transactions);
+        }
+        while self.order.len() > NAMESPACE_TABLE_ROOTS_CACHE_SIZE {
+            if let Some(oldest) = self.order.pop_front() {
+                self.roots.remove(&oldest);
+            }
+        }
+    }
+}
+
+lazy_static! {
+    static ref NAMESPACE_TABLE_ROOTS: Mutex<NamespaceTableRoots> = Default::default();
+}
+
+/// A proof that a set of transactions is exactly the transactions from one namespace in a block.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamespaceProof {
+    /// Commitment to the full list of transactions in the block.
+    pub transactions_commitment: Commitment<Block>,
+    /// The root of the block's [NamespaceTable].
+    pub namespace_table_root: NamespaceTableRoot,
+    /// Proof of the namespace's entry in the table, or of its absence.
+    pub proof: KVMerkleProof<NamespaceTableHash>,
+}
+
+impl NamespaceProof {
+    /// Check that `txns` are all of the transactions from `namespace` in the block with commitment
+    /// `block`.
+    ///
+    /// Each transaction is given along with its index in the block, and they must be in order.
+    pub fn verify(
+        &self,
+        block: Commitment<Block>,
+        namespace: NamespaceId,
+        txns: &[(u64, EspressoTransaction)],
+    ) -> bool {
+        if txns.iter().any(|(_, txn)| {
+            !matches!(txn, EspressoTransaction::AppData(note) if note.namespace == namespace)
+        }) {
+            return false;
+        }
+        let expected = if txns.is_empty() {
+            None
+        } else {
+            Some(
+                NamespaceTransactions(
+                    txns.iter()
+                        .map(|(index, txn)| (*index, TransactionCommitment(txn.commit())))
+                        .collect(),
+                )
+                .commit(),
+            )
+        };
+        match self.proof.check(namespace, self.namespace_table_root) {
+            Some((value, root)) if root == self.namespace_table_root && value == expected => {}
+            _ => return false,
+        }
+        Block::commitment_from_parts(self.transactions_commitment, self.namespace_table_root)
+            == block
+    }
+}
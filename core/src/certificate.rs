@@ -0,0 +1,451 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Espresso library.
+
+//! Quorum certificates in a stable form which can be checked outside of consensus.
+//!
+//! HotShot decides a leaf when enough of the stake-weighted committee votes for it. Each vote is a
+//! signature on the leaf commitment together with a VRF vote token, which proves how many committee
+//! seats the voter won in the sortition for that view. [QuorumCertificateData] lays this out
+//! explicitly, and [QuorumCertificateData::verify] checks it against a stake table, so that clients
+//! which do not run consensus can still tell whether a block was decided.
+//!
+//! The votes only sign the leaf commitment, so a certificate on its own says nothing about the
+//! `block_commitment` it claims. [QuorumCertificateData::check_leaf] recomputes the certified leaf
+//! commitment from the block and state it decides, which binds both to the votes.
+
+use crate::stake_table::{Election, StakingKey, VrfParam};
+use crate::state::{
+    amount_to_nonzerou64, ChainVariables, ConsensusTime, ElaboratedBlock, LedgerStateCommitment,
+    ValidatorState,
+};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use commit::{Commitment, RawCommitmentBuilder};
+use hotshot::traits::election::vrf::{VRFPubKey, VRFVoteToken};
+use hotshot_types::data::{Leaf, QuorumCertificate};
+use hotshot_types::traits::signature_key::{self, SignatureKey};
+use jf_cap::structs::Amount;
+use jf_primitives::signatures::bls::BLSSignature;
+use jf_primitives::signatures::BLSSignatureScheme;
+use jf_utils::tagged_blob;
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, Snafu};
+use std::collections::{BTreeMap, BTreeSet};
+use std::num::NonZeroU64;
+
+/// The vote token HotShot attaches to each vote, as produced by [Election].
+///
+/// HotShot stores this token in quorum certificates serialized with `bincode`.
+type VoteToken = VRFVoteToken<VRFPubKey<BLSSignatureScheme<VrfParam>>, BLSSignature<VrfParam>>;
+
+#[tagged_blob("QCSIG")]
+#[derive(Debug, Clone, CanonicalDeserialize, CanonicalSerialize, Hash, PartialEq, Eq)]
+pub struct EncodedSignature(pub Vec<u8>);
+
+#[tagged_blob("VOTETOKEN")]
+#[derive(Debug, Clone, CanonicalDeserialize, CanonicalSerialize, Hash, PartialEq, Eq)]
+pub struct EncodedVoteToken(pub Vec<u8>);
+
+/// A single vote in a [QuorumCertificateData].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct QuorumSignature {
+    pub key: StakingKey,
+    /// Signature by `key` on the leaf commitment.
+    pub signature: EncodedSignature,
+    /// The VRF vote token proving the voter's stake-weighted committee membership.
+    pub vote_token: EncodedVoteToken,
+}
+
+/// A quorum certificate in a stable, self-describing form.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct QuorumCertificateData {
+    pub view_number: u64,
+    pub block_commitment: Commitment<ElaboratedBlock>,
+    pub leaf_commitment: Commitment<Leaf<ValidatorState>>,
+    pub genesis: bool,
+    /// The votes making up the certificate, in order of public key.
+    pub signatures: Vec<QuorumSignature>,
+}
+
+/// Convert a HotShot certificate.
+///
+/// Fails if any voter key cannot be decoded, rather than dropping that vote, since the certificate
+/// is committed to by the next leaf exactly as HotShot stores it.
+impl TryFrom<QuorumCertificate<ValidatorState>> for QuorumCertificateData {
+    type Error = CertificateError;
+
+    fn try_from(qc: QuorumCertificate<ValidatorState>) -> Result<Self, CertificateError> {
+        let signatures = qc
+            .signatures
+            .into_iter()
+            .map(|(key, (signature, vote_token))| {
+                Ok(QuorumSignature {
+                    key: StakingKey::from_bytes(&key)
+                        .context(InvalidVoterKeySnafu { key: key.0.clone() })?,
+                    signature: EncodedSignature(signature.0),
+                    vote_token: EncodedVoteToken(vote_token),
+                })
+            })
+            .collect::<Result<_, CertificateError>>()?;
+        Ok(Self {
+            view_number: *qc.view_number,
+            block_commitment: qc.block_commitment,
+            leaf_commitment: qc.leaf_commitment,
+            genesis: qc.genesis,
+            signatures,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Snafu, Serialize, Deserialize)]
+#[snafu(visibility(pub(crate)))]
+pub enum CertificateError {
+    #[snafu(display("certificate contains a vote from an invalid key {:?}", key))]
+    InvalidVoterKey { key: Vec<u8> },
+
+    #[snafu(display("{} voted but is not in the stake table", key))]
+    UnknownVoter { key: StakingKey },
+
+    #[snafu(display("{} voted more than once", key))]
+    DuplicateVoter { key: StakingKey },
+
+    #[snafu(display("invalid signature from {}", key))]
+    InvalidSignature { key: StakingKey },
+
+    #[snafu(display("invalid vote token from {}", key))]
+    InvalidVoteToken { key: StakingKey },
+
+    #[snafu(display("certificate has {} votes, but {} are required", votes, threshold))]
+    InsufficientVotes { votes: u64, threshold: u64 },
+
+    #[snafu(display("certificate is not for a leaf deciding this block and state"))]
+    LeafMismatch,
+}
+
+/// The number of committee seats whose votes are required to decide a leaf.
+///
+/// This is more than 2/3 of the expected committee size, the same threshold HotShot's VRF election
+/// uses.
+pub const fn quorum_threshold(committee_size: u64) -> u64 {
+    2 * committee_size / 3 + 1
+}
+
+impl QuorumCertificateData {
+    /// Check that this certificate carries a quorum of valid votes for its leaf.
+    ///
+    /// Each vote must be signed by a key in `stake_table`, and its vote token must prove the seats
+    /// it claims in the sortition defined by `chain`. The seats of all the votes must add up to the
+    /// [quorum_threshold]. Each key may vote only once.
+    ///
+    /// This only checks the votes on `leaf_commitment`. Use [check_leaf](Self::check_leaf) to check
+    /// that the leaf decides a particular block and state.
+    pub fn verify(
+        &self,
+        chain: &ChainVariables,
+        stake_table: &BTreeMap<StakingKey, Amount>,
+    ) -> Result<(), CertificateError> {
+        let mut voters = BTreeSet::new();
+        for vote in &self.signatures {
+            if !voters.insert(&vote.key) {
+                return Err(CertificateError::DuplicateVoter {
+                    key: vote.key.clone(),
+                });
+            }
+        }
+
+        let total_stake = amount_to_nonzerou64(
+            stake_table
+                .values()
+                .fold(Amount::from(0u64), |total, stake| total + *stake),
+        );
+        let committee_size = NonZeroU64::new(chain.committee_size).unwrap();
+        let mut votes = 0;
+        for vote in &self.signatures {
+            let key = &vote.key;
+            let stake = stake_table
+                .get(key)
+                .context(UnknownVoterSnafu { key: key.clone() })?;
+            if !key.validate(
+                &signature_key::EncodedSignature(vote.signature.0.clone()),
+                self.leaf_commitment.as_ref(),
+            ) {
+                return Err(CertificateError::InvalidSignature { key: key.clone() });
+            }
+            let token: VoteToken = bincode::deserialize(&vote.vote_token.0)
+                .ok()
+                .context(InvalidVoteTokenSnafu { key: key.clone() })?;
+            let valid = Election::check_sortition_proof(
+                key.as_ref(),
+                &(),
+                &token.proof,
+                total_stake,
+                amount_to_nonzerou64(*stake),
+                committee_size,
+                token.count,
+                chain.vrf_seed.as_ref(),
+                ConsensusTime::new(self.view_number),
+            );
+            if !matches!(valid, Ok(true)) {
+                return Err(CertificateError::InvalidVoteToken { key: key.clone() });
+            }
+            votes += u64::from(token.count);
+        }
+        let threshold = quorum_threshold(chain.committee_size);
+        if votes < threshold {
+            return Err(CertificateError::InsufficientVotes { votes, threshold });
+        }
+        Ok(())
+    }
+    /// The commitment to the leaf which decides `state`, with this certificate's block and view.
+    ///
+    /// `justify` is the certificate carried by the leaf, which decided the previous block. The
+    /// leaf's parent is the leaf certified by `justify`. This follows HotShot's commitment to
+    /// [Leaf], which does not include the leaf's timestamp or proposer.
+    pub fn certified_leaf_commitment(
+        &self,
+        justify: &QuorumCertificateData,
+        state: LedgerStateCommitment,
+    ) -> Commitment<Leaf<ValidatorState>> {
+        let mut signatures_bytes = vec![];
+        for vote in &justify.signatures {
+            signatures_bytes.extend(vote.key.to_bytes().0);
+            signatures_bytes.extend(&vote.signature.0);
+            signatures_bytes.extend(&vote.vote_token.0);
+        }
+        RawCommitmentBuilder::new("Leaf Comm")
+            .u64_field("view_number", self.view_number)
+            .field("parent Leaf commitment", justify.leaf_commitment)
+            .field("deltas commitment", self.block_commitment)
+            .field("state commitment", state.0)
+            .constant_str("justify_qc view number")
+            .u64(justify.view_number)
+            .field("justify_qc block commitment", justify.block_commitment)
+            .field("justify_qc leaf commitment", justify.leaf_commitment)
+            .var_size_field("justify_qc signatures", &signatures_bytes)
+            .finalize()
+    }
+
+    /// Check that this certificate is for the leaf which decides its block and `state`.
+    ///
+    /// Since the votes sign [leaf_commitment](Self::leaf_commitment), a certificate which passes
+    /// this check and [verify](Self::verify) proves that `state` was decided.
+    pub fn check_leaf(
+        &self,
+        justify: &QuorumCertificateData,
+        state: LedgerStateCommitment,
+    ) -> Result<(), CertificateError> {
+        if self.certified_leaf_commitment(justify, state) != self.leaf_commitment {
+            return Err(CertificateError::LeafMismatch);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_data::{AppDataNote, NamespaceId};
+    use crate::genesis::GenesisNote;
+    use crate::state::ElaboratedTransaction;
+    use crate::universal_params::VERIF_CRS;
+    use commit::Committable;
+    use hotshot::traits::election::vrf::VRFStakeTableConfig;
+    use hotshot::traits::{Block as _, State as _};
+    use hotshot_types::data::ViewNumber;
+    use hotshot_types::traits::election::Election as _;
+    use std::sync::Arc;
+
+    /// A certificate for `leaf`, signed by every key in `keys`.
+    fn sign_leaf(
+        leaf: &Leaf<ValidatorState>,
+        keys: &[(StakingKey, <StakingKey as SignatureKey>::PrivateKey)],
+    ) -> QuorumCertificate<ValidatorState> {
+        let leaf_commitment = leaf.commit();
+        QuorumCertificate {
+            block_commitment: leaf.deltas.commit(),
+            leaf_commitment,
+            view_number: leaf.view_number,
+            signatures: keys
+                .iter()
+                .map(|(key, priv_key)| {
+                    (
+                        key.to_bytes(),
+                        (
+                            StakingKey::sign(priv_key, leaf_commitment.as_ref()),
+                            // The vote token is only hashed into the next leaf's commitment, so
+                            // its contents don't matter here.
+                            vec![1, 2, 3],
+                        ),
+                    )
+                })
+                .collect(),
+            genesis: false,
+        }
+    }
+
+    /// Pin [QuorumCertificateData::certified_leaf_commitment] against HotShot's [Leaf] commitment
+    /// for a chain built the way consensus builds it: a genesis leaf with an empty genesis
+    /// certificate, followed by a leaf with a non-empty block, a signed certificate for its parent,
+    /// and a timestamp and proposer which the commitment must ignore.
+    #[test]
+    fn test_certified_leaf_commitment_matches_hotshot() {
+        let keys = (0..3)
+            .map(|i| StakingKey::generated_from_seed_indexed([7; 32], i))
+            .collect::<Vec<_>>();
+        let note = GenesisNote::new(
+            ChainVariables::new(42, VERIF_CRS.clone(), 3),
+            Arc::new(vec![]),
+            keys.iter()
+                .map(|(key, _)| (key.clone(), Amount::from(10u64)))
+                .collect(),
+        );
+        let genesis_block = ElaboratedBlock::genesis(note.clone());
+        let genesis_state = ValidatorState::genesis(note);
+        let genesis_justify = QuorumCertificate::<ValidatorState> {
+            block_commitment: genesis_block.commit(),
+            leaf_commitment: RawCommitmentBuilder::new("genesis parent").finalize(),
+            view_number: ViewNumber::new(0),
+            signatures: Default::default(),
+            genesis: true,
+        };
+        let genesis_leaf = Leaf {
+            view_number: ViewNumber::new(0),
+            justify_qc: genesis_justify.clone(),
+            parent_commitment: genesis_justify.leaf_commitment,
+            deltas: genesis_block,
+            state: genesis_state.clone(),
+            timestamp: 0,
+            proposer_id: keys[0].0.to_bytes(),
+        };
+        let genesis_qc = sign_leaf(&genesis_leaf, &keys);
+
+        let block = ElaboratedBlock::new(genesis_state.commit())
+            .add_transaction_raw(&ElaboratedTransaction::from(AppDataNote::new(
+                NamespaceId(1),
+                vec![1, 2, 3],
+            )))
+            .unwrap();
+        let state = genesis_state
+            .append(&block, &ConsensusTime::new(1))
+            .unwrap();
+        let leaf = Leaf {
+            view_number: ViewNumber::new(1),
+            justify_qc: genesis_qc.clone(),
+            parent_commitment: genesis_leaf.commit(),
+            deltas: block,
+            state: state.clone(),
+            timestamp: 1_668_000_000_000_000_000,
+            proposer_id: keys[1].0.to_bytes(),
+        };
+        let qc = QuorumCertificateData::try_from(sign_leaf(&leaf, &keys)).unwrap();
+
+        let genesis_justify = QuorumCertificateData::try_from(genesis_justify).unwrap();
+        let genesis_qc = QuorumCertificateData::try_from(genesis_qc).unwrap();
+        assert_eq!(
+            genesis_qc.certified_leaf_commitment(&genesis_justify, genesis_state.commit()),
+            genesis_leaf.commit()
+        );
+        assert_eq!(
+            qc.certified_leaf_commitment(&genesis_qc, state.commit()),
+            leaf.commit()
+        );
+        qc.check_leaf(&genesis_qc, state.commit()).unwrap();
+    }
+
+    #[test]
+    fn test_quorum_threshold_matches_hotshot() {
+        let keys = (0..3)
+            .map(|i| StakingKey::generated_from_seed_indexed([7; 32], i).0.into())
+            .collect::<Vec<_>>();
+        for committee_size in [1, 2, 3, 10, 100, 1000] {
+            let election = Election::with_initial_stake(
+                keys.clone(),
+                &VRFStakeTableConfig {
+                    sortition_parameter: NonZeroU64::new(committee_size).unwrap(),
+                    distribution: vec![NonZeroU64::new(10).unwrap(); keys.len()],
+                },
+                [0; 32],
+            );
+            assert_eq!(
+                quorum_threshold(committee_size),
+                election.get_threshold().get()
+            );
+        }
+    }
+
+    #[test]
+    fn test_certified_leaf_commitment() {
+        let state = ValidatorState::default();
+        let block = ElaboratedBlock::new(state.commit());
+        let key = StakingKey::generated_from_seed_indexed([0; 32], 0).0;
+        let justify_qc = QuorumCertificate::<ValidatorState> {
+            block_commitment: RawCommitmentBuilder::new("parent block").finalize(),
+            leaf_commitment: RawCommitmentBuilder::new("parent leaf").finalize(),
+            view_number: ViewNumber::new(1),
+            signatures: [(
+                key.to_bytes(),
+                (
+                    signature_key::EncodedSignature(vec![1, 2, 3]),
+                    vec![4, 5, 6],
+                ),
+            )]
+            .into_iter()
+            .collect(),
+            genesis: false,
+        };
+        let leaf = Leaf {
+            view_number: ViewNumber::new(2),
+            justify_qc: justify_qc.clone(),
+            parent_commitment: justify_qc.leaf_commitment,
+            deltas: block.clone(),
+            state: state.clone(),
+            timestamp: 0,
+            proposer_id: key.to_bytes(),
+        };
+        let justify = QuorumCertificateData::try_from(justify_qc).unwrap();
+        let qc = QuorumCertificateData {
+            view_number: 2,
+            block_commitment: block.commit(),
+            leaf_commitment: leaf.commit(),
+            genesis: false,
+            signatures: vec![],
+        };
+
+        // The recomputed commitment matches HotShot's.
+        qc.check_leaf(&justify, state.commit()).unwrap();
+
+        // A certificate for the same leaf cannot claim a different block or state.
+        let other_state = LedgerStateCommitment(RawCommitmentBuilder::new("state").finalize());
+        let forged = QuorumCertificateData {
+            block_commitment: ElaboratedBlock::new(other_state).commit(),
+            ..qc.clone()
+        };
+        assert!(matches!(
+            forged.check_leaf(&justify, state.commit()),
+            Err(CertificateError::LeafMismatch)
+        ));
+        assert!(matches!(
+            qc.check_leaf(&justify, other_state),
+            Err(CertificateError::LeafMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_voter_key() {
+        let qc = QuorumCertificate::<ValidatorState> {
+            block_commitment: RawCommitmentBuilder::new("block").finalize(),
+            leaf_commitment: RawCommitmentBuilder::new("leaf").finalize(),
+            view_number: ViewNumber::new(1),
+            signatures: [(
+                signature_key::EncodedPublicKey(vec![0; 3]),
+                (signature_key::EncodedSignature(vec![]), vec![]),
+            )]
+            .into_iter()
+            .collect(),
+            genesis: false,
+        };
+        assert!(matches!(
+            QuorumCertificateData::try_from(qc),
+            Err(CertificateError::InvalidVoterKey { .. })
+        ));
+    }
+}
@@ -27,6 +27,7 @@ pub enum EspressoTransactionKind {
     GENESIS,
     CAP(reef::cap::TransactionKind),
     REWARD,
+    APPDATA,
 }
 
 impl traits::TransactionKind for EspressoTransactionKind {
@@ -103,6 +104,7 @@ impl EspressoTransaction {
             }
             Self::Genesis(_) => Err(ViewingError::NoViewingMemos),
             Self::Reward(_) => Err(ViewingError::NoViewingMemos),
+            Self::AppData(_) => Err(ViewingError::NoViewingMemos),
         }
     }
 
@@ -112,6 +114,7 @@ impl EspressoTransaction {
             Self::Genesis(txn) => txn.output_commitments(),
             Self::CAP(txn) => txn.output_commitments(),
             Self::Reward(txn) => vec![txn.output_commitment()],
+            Self::AppData(_) => vec![],
         }
     }
 
@@ -121,6 +124,7 @@ impl EspressoTransaction {
             Self::Genesis(txn) => Some(txn.output_openings()),
             Self::CAP(txn) => txn.output_openings(), // returns None
             Self::Reward(txn) => Some(vec![txn.output_opening()]),
+            Self::AppData(_) => Some(vec![]),
         }
     }
 
@@ -135,6 +139,7 @@ impl EspressoTransaction {
             Self::Genesis(_) => EspressoTransactionKind::GENESIS,
             Self::CAP(txn) => EspressoTransactionKind::CAP(txn.kind()),
             Self::Reward(_) => EspressoTransactionKind::REWARD,
+            Self::AppData(_) => EspressoTransactionKind::APPDATA,
         }
     }
 
@@ -144,6 +149,7 @@ impl EspressoTransaction {
             Self::Genesis(txn) => txn.output_len(),
             Self::CAP(txn) => txn.output_len(),
             Self::Reward(_) => 1,
+            Self::AppData(_) => 0,
         }
     }
 
@@ -153,6 +159,7 @@ impl EspressoTransaction {
             Self::Genesis(_) => vec![],
             Self::CAP(txn) => txn.input_nullifiers(),
             Self::Reward(_) => vec![],
+            Self::AppData(_) => vec![],
         }
     }
 
@@ -201,6 +208,7 @@ impl traits::Transaction for ElaboratedTransaction {
                 .zip(proofs.clone())
                 .collect(),
             EspressoTxnHelperProofs::Reward(_) => vec![], // no proven nullifiers
+            EspressoTxnHelperProofs::AppData => vec![],
         }
     }
 
@@ -292,8 +300,13 @@ impl traits::Validator for ValidatorState {
         block: Self::Block,
         proof: Self::Proof,
     ) -> Result<(Vec<u64>, MerkleTree), ValidationError> {
-        let outputs =
-            self.validate_and_apply(&proof, block.parent_state, block.block, block.proofs)?;
+        let outputs = self.validate_and_apply(
+            &proof,
+            block.parent_state,
+            block.block,
+            block.proofs,
+            block.memos,
+        )?;
         Ok((outputs.uids, outputs.record_proofs))
     }
 }
@@ -2,11 +2,15 @@
 // Copyright (c) 2022 Espresso Systems (espressosys.com)
 // This file is part of the Espresso library.
 
+pub mod app_data;
+pub mod certificate;
 pub mod genesis;
 pub mod kv_merkle_tree;
 pub mod ledger;
 pub mod lw_persistence;
 pub mod merkle_tree;
+pub mod metrics;
+pub mod proposals;
 pub mod reward;
 pub mod set_merkle_tree;
 pub mod stake_table;
@@ -0,0 +1,246 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Espresso library.
+
+//! Prometheus metrics for Espresso nodes.
+//!
+//! Each node has its own registry, shared by its validator and its query service, which run in the
+//! same process and are scraped together. Components record metrics through clones of the node's
+//! [Metrics], and whichever component serves the `/metrics` endpoint renders the registry using
+//! [Metrics::encode].
+
+use crate::state::ValidationError;
+use prometheus_client::{
+    encoding::text::{self, Encode},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::Registry,
+};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Encode)]
+pub struct ValidationErrorLabels {
+    pub error: String,
+}
+
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Arc<Registry>,
+    /// Blocks decided by consensus.
+    pub decided_blocks: Counter,
+    /// Blocks proposed to this node, as recorded by the [proposal tracker](crate::proposals).
+    pub proposed_blocks: Counter,
+    /// Views which timed out before reaching a decision.
+    pub view_timeouts: Counter,
+    /// Time from the proposal of a block until it is decided, in seconds.
+    pub decide_latency: Histogram,
+    /// Transactions submitted through this node which have not yet been included in a block.
+    pub mempool_transactions: Gauge,
+    /// Submitted transactions rejected by validation, labeled by the kind of error.
+    pub validation_failures: Family<ValidationErrorLabels, Counter>,
+    /// Time taken by the query service to append a decided block to its data sources, in seconds.
+    pub esqs_append_latency: Histogram,
+    /// Clients currently subscribed to the query service event stream.
+    pub event_subscribers: Gauge,
+    /// Time taken to commit the query service's persistent storage, in seconds.
+    pub store_commit_time: Histogram,
+    /// Reward collection transactions submitted by the reward daemon.
+    pub reward_submissions: Counter,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let mut registry = Registry::with_prefix("espresso");
+
+        let decided_blocks = Counter::default();
+        registry.register(
+            "decided_blocks",
+            "Blocks decided by consensus",
+            Box::new(decided_blocks.clone()),
+        );
+        let proposed_blocks = Counter::default();
+        registry.register(
+            "proposed_blocks",
+            "Blocks proposed to this node, counted once per view",
+            Box::new(proposed_blocks.clone()),
+        );
+        let view_timeouts = Counter::default();
+        registry.register(
+            "view_timeouts",
+            "Views which timed out before reaching a decision",
+            Box::new(view_timeouts.clone()),
+        );
+        let decide_latency = Histogram::new(exponential_buckets(0.25, 2.0, 10));
+        registry.register(
+            "decide_latency_seconds",
+            "Time from the proposal of a block until it is decided",
+            Box::new(decide_latency.clone()),
+        );
+        let mempool_transactions = Gauge::default();
+        registry.register(
+            "mempool_transactions",
+            "Transactions submitted through this node which are not yet in a block",
+            Box::new(mempool_transactions.clone()),
+        );
+        let validation_failures = Family::<ValidationErrorLabels, Counter>::default();
+        registry.register(
+            "validation_failures",
+            "Submitted transactions rejected by validation, by kind of error",
+            Box::new(validation_failures.clone()),
+        );
+        let esqs_append_latency = Histogram::new(exponential_buckets(0.001, 2.0, 14));
+        registry.register(
+            "esqs_append_latency_seconds",
+            "Time taken by the query service to append a decided block",
+            Box::new(esqs_append_latency.clone()),
+        );
+        let event_subscribers = Gauge::default();
+        registry.register(
+            "esqs_event_subscribers",
+            "Clients subscribed to the query service event stream",
+            Box::new(event_subscribers.clone()),
+        );
+        let store_commit_time = Histogram::new(exponential_buckets(0.001, 2.0, 14));
+        registry.register(
+            "esqs_store_commit_seconds",
+            "Time taken to commit the query service's persistent storage",
+            Box::new(store_commit_time.clone()),
+        );
+        let reward_submissions = Counter::default();
+        registry.register(
+            "reward_submissions",
+            "Reward collection transactions submitted by the reward daemon",
+            Box::new(reward_submissions.clone()),
+        );
+
+        Self {
+            registry: Arc::new(registry),
+            decided_blocks,
+            proposed_blocks,
+            view_timeouts,
+            decide_latency,
+            mempool_transactions,
+            validation_failures,
+            esqs_append_latency,
+            event_subscribers,
+            store_commit_time,
+            reward_submissions,
+        }
+    }
+
+    pub fn record_validation_failure(&self, err: &ValidationError) {
+        let error = <&'static str>::from(err).to_string();
+        self.validation_failures
+            .get_or_create(&ValidationErrorLabels { error })
+            .inc();
+    }
+
+    /// Record the decision of a block which was proposed at `timestamp`.
+    ///
+    /// `timestamp` is in nanoseconds since the Unix epoch, as in a HotShot leaf.
+    pub fn record_decide(&self, timestamp: i128) {
+        self.decided_blocks.inc();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as i128;
+        // Clocks on different nodes may disagree, so the latency could appear negative.
+        let latency = (now - timestamp).max(0);
+        self.decide_latency.observe(latency as f64 / 1e9);
+    }
+
+    pub fn record_view_timeout(&self) {
+        self.view_timeouts.inc();
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    pub fn encode(&self) -> String {
+        let mut buf = Vec::new();
+        text::encode(&mut buf, &self.registry).expect("writing to a Vec cannot fail");
+        String::from_utf8(buf).expect("Prometheus text encoding is UTF-8")
+    }
+}
+
+/// Counts an event subscriber for as long as it is alive.
+pub struct SubscriberGuard(Gauge);
+
+impl SubscriberGuard {
+    pub fn new(metrics: &Metrics) -> Self {
+        metrics.event_subscribers.inc();
+        Self(metrics.event_subscribers.clone())
+    }
+}
+
+impl Drop for SubscriberGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::ValidationError;
+
+    /// The value of the sample for `name` in the rendered metrics.
+    fn sample(metrics: &Metrics, name: &str) -> f64 {
+        metrics
+            .encode()
+            .lines()
+            .find_map(|line| line.strip_prefix(&format!("{} ", name)))
+            .unwrap_or_else(|| panic!("no sample for {}", name))
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_record_decide_and_timeout() {
+        let metrics = Metrics::new();
+        metrics.record_decide(0);
+        metrics.record_view_timeout();
+        assert_eq!(sample(&metrics, "espresso_decided_blocks_total"), 1.);
+        assert_eq!(sample(&metrics, "espresso_view_timeouts_total"), 1.);
+
+        // Each node has its own registry.
+        let other = Metrics::new();
+        assert_eq!(sample(&other, "espresso_decided_blocks_total"), 0.);
+
+        // Clones share a registry.
+        metrics.clone().record_view_timeout();
+        assert_eq!(sample(&metrics, "espresso_view_timeouts_total"), 2.);
+    }
+
+    #[test]
+    fn test_validation_failures_by_variant() {
+        let metrics = Metrics::new();
+        let err = ValidationError::BadMerkleRoot {};
+        let label = <&'static str>::from(&err);
+        metrics.record_validation_failure(&err);
+        assert_eq!(
+            sample(
+                &metrics,
+                &format!("espresso_validation_failures_total{{error=\"{}\"}}", label)
+            ),
+            1.
+        );
+    }
+
+    #[test]
+    fn test_subscriber_guard() {
+        let metrics = Metrics::new();
+        let guard = SubscriberGuard::new(&metrics);
+        assert_eq!(sample(&metrics, "espresso_esqs_event_subscribers"), 1.);
+        drop(guard);
+        assert_eq!(sample(&metrics, "espresso_esqs_event_subscribers"), 0.);
+    }
+}
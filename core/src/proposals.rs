@@ -0,0 +1,133 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Espresso library.
+
+//! Tracking of blocks proposed to consensus.
+//!
+//! HotShot does not report proposals in its event stream, so each node keeps its own tracker, which
+//! its network layer fills in by view number as proposals are sent and received. When a `Decide`
+//! event arrives, each pending proposal from a view up to the newest decided view is resolved:
+//! either it was decided, or it was abandoned.
+
+use crate::state::ElaboratedBlock;
+use std::collections::{btree_map::Entry, BTreeMap, BTreeSet};
+use std::mem;
+
+#[derive(Debug, Default)]
+pub struct ProposalTracker {
+    pending: BTreeMap<u64, ElaboratedBlock>,
+    /// The newest view whose proposals have been resolved.
+    resolved_view: Option<u64>,
+    proposed: u64,
+    abandoned: u64,
+}
+
+impl ProposalTracker {
+    /// Record a block proposed in `view`.
+    ///
+    /// Each view has at most one proposal, so a block seen again in the same view, or in a view
+    /// which has already been resolved, is ignored. Returns whether the proposal was new.
+    pub fn record(&mut self, view: u64, block: &ElaboratedBlock) -> bool {
+        if matches!(self.resolved_view, Some(resolved) if view <= resolved) {
+            return false;
+        }
+        match self.pending.entry(view) {
+            Entry::Vacant(e) => {
+                e.insert(block.clone());
+                self.proposed += 1;
+                true
+            }
+            Entry::Occupied(_) => false,
+        }
+    }
+
+    /// Resolve pending proposals given the views of newly decided blocks.
+    ///
+    /// Every pending proposal from a view up to the newest of `decided_views` is removed, and those
+    /// whose views were not decided are counted as abandoned. Resolving the same views again has no
+    /// effect, so every consumer of the `Decide` event stream may call this.
+    pub fn decide(&mut self, decided_views: impl IntoIterator<Item = u64>) {
+        let decided_views = decided_views.into_iter().collect::<BTreeSet<_>>();
+        let newest = match decided_views.iter().next_back() {
+            Some(view) => *view,
+            None => return,
+        };
+        if matches!(self.resolved_view, Some(resolved) if newest <= resolved) {
+            return;
+        }
+        let still_pending = self.pending.split_off(&(newest + 1));
+        for view in mem::replace(&mut self.pending, still_pending).into_keys() {
+            if !decided_views.contains(&view) {
+                self.abandoned += 1;
+            }
+        }
+        self.resolved_view = Some(newest);
+    }
+
+    /// Blocks proposed in views which have not been resolved yet, by view number.
+    pub fn pending(&self) -> &BTreeMap<u64, ElaboratedBlock> {
+        &self.pending
+    }
+
+    /// The number of proposals recorded since this process started.
+    pub fn proposed_count(&self) -> u64 {
+        self.proposed
+    }
+
+    /// The number of recorded proposals which were abandoned.
+    pub fn abandoned_count(&self) -> u64 {
+        self.abandoned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::ValidatorState;
+
+    fn block() -> ElaboratedBlock {
+        ElaboratedBlock::new(ValidatorState::default().commit())
+    }
+
+    #[test]
+    fn test_proposal_tracking() {
+        let mut tracker = ProposalTracker::default();
+        let block = block();
+
+        // Proposals are counted once per view.
+        tracker.record(1, &block);
+        tracker.record(1, &block);
+        tracker.record(2, &block);
+        tracker.record(3, &block);
+        tracker.record(5, &block);
+        assert_eq!(tracker.proposed_count(), 4);
+        assert_eq!(
+            tracker.pending().keys().copied().collect::<Vec<_>>(),
+            vec![1, 2, 3, 5]
+        );
+
+        // Deciding views 1 and 3 abandons view 2, but leaves view 5 pending.
+        tracker.decide([1, 3]);
+        assert_eq!(tracker.abandoned_count(), 1);
+        assert_eq!(
+            tracker.pending().keys().copied().collect::<Vec<_>>(),
+            vec![5]
+        );
+
+        // Resolving the same views again, or proposals from resolved views, changes nothing.
+        tracker.decide([1, 3]);
+        tracker.record(2, &block);
+        assert_eq!(tracker.proposed_count(), 4);
+        assert_eq!(tracker.abandoned_count(), 1);
+
+        // A decided view which was never recorded as a proposal is not counted either way.
+        tracker.decide([4]);
+        assert_eq!(tracker.abandoned_count(), 1);
+        assert_eq!(
+            tracker.pending().keys().copied().collect::<Vec<_>>(),
+            vec![5]
+        );
+        tracker.decide([6]);
+        assert_eq!(tracker.abandoned_count(), 2);
+        assert!(tracker.pending().is_empty());
+    }
+}
@@ -21,6 +21,7 @@ pub use crate::util::canonical;
 pub use hotshot_types::data::ViewNumber as ConsensusTime;
 pub use state_comm::LedgerStateCommitment;
 
+use crate::app_data::{AppDataNote, NamespaceTableRoot};
 use crate::genesis::GenesisNote;
 use crate::stake_table::{
     CommittableStakeTableSetCommitment, CommittableStakeTableSetFrontier, StakeTableCommitment,
@@ -58,17 +59,30 @@ use std::sync::Arc;
 use typenum::U32;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
-/// A transaction tht can be either a CAP transaction or a collect reward transaction
+/// A transaction tht can be either a CAP transaction, a collect reward transaction, or opaque
+/// application data
 pub enum EspressoTransaction {
     Genesis(GenesisNote),
     CAP(TransactionNote),
     Reward(Box<CollectRewardNote>),
+    AppData(AppDataNote),
 }
 
 impl EspressoTransaction {
     pub fn is_genesis(&self) -> bool {
         matches!(self, Self::Genesis(_))
     }
+
+    /// The expiration height bound to this transaction's proof, if it has one.
+    ///
+    /// Only transfer notes have one: it is a public input to the transfer proof, which is checked
+    /// against the height of the block including the note.
+    pub fn proof_bound_valid_until(&self) -> Option<u64> {
+        match self {
+            Self::CAP(TransactionNote::Transfer(note)) => Some(note.aux_info.valid_until),
+            _ => None,
+        }
+    }
 }
 
 impl CanonicalSerialize for EspressoTransaction {
@@ -89,6 +103,11 @@ impl CanonicalSerialize for EspressoTransaction {
                 writer.write_all(&[flag])?;
                 <GenesisNote as CanonicalSerialize>::serialize(genesis_note, &mut writer)
             }
+            Self::AppData(app_data_note) => {
+                let flag = 3;
+                writer.write_all(&[flag])?;
+                <AppDataNote as CanonicalSerialize>::serialize(app_data_note, &mut writer)
+            }
         }
     }
 
@@ -97,6 +116,7 @@ impl CanonicalSerialize for EspressoTransaction {
             Self::CAP(txn) => txn.serialized_size() + 1,
             Self::Reward(reward) => reward.serialized_size() + 1,
             Self::Genesis(genesis) => genesis.serialized_size() + 1,
+            Self::AppData(app_data) => app_data.serialized_size() + 1,
         }
     }
 }
@@ -118,6 +138,9 @@ impl CanonicalDeserialize for EspressoTransaction {
             2 => Ok(Self::Genesis(
                 <GenesisNote as CanonicalDeserialize>::deserialize(&mut r)?,
             )),
+            3 => Ok(Self::AppData(
+                <AppDataNote as CanonicalDeserialize>::deserialize(&mut r)?,
+            )),
             _ => Err(SerializationError::InvalidData),
         }
     }
@@ -129,6 +152,7 @@ pub enum EspressoTxnHelperProofs {
     Genesis,
     CAP(Vec<SetMerkleProof>),
     Reward(Box<RewardNoteProofs>),
+    AppData,
 }
 
 impl CanonicalSerialize for EspressoTxnHelperProofs {
@@ -148,14 +172,20 @@ impl CanonicalSerialize for EspressoTxnHelperProofs {
                 writer.write_all(&[2])?;
                 Ok(())
             }
+            Self::AppData => {
+                writer.write_all(&[3])?;
+                Ok(())
+            }
         }
     }
 
     fn serialized_size(&self) -> usize {
-        1 + match &self {
-            Self::CAP(merkle_proofs) => merkle_proofs.serialized_size(),
-            Self::Reward(reward_proofs) => reward_proofs.serialized_size(),
-            Self::Genesis => 0,
+        // Each variant is written as a flag byte followed by its contents, if any.
+        match &self {
+            Self::CAP(merkle_proofs) => merkle_proofs.serialized_size() + 1,
+            Self::Reward(reward_proofs) => reward_proofs.serialized_size() + 1,
+            Self::Genesis => 1,
+            Self::AppData => 1,
         }
     }
 }
@@ -175,6 +205,7 @@ impl CanonicalDeserialize for EspressoTxnHelperProofs {
                 <RewardNoteProofs as CanonicalDeserialize>::deserialize(&mut r)?,
             ))),
             2 => Ok(Self::Genesis),
+            3 => Ok(Self::AppData),
             _ => Err(SerializationError::InvalidData),
         }
     }
@@ -418,7 +449,7 @@ impl ConsensusBlock for ElaboratedBlock {
 }
 
 /// Validation errors.
-#[derive(Debug, Snafu, Serialize, Deserialize)]
+#[derive(Debug, Snafu, Serialize, Deserialize, strum_macros::IntoStaticStr)]
 #[snafu(visibility(pub(crate)))]
 pub enum ValidationError {
     /// A record was already spent.
@@ -511,6 +542,33 @@ pub enum ValidationError {
 
     /// Error when calculating block fees
     BadFeeCalculation {},
+
+    /// A transaction was included in a block after its expiration height.
+    Expired {
+        valid_until: u64,
+        block_height: u64,
+    },
+
+    /// A transaction's receiver memos do not match its outputs, or are not signed by it.
+    BadReceiverMemos {},
+
+    /// A block contains more transactions than the chain allows.
+    TooManyTransactions {
+        count: u64,
+        max: u64,
+    },
+
+    /// The transactions in a block are larger in total than the chain allows.
+    BlockTooLarge {
+        bytes: u64,
+        max: u64,
+    },
+
+    /// The transactions in a block create more outputs in total than the chain allows.
+    TooManyOutputs {
+        count: u64,
+        max: u64,
+    },
 }
 
 pub(crate) mod ser_display {
@@ -576,19 +634,66 @@ impl Clone for ValidationError {
             BadStakeTableProof {} => BadStakeTableProof {},
             BadStakeTableCommitmentsProof {} => BadStakeTableCommitmentsProof {},
             BadFeeCalculation {} => BadFeeCalculation {},
+            Expired {
+                valid_until,
+                block_height,
+            } => Expired {
+                valid_until: *valid_until,
+                block_height: *block_height,
+            },
+            BadReceiverMemos {} => BadReceiverMemos {},
+            TooManyTransactions { count, max } => TooManyTransactions {
+                count: *count,
+                max: *max,
+            },
+            BlockTooLarge { bytes, max } => BlockTooLarge {
+                bytes: *bytes,
+                max: *max,
+            },
+            TooManyOutputs { count, max } => TooManyOutputs {
+                count: *count,
+                max: *max,
+            },
         }
     }
 }
 
-impl Committable for Block {
-    fn commit(&self) -> commit::Commitment<Self> {
-        commit::RawCommitmentBuilder::new("Block Comm")
+impl Block {
+    /// A commitment to the list of transactions in this block.
+    pub fn transactions_commitment(&self) -> commit::Commitment<Self> {
+        commit::RawCommitmentBuilder::new("Block Txns")
             .array_field(
                 "txns",
                 &self.0.iter().map(|x| x.commit()).collect::<Vec<_>>(),
             )
             .finalize()
     }
+
+    /// Compute a block commitment from the commitment to its transactions and the root of its
+    /// namespace table.
+    ///
+    /// This allows a block commitment to be checked without having every transaction in the block.
+    pub fn commitment_from_parts(
+        transactions: commit::Commitment<Self>,
+        namespace_table: NamespaceTableRoot,
+    ) -> commit::Commitment<Self> {
+        commit::RawCommitmentBuilder::new("Block Comm")
+            .field("txns", transactions)
+            .field("namespaces", namespace_table)
+            .finalize()
+    }
+}
+
+impl Committable for Block {
+    /// Commit to the transactions in this block and to its namespace table.
+    ///
+    /// Including the namespace table lets clients check that they have every transaction from a
+    /// namespace without downloading the whole block. This is part of protocol version 0.2 (see
+    /// the crate README).
+    fn commit(&self) -> commit::Commitment<Self> {
+        let transactions = self.transactions_commitment();
+        Self::commitment_from_parts(transactions, self.namespace_table_root(transactions))
+    }
 }
 
 /// A cryptographic commitment to a transaction
@@ -1150,7 +1255,9 @@ impl<T: CanonicalDeserialize> CanonicalDeserialize for ArcSer<T> {
 pub struct ChainVariables {
     /// The version of the protocol this chain is currently using.
     ///
-    /// The protocol version can be changed by committing an update transaction.
+    /// New chains use the version of this crate, which is bumped whenever block validation or
+    /// commitments change incompatibly. The protocol version can be changed by committing an
+    /// update transaction.
     pub protocol_version: (u16, u16, u16),
 
     /// A unique identifier for this chain, to prevent cross-chain replay attacks.
@@ -1166,6 +1273,46 @@ pub struct ChainVariables {
 
     /// Committee size
     pub committee_size: u64,
+
+    /// Limits on the size of each block.
+    pub block_limits: BlockLimits,
+}
+
+/// Limits on the size of a block, enforced during validation.
+///
+/// These are part of the committed chain state, so that every validator agrees on them and a
+/// leader cannot propose a block larger than the rest of the network is willing to accept.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    CanonicalSerialize,
+    CanonicalDeserialize,
+    Arbitrary,
+)]
+pub struct BlockLimits {
+    /// The maximum number of transactions in a block.
+    pub max_transactions: u64,
+    /// The maximum total serialized size, in bytes, of the transactions in a block, including
+    /// their helper proofs and receiver memos.
+    pub max_bytes: u64,
+    /// The maximum total number of record outputs of the transactions in a block.
+    pub max_outputs: u64,
+}
+
+impl Default for BlockLimits {
+    fn default() -> Self {
+        Self {
+            max_transactions: 10000,
+            max_bytes: 1 << 26,
+            max_outputs: 40000,
+        }
+    }
 }
 
 #[tagged_blob("VRFSEED")]
@@ -1220,6 +1367,9 @@ impl Committable for ChainVariables {
             .var_size_bytes(&canonical::serialize(&self.verif_crs).unwrap())
             .fixed_size_bytes(self.vrf_seed.as_ref())
             .u64_field("committee size", self.committee_size)
+            .u64_field("max block transactions", self.block_limits.max_transactions)
+            .u64_field("max block bytes", self.block_limits.max_bytes)
+            .u64_field("max block outputs", self.block_limits.max_outputs)
             .finalize()
     }
 }
@@ -1232,6 +1382,7 @@ impl<'a> Arbitrary<'a> for ChainVariables {
             verif_crs: VERIF_CRS.clone().into(),
             vrf_seed: u.arbitrary()?,
             committee_size: u.arbitrary()?,
+            block_limits: u.arbitrary()?,
         })
     }
 }
@@ -1266,8 +1417,15 @@ impl ChainVariables {
                 .finalize()
                 .into(),
             committee_size,
+            block_limits: Default::default(),
         }
     }
+
+    /// Replace the default block size limits.
+    pub fn with_block_limits(mut self, block_limits: BlockLimits) -> Self {
+        self.block_limits = block_limits;
+        self
+    }
 }
 
 /// The working state of the ledger
@@ -1425,6 +1583,12 @@ impl ValidatorState {
         self.past_nullifiers.count()
     }
 
+    /// Whether a transaction built against the record Merkle root `root` can still be validated.
+    pub fn is_recent_merkle_root(&self, root: &NodeValue) -> bool {
+        self.record_merkle_commitment.root_value == *root
+            || self.past_record_merkle_roots.0.contains(root)
+    }
+
     /// Validate a block of elaborated transactions
     ///
     /// Checks the following
@@ -1433,11 +1597,17 @@ impl ValidatorState {
     ///   outputs
     /// - The Merkle roots are recent enough
     /// - The zero knowledge proofs in each of the transactions verifies
+    /// - None of the transactions has expired
+    /// - The block is within the size limits in [ChainVariables::block_limits]
     ///
     /// If valid, return the input transactions and proofs, otherwise
     /// return a validation error. A future implementation may return
     /// only the valid transactions and proofs.
     ///
+    /// The transactions are returned, and so applied, in the order they were proposed, so record
+    /// UIDs are assigned in block order and the new state's `prev_block` commits to the block as
+    /// proposed. This is part of protocol version 0.2 (see the crate README).
+    ///
     /// # Errors
     /// - [ValidationError::BadMerkleRoot]
     /// - [ValidationError::BadNullifierProof]
@@ -1447,6 +1617,10 @@ impl ValidatorState {
     /// - [ValidationError::UnsupportedTransferSize]
     /// - [ValidationError::RewardAlreadyCollected]
     /// - [ValidationError::RewardAmountTooLarge]
+    /// - [ValidationError::Expired]
+    /// - [ValidationError::TooManyTransactions]
+    /// - [ValidationError::BlockTooLarge]
+    /// - [ValidationError::TooManyOutputs]
     ///
     pub fn validate_block_check(
         &self,
@@ -1454,6 +1628,7 @@ impl ValidatorState {
         parent_state: LedgerStateCommitment,
         txns: Block,
         txns_helper_proofs: Vec<EspressoTxnHelperProofs>,
+        txns_memos: Vec<Option<(Vec<ReceiverMemo>, Signature)>>,
     ) -> Result<(Block, NullifierProofs, CollectedRewardsProofs), ValidationError> {
         // The block must be intended for this state.
         if parent_state != self.commit() {
@@ -1463,6 +1638,21 @@ impl ValidatorState {
         if *now < self.prev_commit_time {
             return Err(ValidationError::InvalidTime);
         }
+        // None of the transactions may have expired. The new block will have height
+        // `self.block_height`. The proof check below would reject these transactions anyway, since
+        // the expiration height is a public input to the proof; checking it first reports why.
+        for valid_until in txns
+            .0
+            .iter()
+            .filter_map(|txn| txn.proof_bound_valid_until())
+        {
+            if valid_until < self.block_height {
+                return Err(ValidationError::Expired {
+                    valid_until,
+                    block_height: self.block_height,
+                });
+            }
+        }
 
         // Check if this is a genesis block. If it is, validation is trivial and we can skip the
         // rest of this. If it is not, then we will reject the block later if it contains any
@@ -1478,6 +1668,45 @@ impl ValidatorState {
             return Ok((txns, vec![], vec![]));
         }
 
+        // The block must not exceed the size limits of the chain.
+        let limits = &self.chain.block_limits;
+        let count = txns.0.len() as u64;
+        if count > limits.max_transactions {
+            return Err(ValidationError::TooManyTransactions {
+                count,
+                max: limits.max_transactions,
+            });
+        }
+        // Count everything a node has to receive and store for the block, not just the transactions.
+        let bytes = txns
+            .0
+            .iter()
+            .map(|txn| txn.serialized_size())
+            .chain(txns_helper_proofs.iter().map(|pfs| pfs.serialized_size()))
+            .chain(txns_memos.iter().map(|memos| memos.serialized_size()))
+            .map(|size| size as u64)
+            .sum::<u64>();
+        if bytes > limits.max_bytes {
+            return Err(ValidationError::BlockTooLarge {
+                bytes,
+                max: limits.max_bytes,
+            });
+        }
+        let outputs = txns
+            .0
+            .iter()
+            .map(|txn| txn.output_len() as u64)
+            .sum::<u64>();
+        if outputs > limits.max_outputs {
+            return Err(ValidationError::TooManyOutputs {
+                count: outputs,
+                max: limits.max_outputs,
+            });
+        }
+
+        // The validated block keeps the original order of the transactions, so that the namespace
+        // table it commits to matches the one computed from the block as proposed.
+        let block = txns.clone();
         let mut cap_txns = vec![];
         let mut reward_txns = vec![];
         let mut cap_nulls_proofs = vec![];
@@ -1495,6 +1724,10 @@ impl ValidatorState {
                     reward_txns.push(reward_txn);
                     rewards_proofs.push(reward_pfs);
                 }
+                (EspressoTransaction::AppData(_), EspressoTxnHelperProofs::AppData) => {
+                    // Application data is opaque to validators. Beyond the block size limits,
+                    // there is nothing to check.
+                }
                 (EspressoTransaction::Genesis(_), _) => {
                     return Err(ValidationError::UnexpectedGenesis)
                 }
@@ -1555,9 +1788,7 @@ impl ValidatorState {
             let mut merkle_roots = vec![];
             for cap_note in cap_txns.iter() {
                 let note_mt_root = cap_note.merkle_root();
-                if self.record_merkle_commitment.root_value == note_mt_root
-                    || self.past_record_merkle_roots.0.contains(&note_mt_root)
-                {
+                if self.is_recent_merkle_root(&note_mt_root) {
                     merkle_roots.push(note_mt_root)
                 } else {
                     return Err(BadMerkleRoot {});
@@ -1574,10 +1805,7 @@ impl ValidatorState {
         let mut verified_rewards_proofs = vec![];
         {
             // verify rewards collection transactions
-            for (pfs, txn) in rewards_proofs
-                .into_iter()
-                .zip(reward_txns.clone().into_iter())
-            {
+            for (pfs, txn) in rewards_proofs.into_iter().zip(reward_txns) {
                 let latest_reward = CollectedRewards {
                     staking_key: txn.staking_key(),
                     time: txn.time(),
@@ -1619,14 +1847,36 @@ impl ValidatorState {
                 ));
             }
         }
-        // assemble Block
-        let txns: Vec<_> = cap_txns
-            .into_iter()
-            .map(EspressoTransaction::CAP)
-            .chain(reward_txns.into_iter().map(EspressoTransaction::Reward))
-            .collect();
+        Ok((block, nullifiers_proofs, verified_rewards_proofs))
+    }
 
-        Ok((Block(txns), nullifiers_proofs, verified_rewards_proofs))
+    /// Check whether a single transaction would be valid in the next block built on this state.
+    ///
+    /// This performs the same checks as [ValidatorState::validate_block_check] on a block
+    /// containing only `txn`, without modifying the state. It also checks that the transaction's
+    /// receiver memos, if it has any, match its outputs and are signed by it. Memos are not part of
+    /// consensus, so this is only checked when a transaction is submitted.
+    ///
+    /// # Errors
+    /// - [ValidationError::BadReceiverMemos]
+    /// - any error from [ValidatorState::validate_block_check]
+    pub fn validate_transaction(&self, txn: &ElaboratedTransaction) -> Result<(), ValidationError> {
+        if let Some((memos, sig)) = &txn.memos {
+            match &txn.txn {
+                EspressoTransaction::CAP(note)
+                    if memos.len() == note.output_len()
+                        && note.verify_receiver_memos_signature(memos, sig).is_ok() => {}
+                _ => return Err(ValidationError::BadReceiverMemos {}),
+            }
+        }
+        self.validate_block_check(
+            &(self.prev_commit_time + 1),
+            self.commit(),
+            Block(vec![txn.txn.clone()]),
+            vec![txn.proofs.clone()],
+            vec![txn.memos.clone()],
+        )?;
+        Ok(())
     }
 
     /// Performs validation for a block, updating the ValidatorState.
@@ -1648,9 +1898,10 @@ impl ValidatorState {
         parent_state: LedgerStateCommitment,
         txns: Block,
         proofs: Vec<EspressoTxnHelperProofs>,
+        memos: Vec<Option<(Vec<ReceiverMemo>, Signature)>>,
     ) -> Result<ValidationOutputs, ValidationError> {
         let (txns, null_pfs, rewards) =
-            self.validate_block_check(now, parent_state, txns, proofs)?;
+            self.validate_block_check(now, parent_state, txns, proofs, memos)?;
         // If the block successfully validates, and the nullifier proofs apply correctly, the
         // remaining (mutating) operations cannot fail, as this would result in an inconsistent
         // state. No operations after the first assignement to a member of self have a possible
@@ -1845,6 +2096,7 @@ impl ConsensusState for ValidatorState {
             block.parent_state,
             block.block.clone(),
             block.proofs.clone(),
+            block.memos.clone(),
         )
         .is_ok()
     }
@@ -1860,6 +2112,7 @@ impl ConsensusState for ValidatorState {
             block.parent_state,
             block.block.clone(),
             block.proofs.clone(),
+            block.memos.clone(),
         )?;
         Ok(state)
     }
@@ -1137,6 +1137,7 @@ impl MultiXfrTestState {
             blk.parent_state,
             blk.block.clone(),
             blk.proofs.clone(),
+            blk.memos.clone(),
         )?;
         let new_state = self.validator.append(&blk, now).unwrap();
 
@@ -1233,7 +1234,9 @@ pub fn crypto_rng_from_seed(seed: [u8; 32]) -> ChaChaRng {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::app_data::{AppDataNote, NamespaceId};
     use crate::stake_table::{StakeTableMap, StakeTableSetMT};
+    use ark_serialize::CanonicalSerialize;
     use async_std::sync::Arc;
     use commit::Committable;
     use jf_cap::structs::{NoteType, Nullifier};
@@ -1542,6 +1545,28 @@ mod tests {
         // against an updated nullifier set.
         for (i, tx) in txns.into_iter().enumerate() {
             let kixs = tx.keys_and_memos.into_iter().map(|(kix, _)| kix).collect();
+            // Simulating the transaction against the current state should predict the outcome.
+            let simulated = state.validator.validate_transaction(&tx.transaction);
+            // The same transaction is rejected if it expired before the next block.
+            if let Some(expired) = state.validator.block_height.checked_sub(1) {
+                let mut expired_tx = tx.transaction.clone();
+                if let EspressoTransaction::CAP(TransactionNote::Transfer(note)) =
+                    &mut expired_tx.txn
+                {
+                    note.aux_info.valid_until = expired;
+                }
+                assert!(matches!(
+                    state.validator.validate_transaction(&expired_tx),
+                    Err(ValidationError::Expired { .. })
+                ));
+            }
+            // Receiver memos must match the outputs of the transaction.
+            let mut bad_memos_tx = tx.transaction.clone();
+            bad_memos_tx.memos.as_mut().unwrap().0.pop();
+            assert!(matches!(
+                state.validator.validate_transaction(&bad_memos_tx),
+                Err(ValidationError::BadReceiverMemos {})
+            ));
             let mut blk = state.validator.next_block();
             let _ = state.try_add_transaction(
                 &mut blk,
@@ -1557,8 +1582,10 @@ mod tests {
                 TxnPrintInfo::new_no_time(i, 2),
             );
             if i == 0 || !double_spend {
+                simulated.unwrap();
                 res.unwrap();
             } else {
+                simulated.unwrap_err();
                 res.unwrap_err();
             }
         }
@@ -1574,6 +1601,94 @@ mod tests {
         test_sliding_nullifiers(true);
     }
 
+    #[test]
+    fn test_app_data() {
+        let mut validator = ValidatorState::new(
+            ChainVariables::new(42, VERIF_CRS.clone(), SORTITION_PARAMETER),
+            MerkleTree::new(MERKLE_HEIGHT).unwrap(),
+            StakeTableCommitment(StakeTableMap::EmptySubtree.hash()),
+            Amount::from(0u64),
+            StakeTableSetMT::new(MERKLE_HEIGHT).unwrap(),
+        );
+        let txns: Vec<ElaboratedTransaction> = vec![
+            AppDataNote::new(NamespaceId(1), vec![1, 2, 3]).into(),
+            AppDataNote::new(NamespaceId(2), vec![]).into(),
+        ];
+
+        // Application data is committed as part of the block.
+        let block = Block(txns.iter().map(|txn| txn.txn.clone()).collect());
+        assert_ne!(block.commit(), Block(vec![txns[0].txn.clone()]).commit());
+
+        // It validates without creating any records.
+        let outputs = validator
+            .validate_and_apply(
+                &(validator.prev_commit_time + 1),
+                validator.commit(),
+                block.clone(),
+                txns.iter().map(|txn| txn.proofs.clone()).collect(),
+                vec![None, None],
+            )
+            .unwrap();
+        assert!(outputs.uids.is_empty());
+        assert_eq!(validator.prev_block, block.commit());
+        assert_eq!(validator.transaction_count, 2);
+
+        // Helper proofs report the size they serialize to, so they are counted correctly against
+        // the block size limit.
+        for proofs in [
+            EspressoTxnHelperProofs::AppData,
+            EspressoTxnHelperProofs::Genesis,
+        ] {
+            assert_eq!(
+                proofs.serialized_size(),
+                canonical::serialize(&proofs).unwrap().len()
+            );
+        }
+    }
+
+    #[test]
+    fn test_namespace_proof() {
+        let block = Block(vec![
+            EspressoTransaction::AppData(AppDataNote::new(NamespaceId(1), vec![1])),
+            EspressoTransaction::AppData(AppDataNote::new(NamespaceId(2), vec![2])),
+            EspressoTransaction::AppData(AppDataNote::new(NamespaceId(1), vec![3])),
+        ]);
+        let comm = block.commit();
+        // The cached namespace table root is the root of the table built from scratch.
+        assert_eq!(block.commit(), comm);
+        assert_eq!(
+            comm,
+            crate::state::Block::commitment_from_parts(
+                block.transactions_commitment(),
+                block.namespace_table().hash()
+            )
+        );
+        let txns = |indices: &[u64]| {
+            indices
+                .iter()
+                .map(|i| (*i, block.0[*i as usize].clone()))
+                .collect::<Vec<_>>()
+        };
+
+        // Each namespace, including one which is absent, has a proof of its transactions.
+        for (namespace, expected) in [(1, vec![0, 2]), (2, vec![1]), (3, vec![])] {
+            let namespace = NamespaceId(namespace);
+            let (indices, proof) = block.namespace_proof(namespace);
+            assert_eq!(indices, expected);
+            assert!(proof.verify(comm, namespace, &txns(&indices)));
+        }
+
+        // The proof fails if a transaction is left out, added, or attributed to the wrong block.
+        let (_, proof) = block.namespace_proof(NamespaceId(1));
+        assert!(!proof.verify(comm, NamespaceId(1), &txns(&[0])));
+        assert!(!proof.verify(comm, NamespaceId(1), &txns(&[0, 1, 2])));
+        assert!(!proof.verify(
+            Block(block.0[..2].to_vec()).commit(),
+            NamespaceId(1),
+            &txns(&[0, 2])
+        ));
+    }
+
     #[test]
     #[allow(unused_variables)]
     fn test_2user() {
@@ -1703,7 +1818,7 @@ mod tests {
         println!("Transfer generated: {}s", now.elapsed().as_secs_f32());
         let now = Instant::now();
 
-        let nullifier_pfs = txn1
+        let nullifier_pfs: Vec<_> = txn1
             .inputs_nullifiers
             .iter()
             .map(|n| nullifiers.contains(*n).unwrap().1)
@@ -1719,18 +1834,70 @@ mod tests {
         let now = Instant::now();
 
         let new_recs = txn1.output_commitments.to_vec();
+        // Application data placed before the transfer must stay before it: the block is applied in
+        // the order it was proposed.
+        let block = Block(vec![
+            EspressoTransaction::AppData(AppDataNote::new(NamespaceId(1), vec![1])),
+            EspressoTransaction::CAP(TransactionNote::Transfer(Box::new(txn1))),
+        ]);
+        let block_comm = block.commit();
+        let helper_proofs = |nullifier_pfs| {
+            vec![
+                EspressoTxnHelperProofs::AppData,
+                EspressoTxnHelperProofs::CAP(nullifier_pfs),
+            ]
+        };
+
+        // A chain with tighter block limits rejects the same block.
+        let limits = BlockLimits::default();
+        for (block_limits, expected) in [
+            (
+                BlockLimits {
+                    max_transactions: 0,
+                    ..limits
+                },
+                "TooManyTransactions",
+            ),
+            (
+                BlockLimits {
+                    max_bytes: 0,
+                    ..limits
+                },
+                "BlockTooLarge",
+            ),
+            (
+                BlockLimits {
+                    max_outputs: 0,
+                    ..limits
+                },
+                "TooManyOutputs",
+            ),
+        ] {
+            let mut limited = validator.clone();
+            limited.chain.block_limits = block_limits;
+            let err = limited
+                .validate_block_check(
+                    &(limited.prev_commit_time + 1),
+                    limited.commit(),
+                    block.clone(),
+                    helper_proofs(nullifier_pfs.clone()),
+                    vec![None, None],
+                )
+                .unwrap_err();
+            assert_eq!(<&'static str>::from(err), expected);
+        }
 
         let new_uids = validator
             .validate_and_apply(
                 &(validator.prev_commit_time + 1),
                 validator.commit(),
-                Block(vec![EspressoTransaction::CAP(TransactionNote::Transfer(
-                    Box::new(txn1),
-                ))]),
-                vec![EspressoTxnHelperProofs::CAP(nullifier_pfs)],
+                block,
+                helper_proofs(nullifier_pfs),
+                vec![None, None],
             )
             .unwrap()
             .uids;
+        assert_eq!(validator.prev_block, block_comm);
 
         println!(
             "Transfer validated & applied: {}s",
@@ -2,11 +2,22 @@
 // Copyright (c) 2022 Espresso Systems (espressosys.com)
 // This file is part of the Espresso library.
 
+use crate::util::canonical;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
 use async_std::sync::Arc;
-use jf_cap::proof::{freeze, mint, transfer};
+use jf_cap::errors::TxnApiError;
+use jf_cap::proof::freeze::{self, FreezeProvingKey, FreezeVerifyingKey};
+use jf_cap::proof::mint::{self, MintProvingKey, MintVerifyingKey};
+use jf_cap::proof::transfer::{self, TransferProvingKey, TransferVerifyingKey};
+use jf_cap::proof::UniversalParam;
+use jf_cap::TransactionVerifyingKey;
 use key_set::{ProverKeySet, VerifierKeySet};
 use lazy_static::lazy_static;
 use reef::Ledger;
+use sha3::{Digest, Sha3_256};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 /// Height of the records Merkle tree
 pub const MERKLE_HEIGHT: u8 = 20 /*H*/;
@@ -14,60 +25,272 @@ pub const MERKLE_HEIGHT: u8 = 20 /*H*/;
 pub const SUPPORTED_TRANSFER_SIZES: [(usize, usize); 3] = [(1, 2), (2, 2), (3, 3)];
 pub const SUPPORTED_FREEZE_SIZES: [usize; 1] = [2];
 
+/// The length of the checksum at the start of a key cache file.
+const CHECKSUM_LEN: usize = 32;
+
 lazy_static! {
     pub static ref UNIVERSAL_PARAM: jf_cap::proof::UniversalParam =
         reef::cap::Ledger::srs().clone();
-    pub static ref VERIF_CRS: Arc<VerifierKeySet> = {
-        use jf_cap::TransactionVerifyingKey::*;
-        Arc::new(VerifierKeySet {
-            mint: Mint(
-                mint::preprocess(&UNIVERSAL_PARAM, MERKLE_HEIGHT)
-                    .expect("failed preprocess of mint circuit")
-                    .1,
-            ),
-            xfr: SUPPORTED_TRANSFER_SIZES
-                .iter()
-                .map(|&(inputs, outputs)| {
-                    Transfer(
-                        transfer::preprocess(&UNIVERSAL_PARAM, inputs, outputs, MERKLE_HEIGHT)
-                            .expect("failed preprocess of transfer circuit")
-                            .1,
-                    )
-                })
-                .collect(),
-            freeze: SUPPORTED_FREEZE_SIZES
-                .iter()
-                .map(|&inputs| {
-                    Freeze(
-                        freeze::preprocess(&UNIVERSAL_PARAM, inputs, MERKLE_HEIGHT)
-                            .expect("failed preprocess of freeze circuit")
-                            .1,
-                    )
-                })
-                .collect(),
-        })
+    pub static ref VERIF_CRS: Arc<VerifierKeySet> = SUPPORTED_KEYS.1.clone();
+    pub static ref PROVER_CRS: Arc<ProverKeySet<'static>> = SUPPORTED_KEYS.0.clone();
+    static ref SUPPORTED_KEYS: (Arc<ProverKeySet<'static>>, Arc<VerifierKeySet>) = {
+        let (prover, verifier) = supported_keys();
+        (Arc::new(prover), Arc::new(verifier))
     };
-    pub static ref PROVER_CRS: Arc<ProverKeySet<'static>> = {
-        Arc::new(ProverKeySet {
-            mint: mint::preprocess(&UNIVERSAL_PARAM, MERKLE_HEIGHT)
-                .expect("failed preprocess of mint circuit")
-                .0,
-            xfr: SUPPORTED_TRANSFER_SIZES
+}
+
+/// Keys for all the supported circuits.
+///
+/// The verifying keys decide which transactions are valid, so they are always generated from
+/// scratch and never touch the key cache, which is only used by [circuit_keys].
+fn supported_keys() -> (ProverKeySet<'static>, VerifierKeySet) {
+    CircuitKeys::preprocess(
+        &UNIVERSAL_PARAM,
+        &SUPPORTED_TRANSFER_SIZES,
+        &SUPPORTED_FREEZE_SIZES,
+    )
+    .expect("failed preprocess of circuits")
+    .into_key_sets()
+}
+
+/// The default directory for cached circuit keys.
+///
+/// This can be overridden with the environment variable `ESPRESSO_KEY_CACHE_PATH`.
+pub fn key_cache_path() -> PathBuf {
+    env::var_os("ESPRESSO_KEY_CACHE_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            dirs::data_local_dir()
+                .unwrap_or_else(|| env::current_dir().unwrap_or_else(|_| PathBuf::from("./")))
+                .join(".espresso")
+                .join("espresso")
+                .join("key-cache")
+        })
+}
+
+/// Proving and verifying keys for the mint circuit and each supported transfer and freeze arity.
+struct CircuitKeys<'a> {
+    mint: (MintProvingKey<'a>, MintVerifyingKey),
+    xfr: Vec<(TransferProvingKey<'a>, TransferVerifyingKey)>,
+    freeze: Vec<(FreezeProvingKey<'a>, FreezeVerifyingKey)>,
+}
+
+impl<'a> CircuitKeys<'a> {
+    fn preprocess(
+        univ_param: &'a UniversalParam,
+        xfr_sizes: &[(usize, usize)],
+        freeze_sizes: &[usize],
+    ) -> Result<Self, TxnApiError> {
+        let (mint_pk, mint_vk, _) = mint::preprocess(univ_param, MERKLE_HEIGHT)?;
+        Ok(Self {
+            mint: (mint_pk, mint_vk),
+            xfr: xfr_sizes
                 .iter()
                 .map(|&(inputs, outputs)| {
-                    transfer::preprocess(&UNIVERSAL_PARAM, inputs, outputs, MERKLE_HEIGHT)
-                        .expect("failed preprocess of transfer circuit")
-                        .0
+                    let (pk, vk, _) =
+                        transfer::preprocess(univ_param, inputs, outputs, MERKLE_HEIGHT)?;
+                    Ok((pk, vk))
                 })
-                .collect(),
-            freeze: SUPPORTED_FREEZE_SIZES
+                .collect::<Result<_, TxnApiError>>()?,
+            freeze: freeze_sizes
                 .iter()
                 .map(|&inputs| {
-                    freeze::preprocess(&UNIVERSAL_PARAM, inputs, MERKLE_HEIGHT)
-                        .expect("failed preprocess of freeze circuit")
-                        .0
+                    let (pk, vk, _) = freeze::preprocess(univ_param, inputs, MERKLE_HEIGHT)?;
+                    Ok((pk, vk))
                 })
-                .collect(),
+                .collect::<Result<_, TxnApiError>>()?,
         })
-    };
+    }
+
+    /// Load keys stored by [store](Self::store).
+    ///
+    /// The file must have a valid checksum and contain exactly the serialized keys, which are fully
+    /// checked as they are deserialized.
+    fn load(path: &Path) -> Result<Self, SerializationError> {
+        let bytes = fs::read(path)?;
+        if bytes.len() < CHECKSUM_LEN {
+            return Err(SerializationError::InvalidData);
+        }
+        let (checksum, mut bytes) = bytes.split_at(CHECKSUM_LEN);
+        if checksum != Sha3_256::digest(bytes).as_slice() {
+            return Err(SerializationError::InvalidData);
+        }
+        let keys = Self {
+            mint: CanonicalDeserialize::deserialize(&mut bytes)?,
+            xfr: CanonicalDeserialize::deserialize(&mut bytes)?,
+            freeze: CanonicalDeserialize::deserialize(&mut bytes)?,
+        };
+        if !bytes.is_empty() {
+            return Err(SerializationError::InvalidData);
+        }
+        Ok(keys)
+    }
+
+    /// Store the keys in `path`, preceded by a checksum of the serialized keys.
+    fn store(&self, path: &Path) -> Result<(), SerializationError> {
+        let mut keys = canonical::serialize(&self.mint)?;
+        self.xfr.serialize(&mut keys)?;
+        self.freeze.serialize(&mut keys)?;
+        let mut bytes = Sha3_256::digest(&keys).to_vec();
+        bytes.extend(keys);
+        // Write to a temporary file and then move it into place, so that concurrent processes
+        // never see a partially written cache file.
+        let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+        fs::write(&tmp, bytes)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Store the keys in the cache file `path`, logging any failure.
+    fn cache(&self, path: &Path) {
+        if let Err(err) = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .map_err(SerializationError::from)
+            .and_then(|()| self.store(path))
+        {
+            tracing::warn!("failed to cache keys at {}: {}", path.display(), err);
+        }
+    }
+
+    fn into_key_sets(self) -> (ProverKeySet<'a>, VerifierKeySet) {
+        let (xfr_pks, xfr_vks): (Vec<_>, Vec<_>) = self.xfr.into_iter().unzip();
+        let (freeze_pks, freeze_vks): (Vec<_>, Vec<_>) = self.freeze.into_iter().unzip();
+        (
+            ProverKeySet {
+                mint: self.mint.0,
+                xfr: xfr_pks.into_iter().collect(),
+                freeze: freeze_pks.into_iter().collect(),
+            },
+            VerifierKeySet {
+                mint: TransactionVerifyingKey::Mint(self.mint.1),
+                xfr: xfr_vks
+                    .into_iter()
+                    .map(TransactionVerifyingKey::Transfer)
+                    .collect(),
+                freeze: freeze_vks
+                    .into_iter()
+                    .map(TransactionVerifyingKey::Freeze)
+                    .collect(),
+            },
+        )
+    }
+}
+
+/// The name of the cache file for keys generated from the given parameters.
+///
+/// This is a hash of everything that determines the keys, so a cache file is never used for
+/// different parameters.
+fn key_cache_file(
+    univ_param: &UniversalParam,
+    xfr_sizes: &[(usize, usize)],
+    freeze_sizes: &[usize],
+) -> Result<String, SerializationError> {
+    let mut hasher = Sha3_256::new();
+    hasher.update(b"Espresso circuit keys v2");
+    hasher.update(canonical::serialize(univ_param)?);
+    hasher.update([MERKLE_HEIGHT]);
+    for (inputs, outputs) in xfr_sizes {
+        hasher.update((*inputs as u64).to_le_bytes());
+        hasher.update((*outputs as u64).to_le_bytes());
+    }
+    hasher.update(b"freeze");
+    for inputs in freeze_sizes {
+        hasher.update((*inputs as u64).to_le_bytes());
+    }
+    Ok(format!("{:x}.bin", hasher.finalize()))
+}
+
+/// Generate proving and verifying keys for the mint circuit and the given transfer and freeze
+/// arities.
+///
+/// Preprocessing the circuits is expensive, so if `cache_dir` is given, the keys are loaded from a
+/// cache file in that directory if one exists for these parameters. Otherwise, they are generated
+/// and stored in the cache for next time. Failure to read or write the cache is not an error; the
+/// keys are just generated from scratch.
+pub fn circuit_keys<'a>(
+    univ_param: &'a UniversalParam,
+    xfr_sizes: &[(usize, usize)],
+    freeze_sizes: &[usize],
+    cache_dir: Option<&Path>,
+) -> Result<(ProverKeySet<'a>, VerifierKeySet), TxnApiError> {
+    let cache_path = cache_dir.and_then(|dir| {
+        let file = key_cache_file(univ_param, xfr_sizes, freeze_sizes)
+            .map_err(|err| tracing::warn!("failed to compute key cache file name: {}", err))
+            .ok()?;
+        Some(dir.join(file))
+    });
+    if let Some(path) = &cache_path {
+        match CircuitKeys::load(path) {
+            Ok(keys) => return Ok(keys.into_key_sets()),
+            Err(err) => tracing::info!("no cached keys at {}: {}", path.display(), err),
+        }
+    }
+
+    let keys = CircuitKeys::preprocess(univ_param, xfr_sizes, freeze_sizes)?;
+    if let Some(path) = &cache_path {
+        keys.cache(path);
+    }
+    Ok(keys.into_key_sets())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn with_checksum(keys: &[u8]) -> Vec<u8> {
+        let mut bytes = Sha3_256::digest(keys).to_vec();
+        bytes.extend(keys);
+        bytes
+    }
+
+    #[test]
+    fn test_key_cache_round_trip() {
+        let dir = TempDir::new("key_cache").unwrap();
+        let xfr_sizes = [(1, 2)];
+        let freeze_sizes = [2];
+        let file = dir
+            .path()
+            .join(key_cache_file(&UNIVERSAL_PARAM, &xfr_sizes, &freeze_sizes).unwrap());
+
+        // Generating the keys populates the cache, and the cached keys are stored unchanged.
+        let (_, verifier) = circuit_keys(
+            &UNIVERSAL_PARAM,
+            &xfr_sizes,
+            &freeze_sizes,
+            Some(dir.path()),
+        )
+        .unwrap();
+        let bytes = fs::read(&file).unwrap();
+        let copy = dir.path().join("copy.bin");
+        CircuitKeys::load(&file).unwrap().store(&copy).unwrap();
+        assert_eq!(fs::read(&copy).unwrap(), bytes);
+
+        // Keys loaded from the cache are the ones which were generated.
+        let (_, cached_verifier) = circuit_keys(
+            &UNIVERSAL_PARAM,
+            &xfr_sizes,
+            &freeze_sizes,
+            Some(dir.path()),
+        )
+        .unwrap();
+        assert_eq!(
+            canonical::serialize(&verifier).unwrap(),
+            canonical::serialize(&cached_verifier).unwrap()
+        );
+
+        // A corrupted file is rejected by its checksum.
+        let mut corrupted = bytes.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        fs::write(&file, corrupted).unwrap();
+        assert!(CircuitKeys::load(&file).is_err());
+
+        // Truncated keys or trailing bytes are rejected even with a matching checksum.
+        let keys = &bytes[CHECKSUM_LEN..];
+        fs::write(&file, with_checksum(&keys[..keys.len() - 1])).unwrap();
+        assert!(CircuitKeys::load(&file).is_err());
+        fs::write(&file, with_checksum(&[keys, &[0]].concat())).unwrap();
+        assert!(CircuitKeys::load(&file).is_err());
+    }
 }
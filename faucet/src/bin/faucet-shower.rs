@@ -71,7 +71,7 @@ async fn create_keystore(
     let mut loader = CreateLoader::exclusive(rng, dir, mnemonic, hex::encode(password));
     let backend = NetworkBackend::new(
         &UNIVERSAL_PARAM,
-        opt.esqs_url.clone(),
+        vec![opt.esqs_url.clone()],
         opt.esqs_url.clone(),
         opt.esqs_url.clone(),
     )
@@ -802,7 +802,7 @@ pub async fn init_web_server(
     let mut loader = RecoveryLoader::new(rng, opt.keystore_path(), opt.mnemonic.clone(), password);
     let backend = NetworkBackend::new(
         &UNIVERSAL_PARAM,
-        opt.esqs_url.clone(),
+        vec![opt.esqs_url.clone()],
         opt.address_book_url.clone(),
         opt.submit_url.clone(),
     )
@@ -1106,7 +1106,7 @@ mod test {
             );
             let backend = NetworkBackend::new(
                 &UNIVERSAL_PARAM,
-                network.query_api.clone(),
+                vec![network.query_api.clone()],
                 network.address_book_api.clone(),
                 network.submit_api.clone(),
             )
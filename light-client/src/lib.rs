@@ -0,0 +1,447 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Espresso library.
+
+//! Light-client verification of Espresso state commitments.
+//!
+//! A [LightClient] follows the chain from a trusted genesis [LedgerStateCommitment] without running
+//! consensus or validating transactions. Each new state is accepted only if it is decided by a leaf
+//! certified by a quorum of the stake table fixed at genesis, and if it follows from the previous
+//! state by the block which produced it. Once a state is accepted, record and nullifier proofs can
+//! be checked against its roots. A client which does not need every state can instead
+//! [skip](LightClient::skip_to) to a later one, relying on the certificate alone.
+//!
+//! All of the inputs can be obtained from the availability API. The state after block `n` is
+//! returned by `getstate/n`, the block itself by `getblock/n` and its quorum certificate by
+//! `getqc/n`. The certificate is carried by the following leaf, so it is only available once the
+//! next block has been decided.
+//!
+//! HotShot votes are signatures on the commitment of a leaf. The light client recomputes the
+//! commitment to the leaf deciding each state from the certificate of the previous block, which the
+//! leaf carries, and the new state. This binds the state to the votes, and the state commits to the
+//! block which produced it. The client also recomputes the record and nullifier roots which the
+//! block leads to, so a state is never accepted on the strength of its commitment alone.
+
+use commit::Committable;
+use espresso_core::certificate::{CertificateError, QuorumCertificateData};
+use espresso_core::genesis::GenesisNote;
+use espresso_core::set_merkle_tree::{set_hash, SetMerkleProof};
+use espresso_core::stake_table::StakingKey;
+use espresso_core::state::{ElaboratedBlock, LedgerStateCommitment, ValidatorState};
+use jf_cap::structs::{Amount, Nullifier, RecordCommitment};
+use jf_cap::{MerkleCommitment, MerkleLeafProof, MerkleTree};
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, Snafu, Serialize, Deserialize)]
+pub enum LightClientError {
+    #[snafu(display(
+        "genesis state {} does not match trusted commitment {}",
+        actual,
+        expected
+    ))]
+    GenesisMismatch {
+        expected: LedgerStateCommitment,
+        actual: LedgerStateCommitment,
+    },
+
+    #[snafu(display("state {} does not follow the current state {}", state, current))]
+    BrokenLink {
+        current: LedgerStateCommitment,
+        state: LedgerStateCommitment,
+    },
+
+    #[snafu(display("block does not produce the state"))]
+    BlockMismatch,
+
+    #[snafu(display(
+        "state at height {} is not after the current state at height {}",
+        height,
+        current
+    ))]
+    Stale { current: u64, height: u64 },
+
+    #[snafu(display("state changes the stake table or chain variables"))]
+    StakeTableChanged,
+
+    #[snafu(display("state does not have the record Merkle root produced by the block"))]
+    RecordsMismatch,
+
+    #[snafu(display("state does not have the nullifier set produced by the block"))]
+    NullifiersMismatch,
+
+    #[snafu(display("invalid certificate: {}", source))]
+    InvalidCertificate { source: CertificateError },
+}
+
+/// A client which tracks verified Espresso states.
+#[derive(Clone, Debug)]
+pub struct LightClient {
+    state: ValidatorState,
+    /// The certificate which decided the block producing `state`.
+    certificate: QuorumCertificateData,
+    stake_table: BTreeMap<StakingKey, Amount>,
+}
+
+impl LightClient {
+    /// Start following the chain from `genesis`.
+    ///
+    /// `note` is the genesis transaction, which can be taken from block 0. The resulting state must
+    /// match the trusted `genesis` commitment.
+    ///
+    /// `certificate` is the certificate which decided block 0 (`getqc/0`). It is carried by the leaf
+    /// of block 1, so it is checked when that block is verified: a certificate which was not the one
+    /// HotShot stored would change the commitment to the leaf, and no quorum would have signed it.
+    pub fn new(
+        genesis: LedgerStateCommitment,
+        note: GenesisNote,
+        certificate: QuorumCertificateData,
+    ) -> Result<Self, LightClientError> {
+        let stake_table = note.stake_table.clone();
+        let state = ValidatorState::genesis(note);
+        if state.commit() != genesis {
+            return Err(LightClientError::GenesisMismatch {
+                expected: genesis,
+                actual: state.commit(),
+            });
+        }
+        Ok(Self {
+            state,
+            certificate,
+            stake_table,
+        })
+    }
+
+    /// Advance to `state`, the result of applying `block` to the current state.
+    ///
+    /// `qc` must be the quorum certificate which decided `block`. It is only accepted if it is for
+    /// the leaf which decides `state` and which carries the certificate of the current state. The
+    /// stake table must not change, and the record and nullifier roots of `state` must be the ones
+    /// produced by `block`. If any check fails, the current state is left unchanged.
+    ///
+    /// The query service refreshes the nullifier proofs in the blocks it serves, so `block` need
+    /// not have the commitment in `qc`. Its transactions are bound to the certificate through
+    /// `state`, which commits to them.
+    pub fn update(
+        &mut self,
+        state: ValidatorState,
+        block: &ElaboratedBlock,
+        qc: &QuorumCertificateData,
+    ) -> Result<(), LightClientError> {
+        let current = self.state.commit();
+        if state.prev_state != Some(current) {
+            return Err(LightClientError::BrokenLink {
+                current,
+                state: state.commit(),
+            });
+        }
+        if block.parent_state != current || block.block.commit() != state.prev_block {
+            return Err(LightClientError::BlockMismatch);
+        }
+        self.check_stake_table(&state)?;
+
+        // Without checking the transactions, we can still compute the roots they lead to.
+        let records = self.state.update_records_frontier(&block.block.0);
+        if records.commitment() != state.record_merkle_commitment {
+            return Err(LightClientError::RecordsMismatch);
+        }
+        let mut nullifiers = self
+            .state
+            .update_nullifier_proofs(&block.block.0, block.proofs.clone())
+            .ok()
+            .context(NullifiersMismatchSnafu)?;
+        for nullifier in block.block.0.iter().flat_map(|txn| txn.input_nullifiers()) {
+            nullifiers
+                .insert(nullifier)
+                .context(NullifiersMismatchSnafu)?;
+        }
+        if nullifiers.hash() != state.nullifiers_root() {
+            return Err(LightClientError::NullifiersMismatch);
+        }
+
+        qc.check_leaf(&self.certificate, state.commit())
+            .context(InvalidCertificateSnafu)?;
+        qc.verify(&self.state.chain, &self.stake_table)
+            .context(InvalidCertificateSnafu)?;
+        self.state = state;
+        self.certificate = qc.clone();
+        Ok(())
+    }
+
+    /// Jump ahead to `state`, a later state decided by `qc`, without following the blocks between.
+    ///
+    /// `justify` is the certificate of the block before the one which produced `state`, which the
+    /// leaf deciding `state` carries. As in [update](Self::update), `qc` must be for that leaf and
+    /// signed by a quorum of the genesis stake table, which `state` must keep. The roots of `state`
+    /// are not recomputed, so the skipped blocks are trusted to have been validated by the quorum.
+    /// If any check fails, the current state is left unchanged.
+    pub fn skip_to(
+        &mut self,
+        state: ValidatorState,
+        qc: &QuorumCertificateData,
+        justify: &QuorumCertificateData,
+    ) -> Result<(), LightClientError> {
+        if state.block_height <= self.state.block_height {
+            return Err(LightClientError::Stale {
+                current: self.state.block_height,
+                height: state.block_height,
+            });
+        }
+        self.check_stake_table(&state)?;
+        qc.check_leaf(justify, state.commit())
+            .context(InvalidCertificateSnafu)?;
+        qc.verify(&self.state.chain, &self.stake_table)
+            .context(InvalidCertificateSnafu)?;
+        self.state = state;
+        self.certificate = qc.clone();
+        Ok(())
+    }
+
+    fn check_stake_table(&self, state: &ValidatorState) -> Result<(), LightClientError> {
+        if state.chain.commit() != self.state.chain.commit()
+            || state.stake_table_root != self.state.stake_table_root
+            || state.total_stake != self.state.total_stake
+        {
+            return Err(LightClientError::StakeTableChanged);
+        }
+        Ok(())
+    }
+
+    /// The latest verified state.
+    pub fn state(&self) -> &ValidatorState {
+        &self.state
+    }
+
+    /// Commitment to the latest verified state.
+    pub fn commitment(&self) -> LedgerStateCommitment {
+        self.state.commit()
+    }
+
+    /// The certificate which decided the latest verified state.
+    pub fn certificate(&self) -> &QuorumCertificateData {
+        &self.certificate
+    }
+
+    /// The stake table, fixed at genesis, against which certificates are checked.
+    pub fn stake_table(&self) -> &BTreeMap<StakingKey, Amount> {
+        &self.stake_table
+    }
+
+    /// The verified root of the record Merkle tree.
+    pub fn record_merkle_commitment(&self) -> MerkleCommitment {
+        self.state.record_merkle_commitment
+    }
+
+    /// The verified root of the nullifier set.
+    pub fn nullifiers_root(&self) -> set_hash::Hash {
+        self.state.nullifiers_root()
+    }
+
+    /// Check that `record` is in the record Merkle tree at position `uid`.
+    pub fn verify_record(
+        &self,
+        record: RecordCommitment,
+        uid: u64,
+        proof: &MerkleLeafProof,
+    ) -> bool {
+        proof.leaf.0 == record.to_field_element()
+            && MerkleTree::check_proof(self.state.record_merkle_commitment.root_value, uid, proof)
+                .is_ok()
+    }
+
+    /// Check whether `nullifier` has been spent.
+    ///
+    /// Returns [None] if `proof` is not a valid proof against the verified nullifier set.
+    pub fn verify_nullifier(&self, nullifier: Nullifier, proof: &SetMerkleProof) -> Option<bool> {
+        proof.check(nullifier, &self.nullifiers_root()).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use commit::RawCommitmentBuilder;
+    use espresso_core::certificate::{EncodedSignature, EncodedVoteToken, QuorumSignature};
+    use espresso_core::state::{ChainVariables, ConsensusTime};
+    use espresso_core::universal_params::VERIF_CRS;
+    use rand_chacha::{rand_core::SeedableRng, ChaChaRng};
+    use std::sync::Arc;
+
+    struct Chain {
+        client: LightClient,
+        /// The block after genesis.
+        block: ElaboratedBlock,
+        /// The state produced by `block`.
+        state: ValidatorState,
+        /// A certificate for the leaf deciding `state`, with no votes.
+        qc: QuorumCertificateData,
+    }
+
+    fn chain() -> Chain {
+        let mut rng = ChaChaRng::from_seed([42; 32]);
+        let stake_table = (0..3)
+            .map(|_| (StakingKey::generate(&mut rng).0, Amount::from(10u64)))
+            .collect();
+        let note = GenesisNote::new(
+            ChainVariables::new(42, VERIF_CRS.clone(), 3),
+            Arc::new(vec![]),
+            stake_table,
+        );
+        let genesis = ValidatorState::genesis(note.clone());
+        let genesis_qc = QuorumCertificateData {
+            view_number: 0,
+            block_commitment: ElaboratedBlock::genesis(note.clone()).commit(),
+            leaf_commitment: RawCommitmentBuilder::new("genesis leaf").finalize(),
+            genesis: true,
+            signatures: vec![],
+        };
+        let client = LightClient::new(genesis.commit(), note, genesis_qc).unwrap();
+
+        let block = ElaboratedBlock::new(genesis.commit());
+        let mut state = genesis;
+        state
+            .validate_and_apply(
+                &ConsensusTime::new(1),
+                block.parent_state,
+                block.block.clone(),
+                vec![],
+                vec![],
+            )
+            .unwrap();
+        let mut qc = QuorumCertificateData {
+            view_number: 1,
+            block_commitment: block.commit(),
+            leaf_commitment: RawCommitmentBuilder::new("leaf").finalize(),
+            genesis: false,
+            signatures: vec![],
+        };
+        qc.leaf_commitment = qc.certified_leaf_commitment(client.certificate(), state.commit());
+
+        Chain {
+            client,
+            block,
+            state,
+            qc,
+        }
+    }
+
+    /// Try to update with `state` and `qc`, which must fail without changing the client.
+    fn reject(
+        chain: &Chain,
+        state: ValidatorState,
+        qc: &QuorumCertificateData,
+    ) -> LightClientError {
+        let mut client = chain.client.clone();
+        let err = client.update(state, &chain.block, qc).unwrap_err();
+        assert_eq!(client.commitment(), chain.client.commitment());
+        err
+    }
+
+    #[test]
+    fn test_forged_block_commitment() {
+        let chain = chain();
+
+        // The certificate is for the leaf deciding the state, so it only lacks votes.
+        assert!(matches!(
+            reject(&chain, chain.state.clone(), &chain.qc),
+            LightClientError::InvalidCertificate {
+                source: CertificateError::InsufficientVotes { .. }
+            }
+        ));
+
+        // Claiming a different block changes the leaf, which the votes would not be for.
+        let qc = QuorumCertificateData {
+            block_commitment: ElaboratedBlock::new(chain.state.commit()).commit(),
+            ..chain.qc.clone()
+        };
+        assert!(matches!(
+            reject(&chain, chain.state.clone(), &qc),
+            LightClientError::InvalidCertificate {
+                source: CertificateError::LeafMismatch
+            }
+        ));
+    }
+
+    #[test]
+    fn test_repeated_vote() {
+        let chain = chain();
+        let key = chain.client.stake_table().keys().next().unwrap().clone();
+        let vote = QuorumSignature {
+            key,
+            signature: EncodedSignature(vec![]),
+            vote_token: EncodedVoteToken(vec![]),
+        };
+        let qc = QuorumCertificateData {
+            signatures: vec![vote.clone(), vote],
+            ..chain.qc.clone()
+        };
+        assert!(matches!(
+            reject(&chain, chain.state.clone(), &qc),
+            LightClientError::InvalidCertificate {
+                source: CertificateError::DuplicateVoter { .. }
+            }
+        ));
+    }
+
+    #[test]
+    fn test_forged_state() {
+        let chain = chain();
+
+        let mut state = chain.state.clone();
+        state.prev_state = Some(state.commit());
+        assert!(matches!(
+            reject(&chain, state, &chain.qc),
+            LightClientError::BrokenLink { .. }
+        ));
+
+        let mut state = chain.state.clone();
+        state.record_merkle_commitment.num_leaves += 1;
+        assert!(matches!(
+            reject(&chain, state, &chain.qc),
+            LightClientError::RecordsMismatch
+        ));
+
+        let mut state = chain.state.clone();
+        state.total_stake = Amount::from(1u64);
+        assert!(matches!(
+            reject(&chain, state, &chain.qc),
+            LightClientError::StakeTableChanged
+        ));
+    }
+
+    #[test]
+    fn test_skip_to() {
+        let chain = chain();
+
+        let mut client = chain.client.clone();
+        let err = client
+            .skip_to(
+                chain.client.state().clone(),
+                &chain.qc,
+                chain.client.certificate(),
+            )
+            .unwrap_err();
+        assert!(matches!(err, LightClientError::Stale { .. }));
+
+        // The leaf must carry `justify`, and the certificate still needs a quorum.
+        let err = client
+            .skip_to(chain.state.clone(), &chain.qc, &chain.qc)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            LightClientError::InvalidCertificate {
+                source: CertificateError::LeafMismatch
+            }
+        ));
+        let err = client
+            .skip_to(chain.state.clone(), &chain.qc, chain.client.certificate())
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            LightClientError::InvalidCertificate {
+                source: CertificateError::InsufficientVotes { .. }
+            }
+        ));
+        assert_eq!(client.commitment(), chain.client.commitment());
+    }
+}
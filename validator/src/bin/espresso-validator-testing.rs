@@ -299,7 +299,7 @@ async fn main() -> Result<(), std::io::Error> {
     let options = Options::parse();
     let id = options.node_opt.id;
     let (genesis, state) = genesis_for_test(&options.node_opt);
-    let hotshot = init(ChaChaRng::from_entropy(), genesis, options.node_opt).await?;
+    let (hotshot, _) = init(ChaChaRng::from_entropy(), genesis, options.node_opt).await?;
     generate_transactions(options.num_txns, id, hotshot, state).await;
     Ok(())
 }
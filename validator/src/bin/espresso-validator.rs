@@ -13,7 +13,7 @@ use rand_chacha::ChaChaRng;
 async fn main() -> Result<(), std::io::Error> {
     let node_opt = NodeOpt::parse();
     let genesis = genesis(&node_opt);
-    let hotshot = init(ChaChaRng::from_entropy(), genesis, node_opt).await?;
-    run_consensus(hotshot, pending::<()>()).await;
+    let (hotshot, metrics) = init(ChaChaRng::from_entropy(), genesis, node_opt).await?;
+    run_consensus(hotshot, metrics, pending::<()>()).await;
     Ok(())
 }
@@ -22,15 +22,20 @@ use async_tungstenite::async_std::connect_async;
 use clap::Parser;
 use commit::Committable;
 use espresso_availability_api::query_data::*;
+use espresso_core::app_data::NamespaceId;
 use espresso_core::ledger::EspressoLedger;
+use espresso_core::state::{EspressoTransaction, ValidationError};
 use espresso_esqs::ApiError;
-use espresso_metastate_api::api::NullifierCheck;
+use espresso_light_client::LightClient;
+use espresso_metastate_api::api::{NullifierBatchCheck, NullifierCheck};
+use espresso_validator_api::query_data::TransactionStatus;
 use futures::prelude::*;
 use hotshot_types::data::ViewNumber;
 use itertools::izip;
+use jf_cap::{MerkleLeafProof, MerkleTree};
 use reef::traits::Transaction;
 use seahorse::events::LedgerEvent;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 use std::ops::Deref;
 use std::time::Duration;
@@ -67,6 +72,42 @@ async fn get<T: for<'de> Deserialize<'de>, S: Display>(opt: &Args, route: S) ->
     surf_disco::get::<T, ApiError>(url).send().await.unwrap()
 }
 
+async fn post<T: for<'de> Deserialize<'de>, S: Display>(
+    opt: &Args,
+    route: S,
+    body: &impl Serialize,
+) -> T {
+    let url = url(opt, route);
+    event!(Level::INFO, "POST {}", url);
+    surf_disco::post::<T, ApiError>(url)
+        .body_json(body)
+        .unwrap()
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Get all the results of a paginated range query, following `next` cursors until the end.
+async fn get_range<T: for<'de> Deserialize<'de>>(
+    opt: &Args,
+    route: impl Display,
+    mut from: u64,
+    until: u64,
+) -> Vec<T> {
+    let mut items = vec![];
+    loop {
+        let page: RangeQueryData<T> = get(opt, format!("{}/{}/{}", route, from, until)).await;
+        items.extend(page.items);
+        match page.next {
+            Some(next) => {
+                assert!(next > from);
+                from = next;
+            }
+            None => return items,
+        }
+    }
+}
+
 async fn validate_committed_block(
     opt: &Args,
     block: &BlockQueryData,
@@ -94,7 +135,26 @@ async fn validate_committed_block(
         get(opt, format!("/availability/getstate/{}", block.block_id)).await;
     assert_eq!(state.block_id, ix);
 
+    // The record frontier after this block is available by block ID, and by the index of the first
+    // event after the block.
+    let frontier: Option<RecordFrontierQueryData> =
+        get(opt, format!("/availability/getfrontier/{}", ix)).await;
+    assert_eq!(frontier, Some(state.clone().into()));
+    assert_eq!(
+        frontier,
+        get(
+            opt,
+            format!(
+                "/availability/getfrontier/event/{}",
+                state.continuation_event_index
+            )
+        )
+        .await
+    );
+
     // Check the block's transactions.
+    let mut nullifiers = vec![];
+    let mut records = vec![];
     let mut uid = block.records_from;
     for (i, hash) in block.txn_hashes.iter().enumerate() {
         // Check that the transaction listed in the block is the same transaction we would get if we
@@ -109,6 +169,21 @@ async fn validate_committed_block(
         assert_eq!(txn.txn_id, i as u64);
         assert_eq!(txn.transaction_hash, *hash);
 
+        // Every committed transaction is reported as committed, whether or not it was submitted
+        // through this server.
+        match get(opt, format!("/validator/status/{}", hash)).await {
+            TransactionStatus::Committed { block_id, txn_id } => {
+                assert_eq!(block_id, ix);
+                assert_eq!(txn_id, i as u64);
+            }
+            status => panic!("committed transaction has status {:?}", status),
+        }
+
+        // A transaction which has already been committed can never be valid again.
+        let simulated: Result<(), ValidationError> =
+            post(opt, "/validator/simulate", &txn.raw_transaction).await;
+        assert!(simulated.is_err());
+
         // Check inputs.
         for n in txn.raw_transaction.input_nullifiers() {
             let check: NullifierCheck =
@@ -121,6 +196,12 @@ async fn validate_committed_block(
                     .unwrap(),
                 check.spent
             );
+            nullifiers.push(n);
+            // Check that we can find this transaction by the nullifiers it spent.
+            assert_eq!(
+                txn,
+                get(opt, format!("/availability/gettransaction/nullifier/{}", n)).await
+            );
         }
 
         // Check outputs.
@@ -133,23 +214,89 @@ async fn validate_committed_block(
                 utxo,
                 get(opt, format!("/availability/getrecord/uid/{}", uid)).await
             );
+            assert_eq!(
+                utxo,
+                get(
+                    opt,
+                    format!("/availability/getrecord/commitment/{}", output)
+                )
+                .await
+            );
             assert_eq!(output, utxo.commitment);
             assert_eq!(utxo.uid, uid);
             assert_eq!(utxo.block_id, ix);
             assert_eq!(utxo.txn_id, i as u64);
             assert_eq!(utxo.output_index, j as u64);
+
+            // Check that the record is included in the record Merkle tree as of this block.
+            let proof: MerkleLeafProof =
+                get(opt, format!("/availability/getrecordproof/{}/{}", uid, ix)).await;
+            assert_eq!(proof.leaf.0, output.to_field_element());
+            MerkleTree::check_proof(state.state.record_merkle_commitment.root_value, uid, &proof)
+                .unwrap();
+
+            records.push(utxo);
         }
         uid += txn.raw_transaction.output_len() as u64;
     }
     assert_eq!(uid, block.records_from + block.record_count);
 
+    // Check that we get the same nullifier proofs if we query for all of them at once.
+    let batch: NullifierBatchCheck = post(
+        opt,
+        format!("/metastate/check_nullifiers/{}", ix),
+        &nullifiers,
+    )
+    .await;
+    assert_eq!(batch.proof.hash(), state.state.nullifiers_root());
+    assert_eq!(batch.checks.len(), nullifiers.len());
+    for (n, check) in nullifiers.into_iter().zip(batch.checks) {
+        assert!(check.spent);
+        assert!(check
+            .proof
+            .check(n, &state.state.nullifiers_root())
+            .unwrap());
+    }
+
+    // Check that we get the same records if we query for the whole range at once.
+    assert_eq!(
+        records,
+        get_range::<RecordQueryData>(opt, "/availability/getrecords", block.records_from, uid)
+            .await
+    );
+
     // Check the block summary.
     assert_eq!(summary.size, block.raw_block.serialized_size());
     assert_eq!(summary.txn_count, block.txn_hashes.len());
     assert_eq!(summary.records_from, block.records_from);
     assert_eq!(summary.record_count, block.record_count);
-    let view_number: ViewNumber = get(opt, format!("/availability/getviewnumber/{}", ix)).await;
-    assert_eq!(summary.view_number, *view_number.deref());
+
+    // The quorum certificate is available by block ID or hash once the next block is decided, and
+    // certifies this block. The view number of the block is the view number of its certificate.
+    if ix + 1 < num_blocks {
+        let qc: QuorumCertificateQueryData = get(opt, format!("/availability/getqc/{}", ix)).await;
+        assert_eq!(qc.block_id, ix);
+        let view_number: ViewNumber = get(opt, format!("/availability/getviewnumber/{}", ix)).await;
+        assert_eq!(*view_number.deref(), qc.certificate.view_number);
+        assert_eq!(summary.view_number, Some(qc.certificate.view_number));
+        if ix > 0 {
+            // The certified leaf carries the certificate for the previous block.
+            let justify: QuorumCertificateQueryData =
+                get(opt, format!("/availability/getqc/{}", ix - 1)).await;
+            let state: StateQueryData = get(opt, format!("/availability/getstate/{}", ix)).await;
+            qc.certificate
+                .check_leaf(&justify.certificate, state.commitment)
+                .unwrap();
+        }
+        assert_eq!(
+            qc,
+            get(
+                opt,
+                format!("/availability/getqc/hash/{}", block.block_hash)
+            )
+            .await
+        );
+    }
 }
 
 async fn test(opt: &Args) {
@@ -172,6 +319,68 @@ async fn test(opt: &Args) {
     .await;
     assert_eq!(block_summaries.len() as u64, num_blocks);
 
+    // Get all the blocks using forward range queries, following the pagination cursors.
+    let blocks: Vec<BlockQueryData> =
+        get_range(opt, "/availability/getblocks", 0, num_blocks).await;
+    assert_eq!(blocks.len() as u64, num_blocks);
+    let summaries: Vec<BlockSummaryQueryData> =
+        get_range(opt, "/availability/getblocksummaries", 0, num_blocks).await;
+    assert_eq!(summaries.len() as u64, num_blocks);
+    for (summary, reverse_summary) in summaries.iter().zip(block_summaries.iter().rev()) {
+        assert_eq!(summary.block_id, reverse_summary.block_id);
+        assert_eq!(summary.block_hash, reverse_summary.block_hash);
+    }
+
+    // Get the transactions from one namespace in every block. Each page must prove that it contains
+    // all of the transactions from the namespace, relative to the block's commitment.
+    let namespace = NamespaceId(0);
+    let namespaces: Vec<NamespaceQueryData> = get_range(
+        opt,
+        format!("/availability/getnamespace/{}", namespace),
+        0,
+        num_blocks,
+    )
+    .await;
+    assert_eq!(namespaces.len() as u64, num_blocks);
+    for (block, ns) in blocks.iter().zip(&namespaces) {
+        assert_eq!(ns.block_id, block.block_id);
+        assert_eq!(ns.block_commitment, block.raw_block.block.commit());
+        let txns = ns
+            .transactions
+            .iter()
+            .map(|txn| (txn.txn_id, txn.raw_transaction.txn.clone()))
+            .collect::<Vec<_>>();
+        assert!(ns.proof.verify(ns.block_commitment, namespace, &txns));
+    }
+    // The block commitment is the one recorded in the state after the block.
+    let state: StateQueryData =
+        get(opt, format!("/availability/getstate/{}", num_blocks - 1)).await;
+    assert_eq!(
+        state.state.prev_block,
+        namespaces[(num_blocks - 1) as usize].block_commitment
+    );
+
+    // Follow the chain with a light client, starting from the genesis state. The certificate for
+    // each block is carried by the next leaf, so we can verify all but the last block.
+    let genesis: StateQueryData = get(opt, "/availability/getstate/0").await;
+    let note = match &blocks[0].raw_block.block.0[..] {
+        [EspressoTransaction::Genesis(note)] => note.clone(),
+        txns => panic!("expected a genesis block, got {:?}", txns),
+    };
+    let genesis_qc: QuorumCertificateQueryData = get(opt, "/availability/getqc/0").await;
+    let mut light_client =
+        LightClient::new(genesis.commitment, note, genesis_qc.certificate).unwrap();
+    for block in blocks.iter().take(blocks.len() - 1).skip(1) {
+        let state: StateQueryData =
+            get(opt, format!("/availability/getstate/{}", block.block_id)).await;
+        let qc: QuorumCertificateQueryData =
+            get(opt, format!("/availability/getqc/{}", block.block_id)).await;
+        light_client
+            .update(state.state, &block.raw_block, &qc.certificate)
+            .unwrap();
+        assert_eq!(light_client.commitment(), state.commitment);
+    }
+
     let test_indices = if opt.all {
         (0..num_blocks).into_iter().collect()
     } else {
@@ -182,7 +391,8 @@ async fn test(opt: &Args) {
 
     // Check that we can query the 0th block and the last block.
     for ix in test_indices {
-        let block = get(opt, format!("/availability/getblock/{}", ix)).await;
+        let block: BlockQueryData = get(opt, format!("/availability/getblock/{}", ix)).await;
+        assert_eq!(block, blocks[ix as usize]);
         validate_committed_block(
             opt,
             &block,
@@ -270,7 +480,7 @@ mod test {
         let mut keystore1 = Keystore::new(
             NetworkBackend::new(
                 &UNIVERSAL_PARAM,
-                network.query_api.clone(),
+                vec![network.query_api.clone()],
                 network.address_book_api.clone(),
                 network.submit_api.clone(),
             )
@@ -286,7 +496,7 @@ mod test {
         let mut keystore2 = Keystore::new(
             NetworkBackend::new(
                 &UNIVERSAL_PARAM,
-                network.query_api.clone(),
+                vec![network.query_api.clone()],
                 network.address_book_api.clone(),
                 network.submit_api.clone(),
             )
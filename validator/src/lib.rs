@@ -7,11 +7,13 @@
 use ark_serialize::*;
 use ark_std::rand::{CryptoRng, RngCore};
 use async_std::sync::{Arc, RwLock};
-use async_std::task::spawn;
+use async_std::task::{sleep, spawn};
 use clap::Parser;
 use cld::ClDuration;
 use dirs::data_local_dir;
+use espresso_core::certificate::quorum_threshold;
 use espresso_core::kv_merkle_tree::KVMerkleTree;
+use espresso_core::metrics::Metrics;
 use espresso_core::reward::{
     eligibility, CollectRewardNote, CollectedRewards, CollectedRewardsSet,
 };
@@ -23,12 +25,15 @@ use espresso_core::{
     genesis::GenesisNote,
     stake_table::{StakeTableHash, StakingPrivKey},
     state::{
-        ChainVariables, ElaboratedBlock, ElaboratedTransaction, LWPersistence, ValidatorState,
+        BlockLimits, ChainVariables, ElaboratedBlock, ElaboratedTransaction, LWPersistence,
+        ValidatorState,
     },
     universal_params::VERIF_CRS,
 };
 use espresso_esqs::full_node::{self};
 use espresso_esqs::full_node_data_source::QueryData;
+use espresso_esqs::ApiError;
+use espresso_status_api::{data_source::UpdateStatusData, query_data::PeerInfo};
 use espresso_validator_api::data_source::ValidatorDataSource;
 use futures::{select, Future, FutureExt};
 use hotshot::types::{ed25519::Ed25519Priv, EventType};
@@ -36,11 +41,12 @@ use hotshot::{
     traits::{
         election::vrf::{VRFStakeTableConfig, VrfImpl, SORTITION_PARAMETER},
         implementations::MemoryStorage,
+        NetworkingImplementation,
     },
     types::{HotShotHandle, SignatureKey as _},
     HotShot, HotShotInitializer,
 };
-use hotshot_types::{ExecutionType, HotShotConfig};
+use hotshot_types::{traits::network::NetworkChange, ExecutionType, HotShotConfig};
 use jf_cap::{
     keys::UserPubKey,
     structs::{Amount, AssetDefinition, FreezeFlag, RecordOpening},
@@ -49,6 +55,7 @@ use jf_utils::tagged_blob;
 use libp2p::identity::ed25519::SecretKey;
 use libp2p::{multiaddr, Multiaddr, PeerId};
 use libp2p_networking::network::NetworkNodeType;
+use mempool::{Mempool, MempoolOptions};
 use node_impl::{SignatureKey, ValidatorNodeImpl};
 use rand_chacha::{rand_core::SeedableRng as _, ChaChaRng};
 use snafu::Snafu;
@@ -65,6 +72,7 @@ use std::time::Duration;
 use tracing::{debug, event, Level};
 use url::Url;
 
+pub mod mempool;
 mod network;
 pub mod node_impl;
 #[cfg(any(test, feature = "testing"))]
@@ -80,7 +88,7 @@ macro_rules! div_ceil {
 
 pub const COMMITTEE_SIZE: u64 = SORTITION_PARAMETER;
 // More than 2/3 of the expected committee size is required to reach quorum.
-pub const QUORUM_THRESHOLD: u64 = 2 * COMMITTEE_SIZE / 3 + 1;
+pub const QUORUM_THRESHOLD: u64 = quorum_threshold(COMMITTEE_SIZE);
 // For the fixed-stake testnet, we arbitrarily assign each node enough stake so that at least 4
 // nodes are required for quorum.
 pub const STAKE_PER_NODE: u64 = QUORUM_THRESHOLD / 4;
@@ -387,6 +395,8 @@ pub struct NodeOpt {
     pub start_delay: Duration,
 
     /// Maximum number of transactions in a block.
+    ///
+    /// This is capped by the block size limits of the chain.
     #[arg(
         long,
         env = "ESPRESSO_VALIDATOR_MAX_TRANSACTIONS",
@@ -394,10 +404,66 @@ pub struct NodeOpt {
     )]
     pub max_transactions: NonZeroUsize,
 
+    /// Maximum number of transactions waiting in the mempool.
+    ///
+    /// When the mempool is full, a new transaction is only admitted if it pays a higher fee than
+    /// some waiting transaction, which is evicted to make room.
+    #[arg(
+        long,
+        env = "ESPRESSO_VALIDATOR_MEMPOOL_MAX_TRANSACTIONS",
+        default_value = "100000"
+    )]
+    pub mempool_max_transactions: usize,
+
+    /// Maximum total size, in bytes, of transactions waiting in the mempool.
+    #[arg(
+        long,
+        env = "ESPRESSO_VALIDATOR_MEMPOOL_MAX_BYTES",
+        default_value = "1000000000"
+    )]
+    pub mempool_max_bytes: usize,
+
+    /// Number of blocks a transaction released from the mempool to consensus may remain undecided
+    /// before it is dropped.
+    #[arg(
+        long,
+        env = "ESPRESSO_VALIDATOR_MEMPOOL_RELEASE_TIMEOUT",
+        default_value = "10"
+    )]
+    pub mempool_release_timeout: u64,
+
     /// Unique identifier for this instance of Espresso.
     #[arg(long, env = "ESPRESSO_VALIDATOR_CHAIN_ID", default_value = "0")]
     pub chain_id: u16,
 
+    /// Maximum number of transactions in a block, committed in the genesis block.
+    ///
+    /// Like the chain ID, this must be the same for all nodes.
+    #[arg(
+        long,
+        env = "ESPRESSO_VALIDATOR_BLOCK_MAX_TRANSACTIONS",
+        default_value = "10000"
+    )]
+    pub block_max_transactions: u64,
+
+    /// Maximum total size, in bytes, of the transactions in a block, including their proofs and
+    /// memos, committed in the genesis block.
+    #[arg(
+        long,
+        env = "ESPRESSO_VALIDATOR_BLOCK_MAX_BYTES",
+        default_value = "67108864"
+    )]
+    pub block_max_bytes: u64,
+
+    /// Maximum total number of record outputs of the transactions in a block, committed in the
+    /// genesis block.
+    #[arg(
+        long,
+        env = "ESPRESSO_VALIDATOR_BLOCK_MAX_OUTPUTS",
+        default_value = "40000"
+    )]
+    pub block_max_outputs: u64,
+
     /// Public key which should own a faucet record in the genesis block.
     ///
     /// For each given public key, the ledger will be initialized with a record of 2^32 native
@@ -416,6 +482,12 @@ pub struct NodeOpt {
     #[arg(long, env = "ESPRESSO_COLORED_LOGS")]
     pub colored_logs: bool,
 
+    /// Port on which to serve Prometheus metrics at `/metrics`.
+    ///
+    /// If not provided, metrics are still collected but not served.
+    #[arg(long, env = "ESPRESSO_VALIDATOR_METRICS_PORT")]
+    pub metrics_port: Option<u16>,
+
     #[command(subcommand)]
     pub esqs: Option<full_node::Command>,
 }
@@ -447,6 +519,15 @@ impl NodeOpt {
 }
 
 impl NodeOpt {
+    pub fn mempool_options(&self) -> MempoolOptions {
+        MempoolOptions {
+            max_transactions: self.mempool_max_transactions,
+            max_bytes: self.mempool_max_bytes,
+            batch_size: self.max_transactions.get(),
+            release_timeout: self.mempool_release_timeout,
+        }
+    }
+
     pub fn check(&self) -> Result<(), String> {
         if self.num_nodes < MINIMUM_NODES {
             return Err(format!(
@@ -568,7 +649,12 @@ pub fn genesis(node_opt: &NodeOpt) -> GenesisNote {
     // generate keys
     let known_nodes = gen_keys(node_opt.secret_key_seed, node_opt.num_nodes);
     GenesisNote::new(
-        ChainVariables::new(node_opt.chain_id, VERIF_CRS.clone(), COMMITTEE_SIZE),
+        ChainVariables::new(node_opt.chain_id, VERIF_CRS.clone(), COMMITTEE_SIZE)
+            .with_block_limits(BlockLimits {
+                max_transactions: node_opt.block_max_transactions,
+                max_bytes: node_opt.block_max_bytes,
+                max_outputs: node_opt.block_max_outputs,
+            }),
         Arc::new(faucet_records),
         initialize_stake_table(
             known_nodes
@@ -619,7 +705,11 @@ async fn init_hotshot(
     };
     let config = HotShotConfig {
         total_nodes: NonZeroUsize::new(known_nodes.len()).unwrap(),
-        max_transactions: node_opt.max_transactions,
+        // Never let HotShot propose more transactions than the chain allows in a block.
+        max_transactions: node_opt.max_transactions.min(
+            NonZeroUsize::new(genesis.chain.block_limits.max_transactions as usize)
+                .unwrap_or(NonZeroUsize::new(1).unwrap()),
+        ),
         known_nodes: known_nodes.clone(),
         next_view_timeout: node_opt.next_view_timeout.as_millis() as u64,
         timeout_ratio: node_opt.timeout_ratio.into(),
@@ -691,7 +781,7 @@ async fn init_hotshot(
     (hotshot, stake_proof, stake_amount, collected_rewards_set)
 }
 
-pub async fn run_consensus<F: Send + Future>(mut consensus: Consensus, kill: F) {
+pub async fn run_consensus<F: Send + Future>(mut consensus: Consensus, metrics: Metrics, kill: F) {
     consensus.start().await;
     let mut kill = kill.boxed().fuse();
     loop {
@@ -705,10 +795,17 @@ pub async fn run_consensus<F: Send + Future>(mut consensus: Consensus, kill: F)
                 match event {
                     Ok(event) => match event.event {
                         EventType::Decide { leaf_chain } => {
+                            for leaf in leaf_chain.iter() {
+                                metrics.record_decide(leaf.timestamp);
+                            }
                             if let Some(leaf) = leaf_chain.last() {
                                 tracing::debug!(". - Committed state {}", leaf.state.commit());
                             }
                         }
+                        EventType::ViewTimeout { view_number } => {
+                            metrics.record_view_timeout();
+                            tracing::debug!("  - View {:?} timed out.", view_number);
+                        }
                         EventType::NextLeaderViewTimeout { view_number } => {
                             tracing::debug!("  - Round {:?} timed out.", view_number);
                         }
@@ -744,7 +841,8 @@ pub async fn init_validator<R: CryptoRng + RngCore + Send + 'static>(
     priv_key: StakingPrivKey,
     pub_keys: Vec<StakingKey>,
     genesis: GenesisNote,
-) -> Consensus {
+    metrics: &Metrics,
+) -> (Consensus, Network) {
     debug!("Current node: {}", node_opt.id);
 
     let num_bootstrap = node_opt.bootstrap_nodes.len();
@@ -799,9 +897,11 @@ pub async fn init_validator<R: CryptoRng + RngCore + Send + 'static>(
     };
 
     let own_network = match node_opt.cdn.clone() {
-        Some(cdn) if !node_opt.libp2p => Network::new_cdn(pub_keys.clone(), cdn, node_opt.id)
-            .await
-            .unwrap(),
+        Some(cdn) if !node_opt.libp2p => {
+            Network::new_cdn(pub_keys.clone(), cdn, node_opt.id, metrics.clone())
+                .await
+                .unwrap()
+        }
         _ => {
             let network = Network::new_p2p(
                 pub_keys[node_opt.id].clone(),
@@ -810,6 +910,7 @@ pub async fn init_validator<R: CryptoRng + RngCore + Send + 'static>(
                 parse_url(&format!("0.0.0.0:{:?}", port)).unwrap(),
                 own_identity,
                 node_opt,
+                metrics.clone(),
             )
             .await
             .unwrap();
@@ -818,7 +919,7 @@ pub async fn init_validator<R: CryptoRng + RngCore + Send + 'static>(
                 // If there is a centralized server, use it as a barrier, so we don't proceed beyond
                 // this point until all nodes have reached this point and connected to the server.
                 // We will still use the libp2p network for consensus itself.
-                Network::new_cdn(pub_keys.clone(), cdn, node_opt.id)
+                Network::new_cdn(pub_keys.clone(), cdn, node_opt.id, metrics.clone())
                     .await
                     .unwrap();
             }
@@ -828,6 +929,7 @@ pub async fn init_validator<R: CryptoRng + RngCore + Send + 'static>(
     };
 
     let known_nodes = pub_keys.clone();
+    let network = own_network.clone();
 
     debug!("All nodes connected to network");
 
@@ -851,21 +953,91 @@ pub async fn init_validator<R: CryptoRng + RngCore + Send + 'static>(
             priv_key,
             rewards_pub_key,
             hotshot.clone(),
+            metrics.clone(),
         ));
     }
 
-    hotshot
+    (hotshot, network)
 }
 
-pub fn open_data_source(node_opt: &NodeOpt, consensus: Consensus) -> Arc<RwLock<QueryData>> {
+pub fn open_data_source(
+    node_opt: &NodeOpt,
+    mempool: Mempool<Consensus>,
+    metrics: &Metrics,
+) -> Arc<RwLock<QueryData>> {
     let storage = get_store_dir(node_opt);
+    let location = node_opt.location.clone();
+    let metrics = metrics.clone();
     Arc::new(RwLock::new(if node_opt.reset_store_state {
-        QueryData::new(&storage, Box::new(consensus), node_opt.location.clone()).unwrap()
+        QueryData::new(&storage, Box::new(mempool), location, metrics).unwrap()
     } else {
-        QueryData::load(&storage, Box::new(consensus), node_opt.location.clone()).unwrap()
+        QueryData::load(&storage, Box::new(mempool), location, metrics).unwrap()
     }))
 }
 
+/// Serve the Prometheus metrics for this node at `/metrics`.
+pub async fn serve_metrics(port: u16, metrics: Metrics) -> std::io::Result<()> {
+    let mut app = tide::with_state(metrics);
+    app.at("/metrics")
+        .get(|req: tide::Request<Metrics>| async move {
+            Ok(tide::Response::builder(200)
+                .content_type("text/plain; version=0.0.4")
+                .body(req.state().encode())
+                .build())
+        });
+    app.listen(format!("0.0.0.0:{}", port)).await
+}
+
+/// How often to poll the network for peers connecting and disconnecting.
+const PEER_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Keep the list of connected peers in the query service status up to date.
+///
+/// This runs forever, so it should be spawned in its own task.
+pub async fn track_peers(network: Network, data_source: Arc<RwLock<QueryData>>) {
+    // Any peers in the persisted status are from a previous run, and may no longer be connected.
+    data_source
+        .write()
+        .await
+        .edit_status(|vs| {
+            vs.peer_list.clear();
+            Ok::<(), ApiError>(())
+        })
+        .unwrap();
+    loop {
+        match network.network_changes().await {
+            Ok(changes) if !changes.is_empty() => {
+                data_source
+                    .write()
+                    .await
+                    .edit_status(|vs| {
+                        for change in changes {
+                            match change {
+                                NetworkChange::NodeConnected(key) => {
+                                    let peer_id = StakingKey::from(key);
+                                    if !vs.peer_list.iter().any(|peer| peer.peer_id == peer_id) {
+                                        vs.peer_list.push(PeerInfo { peer_id });
+                                    }
+                                }
+                                NetworkChange::NodeDisconnected(key) => {
+                                    let peer_id = StakingKey::from(key);
+                                    vs.peer_list.retain(|peer| peer.peer_id != peer_id);
+                                }
+                            }
+                        }
+                        Ok::<(), ApiError>(())
+                    })
+                    .unwrap();
+            }
+            Ok(_) => {}
+            Err(err) => {
+                tracing::warn!("failed to get network changes: {}", err);
+            }
+        }
+        sleep(PEER_POLL_INTERVAL).await;
+    }
+}
+
 #[allow(dead_code)] // FIXME use this function in main
 async fn collect_reward_daemon<R: CryptoRng + RngCore + Send>(
     mut rng: R,
@@ -875,6 +1047,7 @@ async fn collect_reward_daemon<R: CryptoRng + RngCore + Send>(
     staking_priv_key: StakingPrivKey,
     cap_pub_key: UserPubKey,
     mut hotshot: Consensus,
+    metrics: Metrics,
 ) {
     let staking_key = StakingKey::from_private(&staking_priv_key);
     loop {
@@ -932,6 +1105,7 @@ async fn collect_reward_daemon<R: CryptoRng + RngCore + Send>(
                         .submit_transaction(elaborated_tx)
                         .await
                         .expect("Failed to submit reward transaction");
+                    metrics.reward_submissions.inc();
 
                     // 3. update collected_reward_set
                     for txn in blk.block.0.iter() {
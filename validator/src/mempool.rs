@@ -0,0 +1,489 @@
+// Copyright (c) 2022 Espresso Systems (espressosys.com)
+// This file is part of the Espresso library.
+
+//! A bounded, fee-prioritized mempool in front of HotShot.
+//!
+//! HotShot queues every transaction submitted to it and proposes them in the order they arrived.
+//! [Mempool] instead holds submitted transactions itself and releases them to HotShot a block at a
+//! time, highest fee first, so that the transactions HotShot proposes are always the most valuable
+//! ones waiting. It also bounds the number and total size of waiting transactions, refuses
+//! duplicates and transactions which conflict with one already waiting, and drops transactions once
+//! they expire, or once they have been released to HotShot for too long without being decided.
+
+use ark_serialize::CanonicalSerialize;
+use async_trait::async_trait;
+use commit::Committable;
+use espresso_core::state::{
+    ElaboratedTransaction, EspressoTransaction, TransactionCommitment, ValidatorState,
+};
+use espresso_validator_api::{
+    data_source::{ConsensusEvent, MempoolError, SubmitError, ValidatorDataSource},
+    query_data::TransactionStatus,
+};
+use hotshot::types::EventType;
+use jf_cap::{structs::Nullifier, TransactionNote};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Copy, Debug)]
+pub struct MempoolOptions {
+    /// The maximum number of transactions waiting in the mempool.
+    pub max_transactions: usize,
+    /// The maximum total serialized size of transactions waiting in the mempool, including their
+    /// proofs and memos.
+    pub max_bytes: usize,
+    /// The maximum number of transactions released to HotShot and not yet decided.
+    ///
+    /// This should be the maximum number of transactions in a block, so that HotShot always has
+    /// enough transactions to fill a block, but never more than the highest-fee ones.
+    pub batch_size: usize,
+    /// The number of blocks a released transaction may remain undecided before it is dropped.
+    ///
+    /// HotShot may never propose a transaction released to it, for example if the proposal
+    /// containing it fails. Dropping such transactions frees their place in the batch. HotShot has
+    /// no way to withdraw a transaction, so it may still propose one after it is dropped here; such
+    /// transactions are therefore not reported as evicted, and stay pending in the query service
+    /// until they are decided or can no longer be decided.
+    ///
+    /// This is the only timeout on pending transactions.
+    pub release_timeout: u64,
+}
+
+/// The fee paid by a transaction, which determines its priority.
+fn fee(txn: &EspressoTransaction) -> u128 {
+    match txn {
+        EspressoTransaction::CAP(TransactionNote::Transfer(note)) => note.aux_info.fee.into(),
+        EspressoTransaction::CAP(TransactionNote::Mint(note)) => note.aux_info.fee.into(),
+        EspressoTransaction::CAP(TransactionNote::Freeze(note)) => note.aux_info.fee.into(),
+        _ => 0,
+    }
+}
+
+/// Transactions are prioritized by fee, and then by age.
+type Priority = (u128, Reverse<u64>);
+
+struct Entry {
+    txn: ElaboratedTransaction,
+    priority: Priority,
+    size: usize,
+    /// The height of the last block which may include the transaction, bound to its proof.
+    valid_until: Option<u64>,
+    /// The block height at which the transaction was released to HotShot, if it has been.
+    released: Option<u64>,
+}
+
+#[derive(Default)]
+struct Pool {
+    entries: HashMap<TransactionCommitment, Entry>,
+    // Transactions which have not yet been released to HotShot, by priority.
+    held: BTreeMap<Priority, TransactionCommitment>,
+    nullifiers: HashMap<Nullifier, TransactionCommitment>,
+    bytes: usize,
+    in_flight: usize,
+    next_seq: u64,
+    block_height: u64,
+    evicted: Vec<TransactionCommitment>,
+}
+
+impl Pool {
+    /// The held transactions which would be evicted to admit a transaction.
+    fn eviction_candidates(
+        &self,
+        options: &MempoolOptions,
+        priority: Priority,
+        size: usize,
+    ) -> Result<Vec<TransactionCommitment>, MempoolError> {
+        let mut count = self.entries.len() + 1;
+        let mut bytes = self.bytes + size;
+        let mut evict = vec![];
+        let mut candidates = self.held.iter();
+        while count > options.max_transactions || bytes > options.max_bytes {
+            match candidates.next() {
+                Some((candidate, hash)) if *candidate < priority => {
+                    count -= 1;
+                    bytes -= self.entries[hash].size;
+                    evict.push(*hash);
+                }
+                _ => return Err(MempoolError::Full),
+            }
+        }
+        Ok(evict)
+    }
+
+    fn check(
+        &self,
+        options: &MempoolOptions,
+        hash: TransactionCommitment,
+        txn: &ElaboratedTransaction,
+    ) -> Result<Vec<TransactionCommitment>, MempoolError> {
+        if self.entries.contains_key(&hash) {
+            return Err(MempoolError::Duplicate { hash });
+        }
+        for nullifier in txn.txn.input_nullifiers() {
+            if let Some(hash) = self.nullifiers.get(&nullifier) {
+                return Err(MempoolError::ConflictingNullifier {
+                    nullifier,
+                    hash: *hash,
+                });
+            }
+        }
+        self.eviction_candidates(
+            options,
+            (fee(&txn.txn), Reverse(self.next_seq)),
+            txn.serialized_size(),
+        )
+    }
+
+    fn insert(
+        &mut self,
+        options: &MempoolOptions,
+        txn: ElaboratedTransaction,
+    ) -> Result<(), MempoolError> {
+        let hash = TransactionCommitment(txn.txn.commit());
+        for hash in self.check(options, hash, &txn)? {
+            self.remove(&hash);
+            self.evicted.push(hash);
+        }
+
+        let priority = (fee(&txn.txn), Reverse(self.next_seq));
+        self.next_seq += 1;
+        for nullifier in txn.txn.input_nullifiers() {
+            self.nullifiers.insert(nullifier, hash);
+        }
+        let size = txn.serialized_size();
+        let valid_until = txn.txn.proof_bound_valid_until();
+        self.bytes += size;
+        self.held.insert(priority, hash);
+        self.entries.insert(
+            hash,
+            Entry {
+                txn,
+                priority,
+                size,
+                valid_until,
+                released: None,
+            },
+        );
+        Ok(())
+    }
+
+    fn remove(&mut self, hash: &TransactionCommitment) {
+        let entry = match self.entries.remove(hash) {
+            Some(entry) => entry,
+            None => return,
+        };
+        if entry.released.is_some() {
+            self.in_flight -= 1;
+        } else {
+            self.held.remove(&entry.priority);
+        }
+        for nullifier in entry.txn.txn.input_nullifiers() {
+            self.nullifiers.remove(&nullifier);
+        }
+        self.bytes -= entry.size;
+    }
+
+    /// Remove transactions which have been decided, or which conflict with a decided transaction
+    /// and so can never be decided.
+    fn remove_decided(&mut self, txns: &[EspressoTransaction]) {
+        for txn in txns {
+            self.remove(&TransactionCommitment(txn.commit()));
+            for nullifier in txn.input_nullifiers() {
+                if let Some(hash) = self.nullifiers.get(&nullifier).copied() {
+                    self.remove(&hash);
+                }
+            }
+        }
+    }
+
+    /// Advance to a newly decided `block_height`.
+    ///
+    /// Transactions which cannot be included in a block at `block_height` or later are removed.
+    /// Released transactions which have not been decided within the release timeout are removed,
+    /// but not reported as evicted, since HotShot may still propose them.
+    fn advance(&mut self, options: &MempoolOptions, block_height: u64) {
+        self.block_height = self.block_height.max(block_height);
+        let expired = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| matches!(entry.valid_until, Some(h) if h < block_height))
+            .map(|(hash, _)| *hash)
+            .collect::<Vec<_>>();
+        for hash in expired {
+            self.remove(&hash);
+        }
+        let timed_out = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| {
+                matches!(entry.released, Some(h) if h + options.release_timeout <= block_height)
+            })
+            .map(|(hash, _)| *hash)
+            .collect::<Vec<_>>();
+        for hash in timed_out {
+            self.remove(&hash);
+        }
+    }
+
+    /// Take the highest priority held transactions, up to the batch size.
+    fn release(&mut self, options: &MempoolOptions) -> Vec<ElaboratedTransaction> {
+        let mut released = vec![];
+        while self.in_flight < options.batch_size {
+            let (priority, hash) = match self.held.iter().next_back() {
+                Some((priority, hash)) => (*priority, *hash),
+                None => break,
+            };
+            self.held.remove(&priority);
+            let entry = self.entries.get_mut(&hash).unwrap();
+            entry.released = Some(self.block_height);
+            self.in_flight += 1;
+            released.push(entry.txn.clone());
+        }
+        released
+    }
+}
+
+/// A mempool wrapping a consensus handle.
+///
+/// Cloning a [Mempool] yields a handle to the same pool of transactions.
+#[derive(Clone)]
+pub struct Mempool<C> {
+    consensus: C,
+    options: MempoolOptions,
+    pool: Arc<Mutex<Pool>>,
+}
+
+impl<C> Mempool<C>
+where
+    C: ValidatorDataSource + Send + Sync,
+{
+    pub fn new(consensus: C, options: MempoolOptions) -> Self {
+        Self {
+            consensus,
+            options,
+            pool: Default::default(),
+        }
+    }
+
+    /// Release the highest priority transactions to consensus, as long as there is room.
+    ///
+    /// Transactions which consensus fails to accept are removed from the mempool and reported as
+    /// evicted. Returns the transactions which failed, with their errors.
+    async fn release(&mut self) -> Vec<(TransactionCommitment, C::Error)> {
+        let txns = self.pool.lock().unwrap().release(&self.options);
+        let mut failed = vec![];
+        for txn in txns {
+            let hash = TransactionCommitment(txn.txn.commit());
+            if let Err(err) = self.consensus.submit(txn).await {
+                let mut pool = self.pool.lock().unwrap();
+                pool.remove(&hash);
+                pool.evicted.push(hash);
+                failed.push((hash, err));
+            }
+        }
+        failed
+    }
+
+    /// Remove decided and expired transactions from the mempool and release more to consensus.
+    ///
+    /// This runs forever, so it should be spawned in its own task.
+    pub async fn process_events(mut self) {
+        loop {
+            match self.consensus.next_event().await {
+                Ok(EventType::Decide { leaf_chain }) => {
+                    {
+                        let mut pool = self.pool.lock().unwrap();
+                        for leaf in leaf_chain.iter().rev() {
+                            pool.remove_decided(&leaf.deltas.block.0);
+                        }
+                        if let Some(block_height) =
+                            leaf_chain.iter().map(|leaf| leaf.state.block_height).max()
+                        {
+                            pool.advance(&self.options, block_height);
+                        }
+                    }
+                    for (hash, err) in self.release().await {
+                        tracing::warn!(
+                            "failed to release transaction {} to consensus: {}",
+                            hash,
+                            err
+                        );
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => tracing::warn!("error from consensus event stream: {}", err),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<C> ValidatorDataSource for Mempool<C>
+where
+    C: ValidatorDataSource + Send + Sync,
+{
+    type Error = SubmitError<C::Error>;
+
+    async fn submit(
+        &mut self,
+        txn: ElaboratedTransaction,
+    ) -> Result<TransactionCommitment, Self::Error> {
+        let hash = TransactionCommitment(txn.txn.commit());
+        self.pool
+            .lock()
+            .unwrap()
+            .insert(&self.options, txn)
+            .map_err(|error| SubmitError::Mempool { error })?;
+        let mut res = Ok(hash);
+        for (failed, err) in self.release().await {
+            if failed == hash {
+                res = Err(SubmitError::Consensus { source: err });
+            } else {
+                tracing::warn!(
+                    "failed to release transaction {} to consensus: {}",
+                    failed,
+                    err
+                );
+            }
+        }
+        res
+    }
+
+    fn get_transaction_status(&self, _hash: TransactionCommitment) -> Option<TransactionStatus> {
+        None
+    }
+
+    fn get_decided_state(&self) -> Option<&ValidatorState> {
+        None
+    }
+
+    fn check_admission(&self, txn: &ElaboratedTransaction) -> Result<(), MempoolError> {
+        let hash = TransactionCommitment(txn.txn.commit());
+        self.pool
+            .lock()
+            .unwrap()
+            .check(&self.options, hash, txn)
+            .map(|_| ())
+    }
+
+    fn take_evicted(&mut self) -> Vec<TransactionCommitment> {
+        mem::take(&mut self.pool.lock().unwrap().evicted)
+    }
+
+    async fn next_event(&mut self) -> Result<ConsensusEvent, Self::Error> {
+        self.consensus
+            .next_event()
+            .await
+            .map_err(|source| SubmitError::Consensus { source })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use espresso_core::app_data::{AppDataNote, NamespaceId};
+
+    const OPTIONS: MempoolOptions = MempoolOptions {
+        max_transactions: 3,
+        max_bytes: 1_000_000,
+        batch_size: 2,
+        release_timeout: 5,
+    };
+
+    fn txn(payload: u8) -> (TransactionCommitment, ElaboratedTransaction) {
+        let txn = ElaboratedTransaction::from(AppDataNote::new(NamespaceId(0), vec![payload]));
+        (TransactionCommitment(txn.txn.commit()), txn)
+    }
+
+    fn hashes(txns: &[ElaboratedTransaction]) -> Vec<TransactionCommitment> {
+        txns.iter()
+            .map(|txn| TransactionCommitment(txn.txn.commit()))
+            .collect()
+    }
+
+    #[test]
+    fn test_pool_admission() {
+        let mut pool = Pool::default();
+        let (hash, first) = txn(0);
+        pool.insert(&OPTIONS, first.clone()).unwrap();
+        assert!(matches!(
+            pool.insert(&OPTIONS, first),
+            Err(MempoolError::Duplicate { hash: h }) if h == hash
+        ));
+
+        pool.insert(&OPTIONS, txn(1).1).unwrap();
+        pool.insert(&OPTIONS, txn(2).1).unwrap();
+        // All the waiting transactions pay the same fee as a new one, so none can be evicted for it.
+        assert!(matches!(
+            pool.insert(&OPTIONS, txn(3).1),
+            Err(MempoolError::Full)
+        ));
+        assert_eq!(pool.entries.len(), 3);
+        assert!(pool.evicted.is_empty());
+        // The whole elaborated transaction counts towards the size limit, not just the note.
+        assert_eq!(
+            pool.bytes,
+            (0..3).map(|i| txn(i).1.serialized_size()).sum::<usize>()
+        );
+    }
+
+    #[test]
+    fn test_pool_release() {
+        let mut pool = Pool::default();
+        let txns = (0..3).map(txn).collect::<Vec<_>>();
+        for (_, txn) in &txns {
+            pool.insert(&OPTIONS, txn.clone()).unwrap();
+        }
+
+        // With equal fees, the oldest transactions are released first, up to the batch size.
+        let released = pool.release(&OPTIONS);
+        assert_eq!(hashes(&released), vec![txns[0].0, txns[1].0]);
+        assert!(pool.release(&OPTIONS).is_empty());
+
+        // Deciding a released transaction makes room for the next one.
+        pool.remove_decided(&[txns[0].1.txn.clone()]);
+        assert_eq!(hashes(&pool.release(&OPTIONS)), vec![txns[2].0]);
+        assert_eq!(pool.in_flight, 2);
+    }
+
+    #[test]
+    fn test_pool_release_timeout() {
+        let mut pool = Pool::default();
+        let txns = (0..3).map(txn).collect::<Vec<_>>();
+        for (_, txn) in &txns {
+            pool.insert(&OPTIONS, txn.clone()).unwrap();
+        }
+        pool.advance(&OPTIONS, 1);
+        assert_eq!(pool.release(&OPTIONS).len(), 2);
+
+        // Released transactions are kept until the timeout elapses.
+        pool.advance(&OPTIONS, 1 + OPTIONS.release_timeout - 1);
+        assert!(pool.evicted.is_empty());
+        assert!(pool.release(&OPTIONS).is_empty());
+
+        // Then they are dropped, but not reported as evicted, since HotShot may still propose them.
+        // The held transaction can now be released.
+        pool.advance(&OPTIONS, 1 + OPTIONS.release_timeout);
+        assert!(pool.evicted.is_empty());
+        assert!(!pool.entries.contains_key(&txns[0].0));
+        assert!(!pool.entries.contains_key(&txns[1].0));
+        assert_eq!(pool.in_flight, 0);
+        assert_eq!(hashes(&pool.release(&OPTIONS)), vec![txns[2].0]);
+    }
+
+    #[test]
+    fn test_pool_expiry() {
+        let mut pool = Pool::default();
+        let (hash, txn) = txn(0);
+        pool.insert(&OPTIONS, txn).unwrap();
+        // Only transfers have an expiration height, so set one directly.
+        pool.entries.get_mut(&hash).unwrap().valid_until = Some(10);
+        pool.advance(&OPTIONS, 10);
+        assert_eq!(pool.entries.len(), 1);
+        pool.advance(&OPTIONS, 11);
+        assert!(pool.entries.is_empty());
+        assert_eq!(pool.bytes, 0);
+    }
+}
@@ -7,7 +7,9 @@ use async_std::{
     task::sleep,
 };
 use async_trait::async_trait;
-use espresso_core::{state::ValidatorState, StakingKey};
+use espresso_core::{
+    metrics::Metrics, proposals::ProposalTracker, state::ValidatorState, StakingKey,
+};
 use hotshot::{
     traits::{
         election::vrf::VRFStakeTableConfig,
@@ -16,7 +18,10 @@ use hotshot::{
     },
     types::Message,
 };
-use hotshot_types::traits::network::NetworkChange;
+use hotshot_types::{
+    message::{ConsensusMessage, MessageKind},
+    traits::network::NetworkChange,
+};
 use libp2p::identity::Keypair;
 use libp2p_networking::{
     network::{MeshParams, NetworkNodeConfigBuilder, NetworkNodeType},
@@ -26,17 +31,55 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::net::ToSocketAddrs;
 use std::num::NonZeroUsize;
-use std::sync::atomic::AtomicBool;
+use std::sync::{atomic::AtomicBool, Mutex};
 use std::time::Duration;
 use url::Url;
 
 #[derive(Clone, Debug)]
-pub enum HybridNetwork {
+enum Transport {
     P2P(Libp2pNetwork<Message<ValidatorState, SignatureKey>, SignatureKey>),
     Cdn(CentralizedServerNetwork<SignatureKey, VRFStakeTableConfig>),
 }
 
+/// The network of a validator, over libp2p or a centralized server.
+///
+/// HotShot does not report proposals in its event stream, so the network records each block
+/// proposal this node sends or receives in the node's own [ProposalTracker].
+#[derive(Clone, Debug)]
+pub struct HybridNetwork {
+    transport: Transport,
+    proposals: Arc<Mutex<ProposalTracker>>,
+    metrics: Metrics,
+}
+
 impl HybridNetwork {
+    fn new(transport: Transport, metrics: Metrics) -> Self {
+        Self {
+            transport,
+            proposals: Default::default(),
+            metrics,
+        }
+    }
+
+    /// The blocks proposed to this node.
+    pub fn proposals(&self) -> Arc<Mutex<ProposalTracker>> {
+        self.proposals.clone()
+    }
+
+    /// Record `message` in the proposal tracker if it is a block proposal.
+    fn record_proposal(&self, message: &Message<ValidatorState, SignatureKey>) {
+        if let MessageKind::Consensus(ConsensusMessage::Proposal(proposal)) = &message.kind {
+            if self
+                .proposals
+                .lock()
+                .unwrap()
+                .record(*proposal.leaf.view_number, &proposal.leaf.deltas)
+            {
+                self.metrics.proposed_blocks.inc();
+            }
+        }
+    }
+
     /// Create a new libp2p network.
     pub async fn new_p2p(
         pubkey: StakingKey,
@@ -45,6 +88,7 @@ impl HybridNetwork {
         bound_addr: Multiaddr,
         identity: Option<Keypair>,
         node_opt: &NodeOpt,
+        metrics: Metrics,
     ) -> Result<Self, NetworkError> {
         let mut config_builder = NetworkNodeConfigBuilder::default();
         // NOTE we may need to change this as we scale
@@ -79,15 +123,18 @@ impl HybridNetwork {
 
         let config = config_builder.build().unwrap();
 
-        Ok(Self::P2P(
-            Libp2pNetwork::new(
-                config,
-                pubkey.into(),
-                Arc::new(RwLock::new(bs)),
-                node_opt.bootstrap_nodes.len(),
-                node_opt.id,
-            )
-            .await?,
+        Ok(Self::new(
+            Transport::P2P(
+                Libp2pNetwork::new(
+                    config,
+                    pubkey.into(),
+                    Arc::new(RwLock::new(bs)),
+                    node_opt.bootstrap_nodes.len(),
+                    node_opt.id,
+                )
+                .await?,
+            ),
+            metrics,
         ))
     }
 
@@ -96,6 +143,7 @@ impl HybridNetwork {
         known_nodes: Vec<StakingKey>,
         server: Url,
         node_id: usize,
+        metrics: Metrics,
     ) -> Result<Self, NetworkError> {
         let known_nodes = known_nodes
             .into_iter()
@@ -124,12 +172,27 @@ impl HybridNetwork {
             );
             sleep(Duration::from_secs(1)).await;
         }
-        Ok(Self::Cdn(network))
+        Ok(Self::new(Transport::Cdn(network), metrics))
     }
 }
 
+/// Call a networking method on the transport of a [HybridNetwork].
+macro_rules! on_transport {
+    ($network:expr, $name:ident($($param:expr),*)) => {
+        match &$network.transport {
+            Transport::P2P(p2p) =>
+                NetworkingImplementation::<Message<ValidatorState, SignatureKey>, SignatureKey>::
+                    $name(p2p, $($param),*).await,
+            Transport::Cdn(cdn) =>
+                NetworkingImplementation::<Message<ValidatorState, SignatureKey>, SignatureKey>::
+                    $name(cdn, $($param),*).await,
+        }
+    };
+}
+
 macro_rules! impl_networking {
     {
+        { $($intercepted:tt)* }
         $(async fn $name:ident$
             (<$($type_param:ident $(: $type_constraint:tt)?),*>)?
             (&self $(, $($param:ident : $param_type:ty),* $(,)?)?)
@@ -138,19 +201,14 @@ macro_rules! impl_networking {
     {
         #[async_trait]
         impl NetworkingImplementation<Message<ValidatorState, SignatureKey>, SignatureKey> for HybridNetwork {
+            $($intercepted)*
+
             $(
                 async fn $name
                     $(<$($type_param $(: $type_constraint)?),*>)?
                     (&self $(, $($param : $param_type),*)?)
                 $(-> $result_type)? {
-                    match self {
-                        Self::P2P(p2p) =>
-                            NetworkingImplementation::<Message<ValidatorState, SignatureKey>, SignatureKey>::
-                                $name(p2p, $($($param),*)?).await,
-                        Self::Cdn(cdn) =>
-                            NetworkingImplementation::<Message<ValidatorState, SignatureKey>, SignatureKey>::
-                                $name(cdn, $($($param),*)?).await,
-                    }
+                    on_transport!(self, $name($($($param),*)?))
                 }
             )*
         }
@@ -158,18 +216,39 @@ macro_rules! impl_networking {
 }
 
 impl_networking! {
+    {
+        async fn broadcast_message(
+            &self,
+            message: Message<ValidatorState, SignatureKey>,
+        ) -> Result<(), NetworkError> {
+            // A leader does not receive its own proposals, so record them as they are sent.
+            self.record_proposal(&message);
+            on_transport!(self, broadcast_message(message))
+        }
+
+        async fn broadcast_queue(
+            &self,
+        ) -> Result<Vec<Message<ValidatorState, SignatureKey>>, NetworkError> {
+            let messages = on_transport!(self, broadcast_queue())?;
+            for message in &messages {
+                self.record_proposal(message);
+            }
+            Ok(messages)
+        }
+
+        async fn next_broadcast(&self) -> Result<Message<ValidatorState, SignatureKey>, NetworkError> {
+            let message = on_transport!(self, next_broadcast())?;
+            self.record_proposal(&message);
+            Ok(message)
+        }
+    }
+
     async fn ready(&self) -> bool;
-    async fn broadcast_message(
-        &self,
-        message: Message<ValidatorState, SignatureKey>,
-    ) -> Result<(), NetworkError>;
     async fn message_node(
         &self,
         message: Message<ValidatorState, SignatureKey>,
         recipient: SignatureKey,
     ) -> Result<(), NetworkError>;
-    async fn broadcast_queue(&self,) -> Result<Vec<Message<ValidatorState, SignatureKey>>, NetworkError>;
-    async fn next_broadcast(&self) -> Result<Message<ValidatorState, SignatureKey>, NetworkError>;
     async fn direct_queue(&self) -> Result<Vec<Message<ValidatorState, SignatureKey>>, NetworkError>;
     async fn next_direct(&self) -> Result<Message<ValidatorState, SignatureKey>, NetworkError>;
     async fn known_nodes(&self) -> Vec<SignatureKey>;
@@ -2,14 +2,15 @@
 // This file is part of the Espresso library.
 
 use crate::{
-    gen_keys, genesis, init_validator, open_data_source, parse_duration, run_consensus, NodeOpt,
-    MINIMUM_BOOTSTRAP_NODES, MINIMUM_NODES,
+    gen_keys, genesis, init_validator, mempool::Mempool, open_data_source, parse_duration,
+    run_consensus, track_peers, NodeOpt, MINIMUM_BOOTSTRAP_NODES, MINIMUM_NODES,
 };
 use address_book::{error::AddressBookError, store::FileStore};
 use async_std::task::sleep;
 use async_std::task::{block_on, spawn, JoinHandle};
 use async_trait::async_trait;
 use espresso_core::ledger::EspressoLedger;
+use espresso_core::metrics::Metrics;
 use espresso_core::StakingKey;
 use espresso_esqs::full_node::{self, EsQS};
 use futures::Future;
@@ -221,8 +222,14 @@ pub async fn minimal_test_network(
                 ..NodeOpt::new(i, MINIMUM_NODES)
             };
             let genesis = genesis(&node_opt);
-            let consensus = init_validator(new_rng, &node_opt, priv_key, pub_keys, genesis).await;
-            let data_source = open_data_source(&node_opt, consensus.clone());
+            let metrics = Metrics::new();
+            let (consensus, network) =
+                init_validator(new_rng, &node_opt, priv_key, pub_keys, genesis, &metrics).await;
+            let mempool = Mempool::new(consensus.clone(), node_opt.mempool_options());
+            spawn(mempool.clone().process_events());
+            let data_source = open_data_source(&node_opt, mempool, &metrics);
+            let proposals = network.proposals();
+            spawn(track_peers(network, data_source.clone()));
 
             // If applicable, run a query service.
             let esqs = if i == 0 {
@@ -233,6 +240,7 @@ pub async fn minimal_test_network(
                         &full_node::Command::with_port(port),
                         data_source,
                         consensus.clone(),
+                        proposals,
                     )
                     .unwrap(),
                 )
@@ -241,7 +249,7 @@ pub async fn minimal_test_network(
             };
 
             let (kill, recv_kill) = oneshot::channel();
-            let wait = spawn(run_consensus(consensus, recv_kill));
+            let wait = spawn(run_consensus(consensus, metrics, recv_kill));
             TestNode { esqs, kill, wait }
         };
         nodes_futures.push(future);
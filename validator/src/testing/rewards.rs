@@ -33,7 +33,7 @@ mod test {
         let mut keystore1 = Keystore::new(
             NetworkBackend::new(
                 &UNIVERSAL_PARAM,
-                network.query_api.clone(),
+                vec![network.query_api.clone()],
                 network.address_book_api.clone(),
                 network.submit_api.clone(),
             )
@@ -9,11 +9,13 @@ use espresso_esqs::full_node::EsQS;
 use std::process::exit;
 
 /// Initiate the hotshot
+///
+/// Returns the consensus handle and the metrics of the new node.
 pub async fn init<R: CryptoRng + RngCore + Send + 'static>(
     rng: R,
     genesis: GenesisNote,
     node_opt: NodeOpt,
-) -> Result<Consensus, std::io::Error> {
+) -> Result<(Consensus, Metrics), std::io::Error> {
     if let Err(msg) = node_opt.check() {
         eprintln!("{}", msg);
         exit(1);
@@ -24,6 +26,16 @@ pub async fn init<R: CryptoRng + RngCore + Send + 'static>(
         .with_ansi(node_opt.colored_logs)
         .init();
 
+    let metrics = Metrics::new();
+    if let Some(port) = node_opt.metrics_port {
+        let metrics = metrics.clone();
+        spawn(async move {
+            if let Err(err) = serve_metrics(port, metrics).await {
+                tracing::error!("metrics server exited due to {}", err);
+            }
+        });
+    }
+
     // Initialize the hotshot
     let keys = gen_keys(node_opt.secret_key_seed, node_opt.num_nodes);
     let priv_key = keys[node_opt.id].clone();
@@ -31,15 +43,20 @@ pub async fn init<R: CryptoRng + RngCore + Send + 'static>(
         .into_iter()
         .map(|sk| StakingKey::from_private(&sk))
         .collect();
-    let hotshot = init_validator(rng, &node_opt, priv_key, known_nodes, genesis).await;
-    let data_source = open_data_source(&node_opt, hotshot.clone());
+    let (hotshot, network) =
+        init_validator(rng, &node_opt, priv_key, known_nodes, genesis, &metrics).await;
+    let mempool = Mempool::new(hotshot.clone(), node_opt.mempool_options());
+    spawn(mempool.clone().process_events());
+    let data_source = open_data_source(&node_opt, mempool, &metrics);
+    let proposals = network.proposals();
+    spawn(track_peers(network, data_source.clone()));
 
     // Start an EsQS server if requested.
     if let Some(esqs) = &node_opt.esqs {
-        Some(EsQS::new(esqs, data_source, hotshot.clone())?)
+        Some(EsQS::new(esqs, data_source, hotshot.clone(), proposals)?)
     } else {
         None
     };
 
-    Ok(hotshot)
+    Ok((hotshot, metrics))
 }